@@ -85,6 +85,67 @@ fn bench_diff_computation(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_batch_diff(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch_diff");
+
+    let options = DiffOptions::default();
+    let texts: Vec<(String, String, String)> = (0..64)
+        .map(|i| {
+            let old_text = generate_text_data(500, 80);
+            let new_text = generate_modified_text(&old_text, 0.15);
+            (format!("file{}.txt", i), old_text, new_text)
+        })
+        .collect();
+    let pairs: Vec<(&str, &str, &str)> = texts
+        .iter()
+        .map(|(path, old_text, new_text)| (path.as_str(), old_text.as_str(), new_text.as_str()))
+        .collect();
+
+    // The two match unless the `rayon` feature is enabled
+    group.bench_function("sequential", |b| {
+        b.iter(|| black_box(compute_batch_sequential(black_box(&pairs), black_box(&options), 0.0)))
+    });
+    group.bench_function("compute_batch", |b| {
+        b.iter(|| black_box(compute_batch(black_box(&pairs), black_box(&options), 0.0)))
+    });
+
+    group.finish();
+}
+
+fn bench_baseline_diff(c: &mut Criterion) {
+    let mut group = c.benchmark_group("baseline_diff");
+
+    // An editor re-diffing a buffer against its saved version as lines are typed
+    let options = DiffOptions::default();
+    let old_text = generate_text_data(10000, 80);
+    let edits: Vec<String> = (0..10)
+        .map(|i| {
+            let mut lines: Vec<&str> = old_text.lines().collect();
+            let typed = format!("typed line {}", i);
+            lines.insert(9000 + i, &typed);
+            lines.join("\n")
+        })
+        .collect();
+
+    group.bench_function("compute_diff", |b| {
+        b.iter(|| {
+            for new_text in &edits {
+                black_box(compute_diff(black_box(&old_text), black_box(new_text), &options).unwrap());
+            }
+        })
+    });
+    let baseline = BaselineIndex::build(&old_text);
+    group.bench_function("against_baseline", |b| {
+        b.iter(|| {
+            for new_text in &edits {
+                black_box(compute_diff_against_baseline(&baseline, black_box(new_text), &options).unwrap());
+            }
+        })
+    });
+
+    group.finish();
+}
+
 fn bench_streaming_diff(c: &mut Criterion) {
     let mut group = c.benchmark_group("streaming_diff");
     
@@ -164,6 +225,28 @@ fn bench_syntax_highlighting(c: &mut Criterion) {
             }
         })
     });
+
+    // A single minified line, with and without the highlight length limit
+    let minified_old = format!("var a=[{}];", "1,\"x\",".repeat(6000));
+    let minified_new = format!("var a=[{}];", "2,\"y\",".repeat(6000));
+
+    for (name, limit) in [("minified_line_limited", 1000), ("minified_line_unlimited", usize::MAX)] {
+        let options = DiffOptions {
+            language: Some("javascript".to_string()),
+            max_highlight_line_length: limit,
+            ..DiffOptions::default()
+        };
+
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                black_box(compute_diff(
+                    black_box(&minified_old),
+                    black_box(&minified_new),
+                    black_box(&options),
+                ))
+            })
+        });
+    }
     
     group.finish();
 }
@@ -196,6 +279,13 @@ fn bench_semantic_analysis(c: &mut Criterion) {
             }
         })
     });
+
+    // An analyzer is created for every diff, so it should only borrow the
+    // shared pattern table
+    warmup();
+    group.bench_function("analyzer_new", |b| {
+        b.iter(|| black_box(SemanticAnalyzer::new(black_box(Some("python")))))
+    });
     
     group.finish();
 }
@@ -207,13 +297,16 @@ fn bench_virtual_scrolling(c: &mut Criterion) {
     
     for total_lines in [10000, 50000, 100000].iter() {
         let mut scroll = VirtualScroll::new(*total_lines, 50);
+        // Jump around the document in a fixed pseudo-random order, so runs are comparable
+        let mut line = 0;
         
         group.bench_with_input(
             BenchmarkId::new("viewport_update", total_lines),
             total_lines,
             |b, _| {
                 b.iter(|| {
-                    let scroll_top = (rand::random::<f64>() * (*total_lines as f64 * 20.0)).max(0.0);
+                    line = (line + 7919) % total_lines;
+                    let scroll_top = line as f64 * 20.0;
                     black_box(scroll.update_viewport(black_box(scroll_top), 50))
                 })
             },
@@ -229,21 +322,44 @@ fn bench_memory_usage(c: &mut Criterion) {
     // Test memory efficiency with large texts
     for size in [10000, 25000, 50000].iter() {
         let text = generate_text_data(*size, 100);
+        let modified = generate_modified_text(&text, 0.05);
+        let options = DiffOptions::default();
         
         group.bench_with_input(
             BenchmarkId::new("large_text_processing", size),
             size,
             |b, _| {
                 b.iter(|| {
-                    let old_text = black_box(&text);
-                    let new_text = black_box(&generate_modified_text(&text, 0.05));
-                    let options = black_box(&DiffOptions::default());
-                    
-                    black_box(compute_diff(old_text, new_text, options))
+                    black_box(compute_diff(
+                        black_box(&text),
+                        black_box(&modified),
+                        black_box(&options),
+                    ))
                 })
             },
         );
     }
+
+    // One change in a million lines
+    let old_text: String = (0..1_000_000).map(|i| format!("line {}\n", i)).collect();
+    let new_text = old_text.replacen("line 500000\n", "line 500000!\n", 1);
+    let options = DiffOptions {
+        max_file_size: 64 * 1024 * 1024,
+        semantic_diff: false,
+        syntax_highlight: false,
+        ..DiffOptions::default()
+    };
+
+    group.sample_size(10);
+    group.bench_function("single_change_1m_lines", |b| {
+        b.iter(|| {
+            black_box(compute_diff(
+                black_box(&old_text),
+                black_box(&new_text),
+                black_box(&options),
+            ))
+        })
+    });
     
     group.finish();
 }
@@ -342,6 +458,8 @@ criterion_group!(
     benches,
     bench_myers_algorithm,
     bench_diff_computation,
+    bench_batch_diff,
+    bench_baseline_diff,
     bench_streaming_diff,
     bench_syntax_highlighting,
     bench_semantic_analysis,
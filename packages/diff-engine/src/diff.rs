@@ -1,10 +1,20 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use rustc_hash::FxHasher;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use std::error::Error;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
 
-use crate::myers::MyersDiff;
+use crate::myers::{EditOp, LineSimilarity, MyersDiff};
 use crate::semantic::SemanticAnalyzer;
-use crate::syntax::SyntaxHighlighter;
+use crate::syntax::{HighlighterCache, SyntaxHighlighter};
+use crate::token_diff;
+use crate::word_diff::{diff_chars, diff_words_with, WordBoundary, WordSpan};
 
 /// Type of diff algorithm to use
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -15,20 +25,167 @@ pub enum DiffAlgorithm {
     Histogram,
 }
 
+/// Which changes count against similarity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SimilarityBasis {
+    AllChanges,
+    DeletionsOnly,
+    ModificationsOnly,
+}
+
+/// Granularity of the changed spans reported within a modified line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum IntraLineMode {
+    None,
+    Word,
+    /// Single characters, for long tokens like hashes and URLs
+    Char,
+}
+
+/// How `DiffStats` counts a modified line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ModifiedCounting {
+    /// As one modified line
+    Separate,
+    /// As one added and one removed line, like git's line counts
+    AddRemove,
+}
+
+/// How `DiffChange.content` is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ContentEncoding {
+    Raw,
+    /// Escaped for direct insertion into HTML; token and marker offsets
+    /// refer to the escaped text
+    HtmlEscaped,
+}
+
 /// Options for computing diffs
+///
+/// A serialized options object may name a `preset`, which supplies the
+/// defaults that the object's other fields then override.
+///
+/// Options that normalize text (`ignore_whitespace`, `ignore_case` and the
+/// like) only change how lines compare; changes show the lines as written.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(remote = "Self", rename_all = "camelCase", default)]
 pub struct DiffOptions {
     pub algorithm: DiffAlgorithm,
     pub context_lines: usize,
+    /// Compare lines with leading and trailing whitespace trimmed
     pub ignore_whitespace: bool,
     pub ignore_case: bool,
+    /// Token types, such as `"keyword"`, compared case-insensitively while
+    /// everything else keeps its case; needs a known language
+    pub case_insensitive_tokens: Vec<String>,
     pub semantic_diff: bool,
     pub syntax_highlight: bool,
     pub language: Option<String>,
-    pub word_diff: bool,
+    /// Name of the file being diffed; without a `language`, its extension
+    /// picks one, checking `extension_languages` first
+    pub filename: Option<String>,
+    /// Report changed words or characters within modified lines; the
+    /// older `wordDiff: true` is still accepted and means `Word`
+    pub intra_line: IntraLineMode,
+    /// What `IntraLineMode::Word` counts as one word
+    pub word_boundary: WordBoundary,
+    /// With `IntraLineMode::Word` and a language, diff the words inside
+    /// string literals instead of reporting an edited string as one change
+    pub word_diff_split_strings: bool,
     pub line_numbers: bool,
     pub max_file_size: usize,
+    /// Return every line in one sequence instead of grouping into hunks
+    pub emit_all_lines: bool,
+    /// Input size in bytes above which a diff is reported as a large file
+    pub large_file_threshold: usize,
+    /// Similarity at or above which a change counts as trivial; a diff with
+    /// changes that count toward divergence never reports a similarity higher than this
+    pub significant_change_threshold: f32,
+    /// Which changes `stats.similarity` treats as divergence
+    pub similarity_basis: SimilarityBasis,
+    /// How modified lines are counted in stats; changes are reported as
+    /// `Modified` either way, and similarity is unaffected
+    pub stats_modified_as: ModifiedCounting,
+    /// Lines longer than this many bytes are left unhighlighted
+    pub max_highlight_line_length: usize,
+    /// CSS class to emit per token type, e.g. `"keyword" -> "tok-kw"`
+    pub class_name_map: Option<HashMap<String, String>>,
+    /// Extra file extension -> language mappings, checked before the built-in
+    /// table when a language is detected from a file name
+    pub extension_languages: HashMap<String, String>,
+    /// Report where tabs and spaces changed, so they can be drawn visibly
+    pub mark_whitespace: bool,
+    /// Flag modified lines that are equal once trimmed as `whitespace_only`,
+    /// and count them in `stats.whitespace_changed_lines`
+    pub flag_whitespace_only: bool,
+    /// Experimental: for JavaScript/TypeScript, compare lines with formatter
+    /// noise (spacing, quote style, trailing semicolons) normalized away
+    pub ignore_formatting: bool,
+    /// Compare numeric literals by value, so `1.0` matches `1` and `0x10`
+    /// matches `16`
+    pub ignore_number_format: bool,
+    /// Fail with `DiffError::EmptyInput` when both inputs are empty, for
+    /// pipelines where that indicates a bug upstream
+    pub error_on_empty: bool,
+    /// Encoding of change content in the result
+    pub content_encoding: ContentEncoding,
+    /// Report each change's byte range in the original inputs; needs
+    /// `line_numbers`
+    pub include_byte_offsets: bool,
+    /// Treat CRLF and LF line breaks as different; by default CRLF is
+    /// normalized to LF before diffing, and the styles are only reported in
+    /// `DiffResult::line_ending`
+    pub strict_line_endings: bool,
+    /// Budget for the serialized result; larger results lose tokens, then
+    /// per-change details, then trailing hunks. Not applied to streaming diffs.
+    pub max_payload_bytes: Option<usize>,
+    /// Start each hunk with the signature of the function or type it falls
+    /// in, however far above; the line is an unchanged change marked with
+    /// `entity_type: "enclosing_signature"` and lies outside the hunk range
+    pub include_enclosing_signature: bool,
+    /// Experimental: align by tokens rather than lines, so whitespace and line
+    /// breaks between tokens don't count as changes. Meant for languages where
+    /// whitespace is insignificant; ignored with `emit_all_lines`.
+    pub token_diff: bool,
+    /// Collapse a run of lines that only changed indentation (code wrapped in
+    /// a new block, say) into one `ReformatBlock` change; needs `line_numbers`
+    pub reformat_blocks: bool,
+    /// Re-tag blocks of removed lines that reappear among the added lines as
+    /// `ChangeType::Moved`; needs `line_numbers`. Not applied to streaming diffs.
+    pub detect_moves: bool,
+    /// Ignore blank lines where the language makes them meaningless: all of
+    /// them in brace languages, and in Python all but those before a
+    /// top-level statement. Line numbers still refer to the inputs.
+    pub smart_blank_lines: bool,
+    /// Ignore every blank or whitespace-only line, whatever the language;
+    /// takes over from `smart_blank_lines`. Line numbers still refer to the inputs.
+    pub ignore_blank_lines: bool,
+    /// Changed lines at or above which a hunk is hinted `collapsed_by_default`,
+    /// unless it declares a function, type or class; hunks that only change
+    /// whitespace are hinted at any size. `None` leaves every hint off.
+    pub auto_collapse_threshold: Option<usize>,
+    /// Diff only these lines of the old text, 1-based with an exclusive end;
+    /// line numbers and byte ranges still refer to the whole input
+    pub old_line_range: Option<(usize, usize)>,
+    /// Diff only these lines of the new text, like `old_line_range`
+    pub new_line_range: Option<(usize, usize)>,
+    /// Stop searching for the shortest alignment after this many removed
+    /// and added lines and fall back, as reported in
+    /// `DiffResult::alignment_fallback`. `None` searches to the end.
+    pub max_edit_distance: Option<usize>,
+    /// Notebook cells nested deeper than this are diffed as raw JSON instead
+    /// of being parsed; see `notebook::diff_notebooks`
+    pub notebook_max_depth: usize,
+    /// The preset these options were based on; see `DiffOptions::preset`
+    pub preset: Option<String>,
+    /// Report the line pairs the alignment matched as unchanged in
+    /// `DiffResult::debug_anchors`, to diagnose unexpected alignments. Not
+    /// applied to streaming diffs.
+    pub debug: bool,
 }
 
 impl Default for DiffOptions {
@@ -38,28 +195,142 @@ impl Default for DiffOptions {
             context_lines: 3,
             ignore_whitespace: false,
             ignore_case: false,
+            case_insensitive_tokens: Vec::new(),
             semantic_diff: true,
             syntax_highlight: true,
             language: None,
-            word_diff: false,
+            filename: None,
+            intra_line: IntraLineMode::None,
+            word_boundary: WordBoundary::Code,
+            word_diff_split_strings: false,
             line_numbers: true,
             max_file_size: 10 * 1024 * 1024, // 10MB
+            emit_all_lines: false,
+            large_file_threshold: 1024 * 1024, // 1MB
+            significant_change_threshold: 0.999,
+            similarity_basis: SimilarityBasis::AllChanges,
+            stats_modified_as: ModifiedCounting::Separate,
+            max_highlight_line_length: 1000,
+            class_name_map: None,
+            extension_languages: HashMap::new(),
+            mark_whitespace: false,
+            flag_whitespace_only: false,
+            ignore_formatting: false,
+            ignore_number_format: false,
+            error_on_empty: false,
+            content_encoding: ContentEncoding::Raw,
+            include_byte_offsets: false,
+            strict_line_endings: false,
+            max_payload_bytes: None,
+            include_enclosing_signature: false,
+            token_diff: false,
+            reformat_blocks: false,
+            detect_moves: false,
+            smart_blank_lines: false,
+            ignore_blank_lines: false,
+            auto_collapse_threshold: None,
+            old_line_range: None,
+            new_line_range: None,
+            max_edit_distance: None,
+            notebook_max_depth: 64,
+            preset: None,
+            debug: false,
+        }
+    }
+}
+
+impl DiffOptions {
+    /// Map a file extension (with or without the leading dot) to a language
+    pub fn register_extension(&mut self, extension: &str, language: &str) {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        self.extension_languages.insert(extension, language.to_string());
+    }
+
+    /// Options for a named use case: `code_review`, `prose` or `fast`
+    pub fn preset(name: &str) -> Option<Self> {
+        let base = Self {
+            preset: Some(name.to_string()),
+            ..Self::default()
+        };
+        match name {
+            "code_review" => Some(Self {
+                intra_line: IntraLineMode::Word,
+                mark_whitespace: true,
+                include_enclosing_signature: true,
+                ..base
+            }),
+            "prose" => Some(Self {
+                semantic_diff: false,
+                syntax_highlight: false,
+                intra_line: IntraLineMode::Word,
+                ..base
+            }),
+            "fast" => Some(Self {
+                semantic_diff: false,
+                syntax_highlight: false,
+                ..base
+            }),
+            _ => None,
+        }
+    }
+}
+
+// The derived impls are inherent functions (`remote = "Self"`), so
+// deserializing can resolve the preset before applying the other fields
+impl Serialize for DiffOptions {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        DiffOptions::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DiffOptions {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error as _;
+        use serde_json::Value;
+
+        let mut fields = serde_json::Map::deserialize(deserializer)?;
+        // `wordDiff` predates `intraLine`, which wins when both are given
+        if let Some(word_diff) = fields.remove("wordDiff") {
+            if !fields.contains_key("intraLine") {
+                let mode = if word_diff == Value::Bool(true) { "word" } else { "none" };
+                fields.insert("intraLine".to_string(), Value::from(mode));
+            }
         }
+
+        let base = match fields.get("preset") {
+            None | Some(Value::Null) => DiffOptions::default(),
+            Some(Value::String(name)) => DiffOptions::preset(name)
+                .ok_or_else(|| D::Error::custom(format!("unknown preset `{}`", name)))?,
+            Some(other) => return Err(D::Error::custom(format!("invalid preset {}", other))),
+        };
+
+        let mut merged = match serde_json::to_value(&base).map_err(D::Error::custom)? {
+            Value::Object(map) => map,
+            _ => unreachable!("options serialize as an object"),
+        };
+        merged.extend(fields);
+        DiffOptions::deserialize(Value::Object(merged)).map_err(D::Error::custom)
     }
 }
 
 /// Type of change in a diff
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub enum ChangeType {
     Added,
     Removed,
     Modified,
     Unchanged,
+    /// A run of lines that only changed indentation, as one change; see
+    /// `DiffOptions::reformat_blocks`
+    ReformatBlock,
+    /// A line of a block moved elsewhere, reported once where it was removed
+    /// and once where it was added; see `DiffOptions::detect_moves`
+    Moved,
 }
 
 /// A single change in the diff
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct DiffChange {
     pub change_type: ChangeType,
@@ -68,12 +339,81 @@ pub struct DiffChange {
     pub content: String,
     pub tokens: Option<Vec<SyntaxToken>>,
     pub semantic_info: Option<SemanticInfo>,
+    /// Byte offsets of tabs and spaces in the changed part of the line
+    pub whitespace_markers: Option<Vec<(usize, WsKind)>>,
+    /// Changed words or characters of a modified line, per `intra_line`;
+    /// `Added` spans are byte offsets into `content`, `Removed` spans into
+    /// the raw old line
+    pub intra_line_spans: Option<Vec<WordSpan>>,
+    /// Bytes of the line in the old input, including its line terminator
+    pub old_byte_range: Option<Range<usize>>,
+    /// Bytes of the line in the new input, including its line terminator
+    pub new_byte_range: Option<Range<usize>>,
+    /// The line had bytes that weren't valid UTF-8 and were decoded as U+FFFD
+    #[serde(default)]
+    pub has_invalid_bytes: bool,
+    /// Number of lines a `ReformatBlock` covers; 0 for other changes
+    #[serde(default)]
+    pub block_lines: usize,
+    /// A modified line that only changed leading or trailing whitespace,
+    /// when `flag_whitespace_only` is set
+    #[serde(default)]
+    pub whitespace_only: bool,
+    /// The line lies in a merge conflict region, from a `<<<<<<<` marker
+    /// through its `>>>>>>>`; needs `line_numbers`
+    #[serde(default)]
+    pub conflict_region: bool,
+    /// For a `Moved` line where it was removed, its line number in the new text
+    #[serde(default)]
+    pub moved_to: Option<usize>,
+    /// For a `Moved` line where it was added, its line number in the old text
+    #[serde(default)]
+    pub moved_from: Option<usize>,
+}
+
+impl DiffChange {
+    /// A change with the given line numbers and content and nothing else
+    /// filled in: no tokens, markers, spans or flags
+    pub fn new(
+        change_type: ChangeType,
+        old_line_number: Option<usize>,
+        new_line_number: Option<usize>,
+        content: String,
+    ) -> Self {
+        Self {
+            change_type,
+            old_line_number,
+            new_line_number,
+            content,
+            tokens: None,
+            semantic_info: None,
+            whitespace_markers: None,
+            intra_line_spans: None,
+            old_byte_range: None,
+            new_byte_range: None,
+            has_invalid_bytes: false,
+            block_lines: 0,
+            whitespace_only: false,
+            conflict_region: false,
+            moved_to: None,
+            moved_from: None,
+        }
+    }
+}
+
+/// Kind of whitespace character, for rendering visible markers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum WsKind {
+    Tab,
+    Space,
 }
 
 /// Syntax highlighting token
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SyntaxToken {
+    /// Byte offset into the change's content; `end` is exclusive
     pub start: usize,
     pub end: usize,
     pub token_type: String,
@@ -81,7 +421,7 @@ pub struct SyntaxToken {
 }
 
 /// Semantic information about a change
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SemanticInfo {
     pub entity_type: String,
@@ -91,7 +431,7 @@ pub struct SemanticInfo {
 }
 
 /// A hunk in the diff
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct DiffHunk {
     pub old_start: usize,
@@ -100,273 +440,2931 @@ pub struct DiffHunk {
     pub new_lines: usize,
     pub changes: Vec<DiffChange>,
     pub header: String,
+    /// Suggestion to show the hunk collapsed; see
+    /// `DiffOptions::auto_collapse_threshold`
+    #[serde(default)]
+    pub collapsed_by_default: bool,
 }
 
-/// Result of a diff computation
+/// Kind of a logical change block within a hunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BlockKind {
+    Added,
+    Removed,
+    Replaced,
+}
+
+/// A run of related changes the UI can collapse or comment on as a unit
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct ChangeBlock {
+    pub kind: BlockKind,
+    /// Index of the first change in the hunk
+    pub start: usize,
+    /// Index one past the last change in the hunk
+    pub end: usize,
+    pub old_lines: usize,
+    pub new_lines: usize,
+}
+
+impl DiffHunk {
+    /// Unchanged lines before the hunk's first change
+    pub fn leading_context(&self) -> &[DiffChange] {
+        let end = self
+            .changes
+            .iter()
+            .position(|change| change.change_type != ChangeType::Unchanged)
+            .unwrap_or(self.changes.len());
+        &self.changes[..end]
+    }
+
+    /// Unchanged lines after the hunk's last change
+    ///
+    /// Empty for a hunk without changes, whose lines are all leading context.
+    pub fn trailing_context(&self) -> &[DiffChange] {
+        let start = self
+            .changes
+            .iter()
+            .rposition(|change| change.change_type != ChangeType::Unchanged)
+            .map_or(self.changes.len(), |i| i + 1);
+        &self.changes[start..]
+    }
+
+    /// Group the hunk's changes into logical blocks
+    ///
+    /// A run of removals, a run of additions, or removals followed by
+    /// additions (a replacement) each form one block; unchanged lines
+    /// separate blocks.
+    pub fn blocks(&self) -> Vec<ChangeBlock> {
+        let mut blocks: Vec<ChangeBlock> = Vec::new();
+        let mut current: Option<ChangeBlock> = None;
+
+        for (i, change) in self.changes.iter().enumerate() {
+            let kind = match change.change_type {
+                ChangeType::Unchanged => {
+                    blocks.extend(current.take());
+                    continue;
+                }
+                ChangeType::Added => BlockKind::Added,
+                ChangeType::Removed => BlockKind::Removed,
+                ChangeType::Modified | ChangeType::ReformatBlock => BlockKind::Replaced,
+                ChangeType::Moved if change.moved_to.is_some() => BlockKind::Removed,
+                ChangeType::Moved => BlockKind::Added,
+            };
+
+            let merged = match current.as_ref().map(|block| block.kind) {
+                Some(BlockKind::Removed) => true,
+                Some(BlockKind::Added) => kind == BlockKind::Added,
+                Some(BlockKind::Replaced) => kind != BlockKind::Removed,
+                None => false,
+            };
+
+            if !merged {
+                blocks.extend(current.take());
+            }
+
+            let block = current.get_or_insert(ChangeBlock {
+                kind,
+                start: i,
+                end: i,
+                old_lines: 0,
+                new_lines: 0,
+            });
+
+            if block.kind != kind {
+                block.kind = BlockKind::Replaced;
+            }
+            block.end = i + 1;
+            if change.change_type != ChangeType::Added {
+                block.old_lines += 1;
+            }
+            if change.change_type != ChangeType::Removed {
+                block.new_lines += 1;
+            }
+        }
+
+        blocks.extend(current);
+        blocks
+    }
+}
+
+/// Result of a diff computation
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct DiffResult {
     pub hunks: Vec<DiffHunk>,
     pub stats: DiffStats,
     pub file_language: Option<String>,
     pub is_binary: bool,
     pub is_large_file: bool,
+    /// What was left out to keep the result within `max_payload_bytes`
+    #[serde(default)]
+    pub payload_reduction: Option<PayloadReduction>,
+    /// Highlighting was requested, but the language has no rules
+    #[serde(default)]
+    pub highlighting_skipped: bool,
+    /// Line break style of the inputs, for warning when they differ. Not
+    /// detected for streaming diffs, which report `Lf`.
+    #[serde(default)]
+    pub line_ending: LineEnding,
+    /// Either input had a line break, so `line_ending` was detected rather
+    /// than defaulted
+    #[serde(default)]
+    pub line_ending_detected: bool,
+    /// One input starts with a byte order mark and the other doesn't. The
+    /// mark itself is never diffed.
+    #[serde(default)]
+    pub bom_changed: bool,
+    /// One input ends with a line break and the other doesn't, which the
+    /// diff doesn't show either. Neither flag is set for streaming diffs.
+    #[serde(default)]
+    pub final_newline_changed: bool,
+    /// Either input has unresolved merge conflict markers
+    #[serde(default)]
+    pub has_conflict_markers: bool,
+    /// With `debug`, every (old, new) pair of 1-based line numbers the
+    /// alignment matched as unchanged, in order
+    #[serde(default)]
+    pub debug_anchors: Option<Vec<(usize, usize)>>,
+    /// The alignment search gave up, under `max_edit_distance`, and the
+    /// lines were aligned this way instead. Not reported for streaming diffs.
+    #[serde(default)]
+    pub alignment_fallback: Option<AlignmentFallback>,
 }
 
-/// Statistics about the diff
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Detail dropped from a result that was over its payload budget, in the
+/// order it is dropped
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
-pub struct DiffStats {
-    pub total_lines: usize,
-    pub added_lines: usize,
-    pub removed_lines: usize,
-    pub modified_lines: usize,
-    pub unchanged_lines: usize,
-    pub similarity: f32,
+pub struct PayloadReduction {
+    /// Syntax tokens were removed from every change
+    pub tokens_dropped: bool,
+    /// Semantic info, whitespace markers and intra-line spans were removed from every change
+    pub details_dropped: bool,
+    /// Number of hunks cut from the end of the diff
+    pub hunks_dropped: usize,
 }
 
-/// Custom error type for diff operations
-#[derive(Debug)]
-pub enum DiffError {
-    FileTooLarge,
-    InvalidEncoding,
-    AlgorithmError(String),
-    SyntaxError(String),
+/// How lines were aligned after the shortest-script search gave up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum AlignmentFallback {
+    /// By a longest common subsequence table, which still finds a shortest script
+    Lcs,
+    /// Too large for that table: only the lines shared at the start and end
+    /// are matched, and everything between is removed and re-added
+    Unaligned,
 }
 
-impl fmt::Display for DiffError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            DiffError::FileTooLarge => write!(f, "File is too large to process"),
-            DiffError::InvalidEncoding => write!(f, "Invalid text encoding"),
-            DiffError::AlgorithmError(msg) => write!(f, "Diff algorithm error: {}", msg),
-            DiffError::SyntaxError(msg) => write!(f, "Syntax highlighting error: {}", msg),
+/// Line break style of one input, or of both together
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+    /// Some lines end in CRLF and others in LF
+    Mixed,
+}
+
+impl LineEnding {
+    /// The style of every line break in `text`, or `None` if it has none;
+    /// a lone `\r` isn't a line break
+    pub fn detect(text: &str) -> Option<LineEnding> {
+        let mut found = None;
+        for line in text.split_inclusive('\n').filter(|line| line.ends_with('\n')) {
+            let ending = if line.ends_with("\r\n") { LineEnding::Crlf } else { LineEnding::Lf };
+            match found {
+                Some(seen) if seen != ending => return Some(LineEnding::Mixed),
+                _ => found = Some(ending),
+            }
+        }
+        found
+    }
+
+    /// The style of two inputs together: `Mixed` unless they agree, with
+    /// an input without line breaks agreeing with anything; `None` if
+    /// neither has any
+    fn of_inputs(old_text: &str, new_text: &str) -> Option<LineEnding> {
+        match (LineEnding::detect(old_text), LineEnding::detect(new_text)) {
+            (Some(old), Some(new)) if old != new => Some(LineEnding::Mixed),
+            (old, new) => old.or(new),
         }
     }
 }
 
-impl Error for DiffError {}
+/// Line correspondence between the two files, for syncing side-by-side scrolling
+///
+/// Both vectors are indexed by 0-based line index and hold 0-based indices
+/// into the other file. Unchanged and modified lines map both ways; removed
+/// lines have no entry in `old_to_new`, added lines none in `new_to_old`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LineMap {
+    pub old_to_new: Vec<Option<usize>>,
+    pub new_to_old: Vec<Option<usize>>,
+}
 
-/// Compute diff between two texts
-pub fn compute_diff(
-    old_text: &str,
-    new_text: &str,
-    options: &DiffOptions,
-) -> Result<DiffResult, DiffError> {
-    // Check file size limits
-    if old_text.len() > options.max_file_size || new_text.len() > options.max_file_size {
-        return Err(DiffError::FileTooLarge);
+impl LineMap {
+    fn pair(&mut self, old_idx: usize, new_idx: usize) {
+        if old_idx < self.old_to_new.len() && new_idx < self.new_to_old.len() {
+            self.old_to_new[old_idx] = Some(new_idx);
+            self.new_to_old[new_idx] = Some(old_idx);
+        }
     }
+}
 
-    // Preprocess text based on options
-    let (processed_old, processed_new) = preprocess_text(old_text, new_text, options);
+/// How the hunks of one result differ from another's for the same file pair
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResultDelta {
+    /// Indices of hunks only in the newer result
+    pub added_hunks: Vec<usize>,
+    /// Indices of hunks only in the older result
+    pub removed_hunks: Vec<usize>,
+    /// Older and newer index of hunks present in both but with different changes
+    pub changed_hunks: Vec<(usize, usize)>,
+}
 
-    // Split into lines
-    let old_lines: Vec<&str> = processed_old.lines().collect();
-    let new_lines: Vec<&str> = processed_new.lines().collect();
+/// A run of lines repeated verbatim elsewhere in the same file
+///
+/// Line numbers are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateBlock {
+    /// Where the lines first appear
+    pub first_line: usize,
+    /// Where they appear again
+    pub duplicate_line: usize,
+    pub line_count: usize,
+}
 
-    // Compute raw diff using selected algorithm
-    let raw_changes = match options.algorithm {
-        DiffAlgorithm::Myers => {
-            let myers = MyersDiff::new(&old_lines, &new_lines);
-            myers.compute_diff()
-        }
-        DiffAlgorithm::Patience => {
-            // For now, fallback to Myers
-            let myers = MyersDiff::new(&old_lines, &new_lines);
-            myers.compute_diff()
-        }
-        DiffAlgorithm::Histogram => {
-            // For now, fallback to Myers
-            let myers = MyersDiff::new(&old_lines, &new_lines);
-            myers.compute_diff()
-        }
-    };
+impl ResultDelta {
+    pub fn is_empty(&self) -> bool {
+        self.added_hunks.is_empty() && self.removed_hunks.is_empty() && self.changed_hunks.is_empty()
+    }
+}
 
-    // Apply semantic analysis if enabled
-    let changes = if options.semantic_diff {
-        let analyzer = SemanticAnalyzer::new(options.language.as_deref());
-        analyzer.analyze_changes(raw_changes, &old_lines, &new_lines)
-    } else {
-        raw_changes
-    };
+impl DiffResult {
+    /// Whether the inputs differ in more than a byte order mark, a final
+    /// line break or line break style, for a "no real changes" badge
+    ///
+    /// Under `strict_line_endings` line break style does count.
+    pub fn is_substantive_change(&self) -> bool {
+        self.stats.has_changes
+    }
 
-    // Group changes into hunks
-    let hunks = create_hunks(changes, &old_lines, &new_lines, options)?;
+    /// Compare this result with `other`, a later result for the same file pair
+    ///
+    /// Hunks are matched by their changes' types and content, so a hunk that
+    /// only moved because of an edit above it counts as unchanged. Hunks are
+    /// aligned like lines, and a removed hunk followed by a similar added one
+    /// is reported as changed.
+    pub fn delta(&self, other: &DiffResult) -> ResultDelta {
+        let fingerprint = |hunk: &DiffHunk| {
+            hunk.changes
+                .iter()
+                .map(|change| format!("{:?} {}", change.change_type, change.content))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let old_keys: Vec<String> = self.hunks.iter().map(fingerprint).collect();
+        let new_keys: Vec<String> = other.hunks.iter().map(fingerprint).collect();
+        let old_refs: Vec<&str> = old_keys.iter().map(String::as_str).collect();
+        let new_refs: Vec<&str> = new_keys.iter().map(String::as_str).collect();
 
-    // Apply syntax highlighting if enabled
-    let highlighted_hunks = if options.syntax_highlight {
-        apply_syntax_highlighting(hunks, options.language.as_deref())?
-    } else {
-        hunks
-    };
+        let mut delta = ResultDelta::default();
+        MyersDiff::new(&old_refs, &new_refs).for_each_change(|change_type, old_idx, new_idx| {
+            match change_type {
+                ChangeType::Added => delta.added_hunks.push(new_idx),
+                ChangeType::Removed => delta.removed_hunks.push(old_idx),
+                ChangeType::Modified | ChangeType::ReformatBlock => {
+                    delta.changed_hunks.push((old_idx, new_idx))
+                }
+                ChangeType::Unchanged | ChangeType::Moved => {}
+            }
+        });
+        delta
+    }
 
-    // Calculate statistics
-    let stats = calculate_stats(&highlighted_hunks, old_lines.len(), new_lines.len());
+    /// Encode the result as MessagePack, with the same field names as the JSON
+    /// form, so any MessagePack decoder yields the same object far faster
+    /// than parsing JSON
+    pub fn to_msgpack(&self) -> Result<Vec<u8>, DiffError> {
+        rmp_serde::to_vec_named(self).map_err(|e| DiffError::InvalidFormat(e.to_string()))
+    }
 
-    Ok(DiffResult {
-        hunks: highlighted_hunks,
-        stats,
-        file_language: detect_language(old_text, new_text, options.language.as_deref()),
-        is_binary: is_binary(old_text) || is_binary(new_text),
-        is_large_file: old_text.len() > 1024 * 1024 || new_text.len() > 1024 * 1024,
-    })
-}
+    /// Decode a result produced by `to_msgpack`
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Self, DiffError> {
+        rmp_serde::from_slice(bytes).map_err(|e| DiffError::InvalidFormat(e.to_string()))
+    }
 
-/// Preprocess text based on diff options
-fn preprocess_text(old_text: &str, new_text: &str, options: &DiffOptions) -> (String, String) {
-    let mut old = old_text.to_string();
-    let mut new = new_text.to_string();
+    /// Shrink the result until its JSON form fits in `max_bytes`
+    ///
+    /// Tokens go first, then per-change details, then whole hunks from the
+    /// end. Stats always describe the full diff.
+    fn fit_payload(&mut self, max_bytes: usize) {
+        if serialized_size(self) <= max_bytes {
+            return;
+        }
 
-    if options.ignore_whitespace {
-        old = normalize_whitespace(&old);
-        new = normalize_whitespace(&new);
+        let mut reduction = PayloadReduction {
+            tokens_dropped: true,
+            ..PayloadReduction::default()
+        };
+        for change in self.hunks.iter_mut().flat_map(|hunk| &mut hunk.changes) {
+            change.tokens = None;
+        }
+        self.payload_reduction = Some(reduction.clone());
+        if serialized_size(self) <= max_bytes {
+            return;
+        }
+
+        reduction.details_dropped = true;
+        for change in self.hunks.iter_mut().flat_map(|hunk| &mut hunk.changes) {
+            change.semantic_info = None;
+            change.whitespace_markers = None;
+            change.intra_line_spans = None;
+        }
+        self.payload_reduction = Some(reduction.clone());
+
+        let hunk_sizes: Vec<usize> = self.hunks.iter().map(serialized_size).collect();
+        let mut size = serialized_size(self);
+        while size > max_bytes && !self.hunks.is_empty() {
+            while size > max_bytes && !self.hunks.is_empty() {
+                self.hunks.pop();
+                // Every hunk but the first is preceded by a comma
+                size -= hunk_sizes[self.hunks.len()] + usize::from(!self.hunks.is_empty());
+                reduction.hunks_dropped += 1;
+            }
+            // The count itself takes room, so measure again with it in place
+            self.payload_reduction = Some(reduction.clone());
+            size = serialized_size(self);
+        }
     }
 
-    if options.ignore_case {
-        old = old.to_lowercase();
-        new = new.to_lowercase();
+    /// Shift every line number by the given offsets, for showing a diff of
+    /// snippets inside larger files; snippets starting at line 501 take an
+    /// offset of 500
+    ///
+    /// Hunk starts and headers, change line numbers and debug anchors move.
+    /// Byte ranges still refer to the snippets.
+    pub fn offset_line_numbers(mut self, old_offset: usize, new_offset: usize) -> DiffResult {
+        offset_line_numbers(&mut self.hunks, old_offset, new_offset);
+        for (old_line, new_line) in self.debug_anchors.iter_mut().flatten() {
+            *old_line += old_offset;
+            *new_line += new_offset;
+        }
+        self
     }
 
-    (old, new)
-}
+    /// Build the old/new line correspondence from the hunks
+    ///
+    /// Lines outside the hunks are unchanged, so they are paired up in order.
+    /// The hunks don't record how long the inputs are, so `old_line_count`
+    /// and `new_line_count` give the length of each side.
+    pub fn line_map(&self, old_line_count: usize, new_line_count: usize) -> LineMap {
+        let mut map = LineMap {
+            old_to_new: vec![None; old_line_count],
+            new_to_old: vec![None; new_line_count],
+        };
 
-/// Normalize whitespace in text
-fn normalize_whitespace(text: &str) -> String {
-    text.lines()
-        .map(|line| line.trim())
-        .filter(|line| !line.is_empty())
-        .collect::<Vec<_>>()
-        .join("\n")
-}
+        // Next unaccounted line on each side
+        let mut old_pos = 0;
+        let mut new_pos = 0;
 
-/// Create hunks from raw changes
-fn create_hunks(
-    changes: Vec<(ChangeType, usize, usize)>,
-    old_lines: &[&str],
-    new_lines: &[&str],
-    options: &DiffOptions,
-) -> Result<Vec<DiffHunk>, DiffError> {
-    let mut hunks = Vec::new();
-    let mut current_hunk: Option<DiffHunk> = None;
-    let mut last_change_idx = 0;
+        for hunk in &self.hunks {
+            // An empty side of a hunk points at the line before it
+            let old_first = if hunk.old_lines == 0 { hunk.old_start } else { hunk.old_start.saturating_sub(1) };
+            let new_first = if hunk.new_lines == 0 { hunk.new_start } else { hunk.new_start.saturating_sub(1) };
+
+            // Pair up the unchanged gap before this hunk
+            while old_pos < old_first && new_pos < new_first {
+                map.pair(old_pos, new_pos);
+                old_pos += 1;
+                new_pos += 1;
+            }
+
+            for change in &hunk.changes {
+                match change.change_type {
+                    ChangeType::Unchanged | ChangeType::Modified => {
+                        map.pair(old_pos, new_pos);
+                        old_pos += 1;
+                        new_pos += 1;
+                    }
+                    ChangeType::Removed => old_pos += 1,
+                    ChangeType::Added => new_pos += 1,
+                    // Reported on both sides, each unpaired like a removal or addition
+                    ChangeType::Moved if change.moved_to.is_some() => old_pos += 1,
+                    ChangeType::Moved => new_pos += 1,
+                    ChangeType::ReformatBlock => {
+                        for _ in 0..change.block_lines {
+                            map.pair(old_pos, new_pos);
+                            old_pos += 1;
+                            new_pos += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        while old_pos < old_line_count && new_pos < new_line_count {
+            map.pair(old_pos, new_pos);
+            old_pos += 1;
+            new_pos += 1;
+        }
 
-    for (i, &(change_type, old_idx, new_idx)) in changes.iter().enumerate() {
-        let should_start_new_hunk = current_hunk.is_none()
-            || (change_type == ChangeType::Unchanged
-                && i - last_change_idx > options.context_lines * 2);
+        map
+    }
+
+    /// Extend this result with lines appended to the new file, without re-diffing
+    ///
+    /// `base_line` is the number of lines the new file had when this result was
+    /// computed, and `new_tail` starts on the line after it. Every tail line is
+    /// reported as added, which is exactly what a full diff would produce as long
+    /// as the end of the old file was matched. Returns `Ok(false)` and leaves the
+    /// result untouched when that can't be guaranteed (trailing removals the tail
+    /// could pair with, whitespace normalization, missing line numbers, semantic
+    /// header context, byte offsets, token alignment) or when the tail needs
+    /// context lines from before it that the result doesn't show; recompute the
+    /// full diff in that case.
+    pub fn extend_with_appended(
+        &mut self,
+        new_tail: &str,
+        base_line: usize,
+        options: &DiffOptions,
+    ) -> Result<bool, DiffError> {
+        let unsupported = options.ignore_whitespace
+            || options.emit_all_lines
+            || options.include_byte_offsets
+            || options.token_diff
+            || options.smart_blank_lines
+            || options.ignore_blank_lines
+            || options.detect_moves
+            || !options.case_insensitive_tokens.is_empty()
+            || options.old_line_range.is_some()
+            || options.new_line_range.is_some()
+            || options.stats_modified_as != ModifiedCounting::Separate
+            || !options.line_numbers
+            || (options.semantic_diff && options.language.is_some());
+        if unsupported || base_line < self.stats.added_lines {
+            return Ok(false);
+        }
 
-        if should_start_new_hunk && current_hunk.is_some() {
-            hunks.push(current_hunk.take().unwrap());
+        let old_count = base_line - self.stats.added_lines + self.stats.removed_lines;
+        if self.stats.total_lines != old_count.max(base_line) {
+            return Ok(false);
         }
 
-        if change_type != ChangeType::Unchanged {
-            last_change_idx = i;
+        // Unchanged lines between the last change and the end of the prefix
+        let last_change = self
+            .hunks
+            .last()
+            .and_then(|hunk| hunk.changes.iter().rfind(|change| change.change_type != ChangeType::Unchanged));
+        let unchanged_after = match last_change {
+            Some(change) => match (change.change_type, change.new_line_number, change.old_line_number) {
+                (ChangeType::Added, Some(new_num), _) => base_line - new_num,
+                (_, _, Some(old_num)) if old_num == old_count => return Ok(false),
+                (_, Some(new_num), _) => base_line - new_num,
+                (_, None, Some(old_num)) => old_count - old_num,
+                (_, None, None) => return Ok(false),
+            },
+            None => base_line,
+        };
+        // The last hunk's trailing context covers the lines up to the tail only
+        // if there are no more of them than the context size
+        let joins_last_hunk = last_change.is_some() && unchanged_after <= options.context_lines;
+        if !joins_last_hunk && options.context_lines > 0 && base_line > 0 {
+            return Ok(false);
+        }
 
-            if current_hunk.is_none() {
-                current_hunk = Some(create_new_hunk(old_idx, new_idx, options.context_lines));
+        // Without line breaks so far, the tail's own style is the first one seen
+        if let Some(ending) = LineEnding::detect(new_tail) {
+            if !self.line_ending_detected {
+                self.line_ending = ending;
+                self.line_ending_detected = true;
+            } else if ending != self.line_ending {
+                self.line_ending = LineEnding::Mixed;
             }
+        }
 
-            let hunk = current_hunk.as_mut().unwrap();
-            let content = match change_type {
-                ChangeType::Removed => old_lines.get(old_idx).copied().unwrap_or(""),
-                ChangeType::Added => new_lines.get(new_idx).copied().unwrap_or(""),
-                _ => "",
-            };
+        let mut tail = if options.ignore_case {
+            new_tail.to_lowercase()
+        } else {
+            new_tail.to_string()
+        };
+        if options.ignore_number_format {
+            tail = normalize_numbers(&tail);
+        }
+        let tail_lines = split_lines(&tail, options);
+        if tail_lines.is_empty() {
+            return Ok(true);
+        }
 
-            hunk.changes.push(DiffChange {
-                change_type,
-                old_line_number: if change_type != ChangeType::Added {
-                    Some(old_idx + 1)
-                } else {
-                    None
-                },
-                new_line_number: if change_type != ChangeType::Removed {
-                    Some(new_idx + 1)
-                } else {
-                    None
-                },
-                content: content.to_string(),
-                tokens: None,
-                semantic_info: None,
+        let mut tail_hunk = create_new_hunk(old_count, base_line);
+        let tail_changes = tail_lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| DiffChange {
+                whitespace_markers: markers_for(ChangeType::Added, "", line, options),
+                ..DiffChange::new(ChangeType::Added, None, Some(base_line + i + 1), line.to_string())
             });
+        for change in tail_changes {
+            push_to_hunk(&mut tail_hunk, change);
         }
-    }
+        finish_hunk_range(&mut tail_hunk);
 
-    if let Some(hunk) = current_hunk {
-        hunks.push(hunk);
-    }
+        if options.syntax_highlight {
+            let tail = std::slice::from_mut(&mut tail_hunk);
+            apply_syntax_highlighting(tail, "", "", options, &mut HighlighterCache::new())?;
+        }
+        encode_content(std::slice::from_mut(&mut tail_hunk), options.content_encoding);
 
-    Ok(hunks)
-}
+        match self.hunks.last_mut() {
+            Some(hunk) if joins_last_hunk => {
+                hunk.new_lines += tail_hunk.new_lines;
+                hunk.changes.append(&mut tail_hunk.changes);
+                rewrite_header_range(hunk);
+            }
+            _ => self.hunks.push(tail_hunk),
+        }
 
-/// Create a new hunk
-fn create_new_hunk(old_start: usize, new_start: usize, context_lines: usize) -> DiffHunk {
-    let start = old_start.saturating_sub(context_lines);
-    DiffHunk {
-        old_start: start + 1,
-        old_lines: 0,
-        new_start: new_start.saturating_sub(context_lines) + 1,
-        new_lines: 0,
-        changes: Vec::new(),
-        header: format!("@@ -{},{} +{},{} @@", start + 1, 0, new_start + 1, 0),
+        // Appended lines only end the trailing run, which was already counted
+        let (longest_unchanged_run, whitespace_changed_lines) =
+            (self.stats.longest_unchanged_run, self.stats.whitespace_changed_lines);
+        self.stats = DiffStats::from_counts(
+            old_count.max(base_line + tail_lines.len()),
+            self.stats.added_lines + tail_lines.len(),
+            self.stats.removed_lines,
+            self.stats.modified_lines,
+            options,
+        );
+        self.stats.longest_unchanged_run = longest_unchanged_run;
+        self.stats.whitespace_changed_lines = whitespace_changed_lines;
+        self.is_binary = self.is_binary || is_binary(new_tail);
+        self.is_large_file = self.is_large_file || new_tail.len() > options.large_file_threshold;
+
+        Ok(true)
     }
 }
 
-/// Apply syntax highlighting to hunks
-fn apply_syntax_highlighting(
-    mut hunks: Vec<DiffHunk>,
-    language: Option<&str>,
-) -> Result<Vec<DiffHunk>, DiffError> {
-    if let Some(lang) = language {
-        let highlighter = SyntaxHighlighter::new(lang)
-            .map_err(|e| DiffError::SyntaxError(e.to_string()))?;
+/// Statistics about the diff
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffStats {
+    pub total_lines: usize,
+    pub added_lines: usize,
+    pub removed_lines: usize,
+    pub modified_lines: usize,
+    pub unchanged_lines: usize,
+    /// Modified lines flagged `whitespace_only`, also counted in `modified_lines`
+    #[serde(default)]
+    pub whitespace_changed_lines: usize,
+    /// Length of the longest run of consecutive unchanged lines
+    #[serde(default)]
+    pub longest_unchanged_run: usize,
+    pub similarity: f32,
+    /// Whether any line differs
+    pub has_changes: bool,
+    /// Whether the inputs differ, but by less than the significance threshold
+    pub is_trivial_change: bool,
+}
 
-        for hunk in &mut hunks {
-            for change in &mut hunk.changes {
-                if !change.content.is_empty() {
-                    change.tokens = Some(highlighter.highlight(&change.content));
-                }
+impl DiffStats {
+    /// Build stats from change counts, keeping a real change from rounding
+    /// up to a perfect similarity
+    pub(crate) fn from_counts(
+        total_lines: usize,
+        added_lines: usize,
+        removed_lines: usize,
+        modified_lines: usize,
+        options: &DiffOptions,
+    ) -> Self {
+        let total_changes = added_lines + removed_lines + modified_lines;
+        let has_changes = total_changes > 0;
+
+        let divergent_lines = match options.similarity_basis {
+            SimilarityBasis::AllChanges => total_changes,
+            SimilarityBasis::DeletionsOnly => removed_lines,
+            SimilarityBasis::ModificationsOnly => modified_lines,
+        };
+        let mut similarity = if total_lines > 0 {
+            1.0 - (divergent_lines as f32 / total_lines as f32)
+        } else {
+            1.0
+        };
+        similarity = similarity.clamp(0.0, 1.0);
+
+        let threshold = options.significant_change_threshold;
+        let is_trivial_change = has_changes && similarity >= threshold;
+        if divergent_lines > 0 {
+            similarity = similarity.min(threshold);
+        }
+
+        let unchanged_lines = total_lines.saturating_sub(total_changes);
+        let (added_lines, removed_lines, modified_lines) = match options.stats_modified_as {
+            ModifiedCounting::Separate => (added_lines, removed_lines, modified_lines),
+            ModifiedCounting::AddRemove => {
+                (added_lines + modified_lines, removed_lines + modified_lines, 0)
             }
+        };
+
+        Self {
+            total_lines,
+            added_lines,
+            removed_lines,
+            modified_lines,
+            unchanged_lines,
+            whitespace_changed_lines: 0,
+            // Only alignment sees where the unchanged lines fall
+            longest_unchanged_run: if has_changes { 0 } else { unchanged_lines },
+            similarity,
+            has_changes,
+            is_trivial_change,
         }
     }
-
-    Ok(hunks)
 }
 
-/// Calculate diff statistics
-fn calculate_stats(hunks: &[DiffHunk], old_total: usize, new_total: usize) -> DiffStats {
-    let mut added_lines = 0;
-    let mut removed_lines = 0;
-    let mut modified_lines = 0;
+/// Length of the JSON encoding of `value`, without building the string
+fn serialized_size<T: Serialize>(value: &T) -> usize {
+    struct ByteCounter(usize);
 
-    for hunk in hunks {
-        for change in &hunk.changes {
-            match change.change_type {
-                ChangeType::Added => added_lines += 1,
-                ChangeType::Removed => removed_lines += 1,
-                ChangeType::Modified => modified_lines += 1,
-                ChangeType::Unchanged => {}
-            }
+    impl std::io::Write for ByteCounter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
         }
     }
 
-    let total_changes = added_lines + removed_lines + modified_lines;
-    let total_lines = old_total.max(new_total);
-    let similarity = if total_lines > 0 {
-        1.0 - (total_changes as f32 / total_lines as f32)
-    } else {
-        1.0
-    };
+    let mut counter = ByteCounter(0);
+    let _ = serde_json::to_writer(&mut counter, value);
+    counter.0
+}
 
-    DiffStats {
-        total_lines,
-        added_lines,
-        removed_lines,
-        modified_lines,
-        unchanged_lines: total_lines.saturating_sub(total_changes),
-        similarity: similarity.max(0.0).min(1.0),
+/// Custom error type for diff operations
+#[derive(Debug)]
+pub enum DiffError {
+    FileTooLarge,
+    InvalidEncoding,
+    AlgorithmError(String),
+    SyntaxError(String),
+    InvalidFormat(String),
+    EmptyInput,
+    /// A patch hunk doesn't match the text at this 1-based line
+    PatchMismatch(usize),
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiffError::FileTooLarge => write!(f, "File is too large to process"),
+            DiffError::InvalidEncoding => write!(f, "Invalid text encoding"),
+            DiffError::AlgorithmError(msg) => write!(f, "Diff algorithm error: {}", msg),
+            DiffError::SyntaxError(msg) => write!(f, "Syntax highlighting error: {}", msg),
+            DiffError::InvalidFormat(msg) => write!(f, "Invalid input format: {}", msg),
+            DiffError::EmptyInput => write!(f, "Both inputs are empty"),
+            DiffError::PatchMismatch(line) => write!(f, "Patch does not match the text at line {}", line),
+        }
+    }
+}
+
+impl Error for DiffError {}
+
+/// JSON Schema describing the serialized shape of a `DiffResult`
+pub fn result_schema() -> serde_json::Value {
+    serde_json::to_value(schemars::schema_for!(DiffResult)).unwrap_or_default()
+}
+
+/// Compute diff between two texts
+pub fn compute_diff(
+    old_text: &str,
+    new_text: &str,
+    options: &DiffOptions,
+) -> Result<DiffResult, DiffError> {
+    compute_diff_cached(old_text, new_text, options, &mut HighlighterCache::new())
+}
+
+/// Compute diff between two texts, reusing highlighters from `highlighters`
+pub fn compute_diff_cached(
+    old_text: &str,
+    new_text: &str,
+    options: &DiffOptions,
+    highlighters: &mut HighlighterCache,
+) -> Result<DiffResult, DiffError> {
+    compute_diff_with(old_text, new_text, options, highlighters, None, None, None)
+}
+
+/// Compute diff, deciding which removed/added line pairs are modifications
+/// with `similarity` rather than Levenshtein distance
+pub fn compute_diff_with_similarity(
+    old_text: &str,
+    new_text: &str,
+    options: &DiffOptions,
+    highlighters: &mut HighlighterCache,
+    similarity: LineSimilarity<'_>,
+) -> Result<DiffResult, DiffError> {
+    compute_diff_with(old_text, new_text, options, highlighters, Some(similarity), None, None)
+}
+
+/// A fixed old text prepared for diffing against many new texts, such as an
+/// editor buffer compared with its saved version on every keystroke
+///
+/// The baseline's lines are split and hashed once. Each diff then hashes
+/// only the new text, and the lines both texts start with are matched by
+/// hash instead of being handed to the diff algorithm. Build a new index
+/// when the baseline changes.
+#[derive(Debug, Clone)]
+pub struct BaselineIndex {
+    text: String,
+    /// `text` with CRLF folded to LF, as the default preprocessing does
+    normalized: String,
+    /// Byte range of each line in `normalized`
+    lines: Vec<Range<usize>>,
+    hashes: Vec<u64>,
+}
+
+impl BaselineIndex {
+    pub fn build(old_text: &str) -> Self {
+        let normalized = strip_bom(old_text).replace("\r\n", "\n");
+        let lines: Vec<Range<usize>> = normalized
+            .lines()
+            .map(|line| {
+                let start = line.as_ptr() as usize - normalized.as_ptr() as usize;
+                start..start + line.len()
+            })
+            .collect();
+        let hashes = lines.iter().map(|range| line_hash(&normalized[range.clone()])).collect();
+
+        Self {
+            text: old_text.to_string(),
+            normalized,
+            lines,
+            hashes,
+        }
+    }
+
+    /// The baseline as given to `build`
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn lines(&self) -> Vec<&str> {
+        self.lines.iter().map(|range| &self.normalized[range.clone()]).collect()
+    }
+
+    /// Number of lines `new_lines` shares with the start of the baseline
+    fn shared_prefix(&self, new_lines: &[&str]) -> usize {
+        self.lines
+            .iter()
+            .zip(&self.hashes)
+            .zip(new_lines)
+            .take_while(|&((range, &hash), &new_line)| {
+                hash == line_hash(new_line) && &self.normalized[range.clone()] == new_line
+            })
+            .count()
+    }
+
+    /// Whether the index's lines are what `options` would diff; the others
+    /// preprocess or filter the baseline differently, and are diffed from
+    /// its text instead
+    fn supports(options: &DiffOptions) -> bool {
+        !(rewrites_lines(options)
+            || options.strict_line_endings
+            || options.smart_blank_lines
+            || options.ignore_blank_lines
+            || options.old_line_range.is_some()
+            || options.new_line_range.is_some())
+    }
+}
+
+fn line_hash(line: &str) -> u64 {
+    let mut hasher = FxHasher::default();
+    line.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Diff `new_text` against an indexed baseline; the result is the same as
+/// `compute_diff(baseline.text(), new_text, options)`
+pub fn compute_diff_against_baseline(
+    baseline: &BaselineIndex,
+    new_text: &str,
+    options: &DiffOptions,
+) -> Result<DiffResult, DiffError> {
+    let index = BaselineIndex::supports(options).then_some(baseline);
+    compute_diff_with(&baseline.text, new_text, options, &mut HighlighterCache::new(), None, index, None)
+}
+
+/// The line alignment one diff found, kept so a second diff of the same
+/// lines can replay it instead of searching again
+///
+/// Only valid between option sets that split and preprocess lines the same
+/// way and steer the search the same way.
+#[derive(Debug, Default)]
+struct RecordedAlignment {
+    changes: Option<Vec<(ChangeType, usize, usize)>>,
+    fallback: Option<AlignmentFallback>,
+}
+
+fn compute_diff_with(
+    old_text: &str,
+    new_text: &str,
+    options: &DiffOptions,
+    highlighters: &mut HighlighterCache,
+    similarity: Option<LineSimilarity<'_>>,
+    baseline: Option<&BaselineIndex>,
+    mut alignment: Option<&mut RecordedAlignment>,
+) -> Result<DiffResult, DiffError> {
+    // A language named by the file's extension counts as given
+    let named_options;
+    let options = match filename_language(options) {
+        Some(language) => {
+            named_options = DiffOptions {
+                language: Some(language),
+                ..options.clone()
+            };
+            &named_options
+        }
+        None => options,
+    };
+
+    // Check file size limits
+    if old_text.len() > options.max_file_size || new_text.len() > options.max_file_size {
+        return Err(DiffError::FileTooLarge);
+    }
+
+    if options.error_on_empty && old_text.is_empty() && new_text.is_empty() {
+        return Err(DiffError::EmptyInput);
+    }
+
+    // Diff only the requested line ranges, as if they were the whole inputs
+    let (full_old, full_new) = (old_text, new_text);
+    let (old_text, old_skipped) = line_range(old_text, options.old_line_range);
+    let (new_text, new_skipped) = line_range(new_text, options.new_line_range);
+
+    // Preprocess text based on options; an indexed baseline only needs the
+    // new text's line breaks folded to match
+    let (processed_old, processed_new) = match baseline {
+        Some(index) => (
+            Cow::Borrowed(index.normalized.as_str()),
+            Cow::Owned(strip_bom(new_text).replace("\r\n", "\n")),
+        ),
+        None => {
+            let (old, new) = preprocess_text(old_text, new_text, options, highlighters);
+            (Cow::Owned(old), Cow::Owned(new))
+        }
+    };
+
+    // Identical inputs need no alignment at all
+    if processed_old == processed_new && !options.emit_all_lines {
+        let total_lines = split_lines(&processed_new, options).len();
+        let mut result = identical_result(old_text, new_text, total_lines, options);
+        result.bom_changed = bom_changed(full_old, full_new);
+        result.final_newline_changed = final_newline_changed(full_old, full_new);
+        result.debug_anchors = options
+            .debug
+            .then(|| (1..=total_lines).map(|n| (n + old_skipped, n + new_skipped)).collect());
+        return Ok(result);
+    }
+
+    // Split into lines
+    let old_lines = match baseline {
+        Some(index) => index.lines(),
+        None => split_lines(&processed_old, options),
+    };
+    let new_lines = split_lines(&processed_new, options);
+    let (old_total, new_total) = (old_lines.len(), new_lines.len());
+    let shared_prefix = baseline.map_or(0, |index| index.shared_prefix(&new_lines));
+
+    // Changes show the inputs' own lines rather than their preprocessed
+    // text; lines stay where they were, so the same indices reach both
+    let shown_lines = rewrites_lines(options)
+        .then(|| (split_lines(strip_bom(old_text), options), split_lines(strip_bom(new_text), options)))
+        .filter(|(old, new)| old.len() == old_total && new.len() == new_total);
+
+    // Drop insignificant blank lines, remembering where the rest came from
+    let kept_lines = if options.ignore_blank_lines {
+        Some((non_blank_lines(&old_lines), non_blank_lines(&new_lines)))
+    } else if options.smart_blank_lines {
+        let language = detect_language(old_text, new_text, options.language.as_deref());
+        significant_lines(&old_lines, language.as_deref())
+            .zip(significant_lines(&new_lines, language.as_deref()))
+    } else {
+        None
+    };
+    let (old_lines, new_lines, shown_lines) = match &kept_lines {
+        Some((old_kept, new_kept)) => (
+            pick_lines(&old_lines, old_kept),
+            pick_lines(&new_lines, new_kept),
+            shown_lines.map(|(old, new)| (pick_lines(&old, old_kept), pick_lines(&new, new_kept))),
+        ),
+        None => (old_lines, new_lines, shown_lines),
+    };
+    let (old_shown, new_shown) = match &shown_lines {
+        Some((old, new)) => (old.as_slice(), new.as_slice()),
+        None => (old_lines.as_slice(), new_lines.as_slice()),
+    };
+
+    // Select the diff algorithm
+    let differ = match options.algorithm {
+        DiffAlgorithm::Myers => MyersDiff::new(&old_lines, &new_lines),
+        DiffAlgorithm::Patience => {
+            // For now, fallback to Myers
+            MyersDiff::new(&old_lines, &new_lines)
+        }
+        DiffAlgorithm::Histogram => {
+            // For now, fallback to Myers
+            MyersDiff::new(&old_lines, &new_lines)
+        }
+    };
+    let differ = configure_differ(differ, options, similarity);
+    let mut alignment_fallback = None;
+
+    // Replay a recorded alignment, or record this one as it is found
+    let replay = alignment.as_deref_mut().and_then(|recorded| {
+        let changes = recorded.changes.take()?;
+        alignment_fallback = recorded.fallback;
+        Some(changes)
+    });
+    let mut recording = (alignment.is_some() && replay.is_none()).then(Vec::new);
+
+    let analyzer = options
+        .semantic_diff
+        .then(|| SemanticAnalyzer::new(options.language.as_deref()));
+
+    // Group changes into hunks, or keep the whole file as one sequence
+    let mut unchanged_runs = UnchangedRuns::default();
+    let mut anchors = Vec::new();
+    let mut observe = |change_type, old_idx, new_idx| {
+        unchanged_runs.record(change_type);
+        if let Some(recording) = &mut recording {
+            recording.push((change_type, old_idx, new_idx));
+        }
+        if options.debug && change_type == ChangeType::Unchanged {
+            anchors.push((old_idx, new_idx));
+        }
+    };
+    // String literals stay whole in word spans and token alignment; the
+    // highlighter that finds them is looked up once for the whole diff
+    let strings_language = if options.token_diff {
+        detect_language(old_text, new_text, options.language.as_deref())
+    } else if options.intra_line == IntraLineMode::Word && !options.word_diff_split_strings {
+        options.language.clone()
+    } else {
+        None
+    };
+    let strings = strings_language.and_then(|language| highlighters.get(&language, None));
+    let word_strings = strings.filter(|_| options.language.is_some() && !options.word_diff_split_strings);
+
+    let hunks = if options.emit_all_lines {
+        let changes = match replay {
+            Some(changes) => changes,
+            None => {
+                let changes = differ.compute_diff();
+                alignment_fallback = differ.fallback();
+                changes
+            }
+        };
+        for &(change_type, old_idx, new_idx) in &changes {
+            observe(change_type, old_idx, new_idx);
+        }
+
+        create_full_hunk(changes, old_shown, new_shown, options, word_strings)
+    } else {
+        // Semantic analysis only annotates the finished hunks, so hunks are built
+        // as the diff produces them instead of collecting every unchanged line first
+        let mut builder = HunkBuilder::new(old_shown, new_shown, options, word_strings);
+        let mut push = |change_type, old_idx, new_idx| {
+            observe(change_type, old_idx, new_idx);
+            builder.push(change_type, old_idx, new_idx);
+        };
+        if let Some(changes) = replay {
+            for (change_type, old_idx, new_idx) in changes {
+                push(change_type, old_idx, new_idx);
+            }
+        } else if options.token_diff {
+            token_diff::for_each_line_change(&old_lines, &new_lines, strings, push);
+        } else if shared_prefix > 0 {
+            // Myers would follow this diagonal first anyway, so aligning the
+            // rest from its end gives the same script
+            for idx in 0..shared_prefix {
+                push(ChangeType::Unchanged, idx, idx);
+            }
+            let rest = MyersDiff::new(&old_lines[shared_prefix..], &new_lines[shared_prefix..]);
+            let rest = configure_differ(rest, options, similarity);
+            rest.for_each_change(|change_type, old_idx, new_idx| {
+                push(change_type, old_idx + shared_prefix, new_idx + shared_prefix)
+            });
+            alignment_fallback = rest.fallback();
+        } else {
+            differ.for_each_change(push);
+            alignment_fallback = differ.fallback();
+        }
+
+        let mut hunks = merge_adjacent_hunks(builder.finish());
+        if let Some(analyzer) = &analyzer {
+            add_header_context(&mut hunks, old_shown, analyzer);
+        }
+        hunks
+    };
+    if let (Some(recorded), Some(changes)) = (alignment, recording) {
+        recorded.changes = Some(changes);
+        recorded.fallback = alignment_fallback;
+    }
+
+    let mut hunks = hunks;
+    if options.reformat_blocks && options.line_numbers {
+        collapse_reformat_blocks(&mut hunks, old_shown, new_shown, options, word_strings);
+    }
+    if let Some(analyzer) = &analyzer {
+        for hunk in &mut hunks {
+            analyzer.analyze_changes(&mut hunk.changes, old_shown, new_shown);
+        }
+    }
+    if let Some(threshold) = options.auto_collapse_threshold {
+        suggest_collapsed(&mut hunks, old_shown, analyzer.as_ref(), threshold);
+    }
+    if let Some((old_kept, new_kept)) = &kept_lines {
+        restore_line_numbers(&mut hunks, old_kept, new_kept);
+    }
+    if old_skipped > 0 || new_skipped > 0 {
+        offset_line_numbers(&mut hunks, old_skipped, new_skipped);
+    }
+    if options.detect_moves && options.line_numbers {
+        mark_moved_blocks(&mut hunks);
+    }
+
+    // Apply syntax highlighting if enabled
+    let highlighting_skipped = if options.syntax_highlight {
+        apply_syntax_highlighting(&mut hunks, full_old, full_new, options, highlighters)?
+    } else {
+        false
+    };
+    if options.include_byte_offsets {
+        add_byte_ranges(&mut hunks, full_old, full_new);
+    }
+    let has_conflict_markers = mark_conflict_regions(&mut hunks, full_old, full_new, options);
+    encode_content(&mut hunks, options.content_encoding);
+
+    // Calculate statistics
+    let mut stats = calculate_stats(&hunks, old_total, new_total, options);
+    stats.longest_unchanged_run = unchanged_runs.longest();
+
+    // Anchors are indices into the lines that were diffed
+    let debug_anchors = options.debug.then(|| {
+        let input_line = |kept: Option<&Vec<usize>>, idx: usize, skipped: usize| {
+            kept.map_or(idx, |kept| kept[idx]) + skipped + 1
+        };
+        anchors
+            .into_iter()
+            .map(|(old_idx, new_idx)| {
+                (
+                    input_line(kept_lines.as_ref().map(|(old_kept, _)| old_kept), old_idx, old_skipped),
+                    input_line(kept_lines.as_ref().map(|(_, new_kept)| new_kept), new_idx, new_skipped),
+                )
+            })
+            .collect()
+    });
+
+    let line_ending = LineEnding::of_inputs(old_text, new_text);
+    let mut result = DiffResult {
+        hunks,
+        stats,
+        file_language: detect_language(old_text, new_text, options.language.as_deref()),
+        is_binary: is_binary(old_text) || is_binary(new_text),
+        is_large_file: old_text.len() > options.large_file_threshold
+            || new_text.len() > options.large_file_threshold,
+        payload_reduction: None,
+        highlighting_skipped,
+        line_ending: line_ending.unwrap_or_default(),
+        line_ending_detected: line_ending.is_some(),
+        bom_changed: bom_changed(full_old, full_new),
+        final_newline_changed: final_newline_changed(full_old, full_new),
+        has_conflict_markers,
+        debug_anchors,
+        alignment_fallback,
+    };
+
+    if let Some(max_bytes) = options.max_payload_bytes {
+        result.fit_payload(max_bytes);
+    }
+
+    Ok(result)
+}
+
+/// Apply the options that steer the Myers search to `differ`
+fn configure_differ<'a>(
+    differ: MyersDiff<'a>,
+    options: &DiffOptions,
+    similarity: Option<LineSimilarity<'a>>,
+) -> MyersDiff<'a> {
+    let differ = match similarity {
+        Some(similarity) => differ.with_similarity(similarity),
+        None => differ,
+    };
+    let differ = match options.max_edit_distance {
+        Some(max_edits) => differ.with_max_edits(max_edits),
+        None => differ,
+    };
+    // Flagged lines have to pair up first, or a short line would be a removal and an addition
+    if options.flag_whitespace_only {
+        differ.pair_trimmed()
+    } else {
+        differ
+    }
+}
+
+/// Compute diff between two byte buffers that may not be valid UTF-8
+///
+/// Invalid sequences are decoded as U+FFFD and the lines still take part in
+/// the diff, but changes touching such a line are flagged with
+/// `has_invalid_bytes`, telling replacement artifacts apart from a literal
+/// U+FFFD in the text. Flags need `line_numbers` to locate the lines.
+pub fn compute_diff_bytes(
+    old_bytes: &[u8],
+    new_bytes: &[u8],
+    options: &DiffOptions,
+) -> Result<DiffResult, DiffError> {
+    let old_text = String::from_utf8_lossy(old_bytes);
+    let new_text = String::from_utf8_lossy(new_bytes);
+    let mut result = compute_diff(&old_text, &new_text, options)?;
+
+    let old_invalid = invalid_lines(old_bytes);
+    let new_invalid = invalid_lines(new_bytes);
+    let is_invalid = |lines: &[bool], line_number: Option<usize>| {
+        line_number
+            .and_then(|n| lines.get(n.checked_sub(1)?))
+            .copied()
+            .unwrap_or(false)
+    };
+
+    for change in result.hunks.iter_mut().flat_map(|hunk| &mut hunk.changes) {
+        change.has_invalid_bytes = is_invalid(&old_invalid, change.old_line_number)
+            || is_invalid(&new_invalid, change.new_line_number);
+    }
+
+    Ok(result)
+}
+
+/// Whether each line of `bytes` holds invalid UTF-8
+fn invalid_lines(bytes: &[u8]) -> Vec<bool> {
+    bytes
+        .split(|&b| b == b'\n')
+        .map(|line| std::str::from_utf8(line).is_err())
+        .collect()
+}
+
+/// One file pair of a `compute_batch` run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchEntry {
+    pub path: String,
+    pub similarity: f32,
+    /// The full diff, or `None` for a pair too similar to be worth reviewing
+    pub result: Option<DiffResult>,
+}
+
+/// Diff a batch of `(path, old_text, new_text)` pairs, such as a folder
+///
+/// A pair whose similarity exceeds `1.0 - min_divergence` is only aligned,
+/// without highlighting or hunk detail, and reported with its similarity but
+/// no result; a `min_divergence` of 0 keeps every result. Fails on the first
+/// pair that fails to diff.
+///
+/// With the `rayon` feature on native targets, pairs are diffed in parallel;
+/// entries still come back in input order.
+pub fn compute_batch(
+    pairs: &[(&str, &str, &str)],
+    options: &DiffOptions,
+    min_divergence: f32,
+) -> Result<Vec<BatchEntry>, DiffError> {
+    #[cfg(all(feature = "rayon", not(target_arch = "wasm32")))]
+    {
+        use rayon::prelude::*;
+
+        let survey_options = batch_survey_options(options);
+        pairs
+            .par_iter()
+            .map_init(HighlighterCache::new, |highlighters, &pair| {
+                diff_batch_pair(pair, options, &survey_options, min_divergence, highlighters)
+            })
+            .collect()
+    }
+    #[cfg(not(all(feature = "rayon", not(target_arch = "wasm32"))))]
+    compute_batch_sequential(pairs, options, min_divergence)
+}
+
+/// `compute_batch` on the calling thread, sharing highlighters across the batch
+pub fn compute_batch_sequential(
+    pairs: &[(&str, &str, &str)],
+    options: &DiffOptions,
+    min_divergence: f32,
+) -> Result<Vec<BatchEntry>, DiffError> {
+    let survey_options = batch_survey_options(options);
+    let mut highlighters = HighlighterCache::new();
+    pairs
+        .iter()
+        .map(|&pair| diff_batch_pair(pair, options, &survey_options, min_divergence, &mut highlighters))
+        .collect()
+}
+
+/// Options for the cheap first pass that measures a batch pair's similarity
+fn batch_survey_options(options: &DiffOptions) -> DiffOptions {
+    DiffOptions {
+        context_lines: 0,
+        semantic_diff: false,
+        syntax_highlight: false,
+        intra_line: IntraLineMode::None,
+        mark_whitespace: false,
+        include_byte_offsets: false,
+        include_enclosing_signature: false,
+        auto_collapse_threshold: None,
+        max_payload_bytes: None,
+        debug: false,
+        ..options.clone()
+    }
+}
+
+fn diff_batch_pair(
+    (path, old_text, new_text): (&str, &str, &str),
+    options: &DiffOptions,
+    survey_options: &DiffOptions,
+    min_divergence: f32,
+    highlighters: &mut HighlighterCache,
+) -> Result<BatchEntry, DiffError> {
+    // The full diff replays the survey's alignment rather than searching again
+    let mut alignment = RecordedAlignment::default();
+    let similarity =
+        compute_diff_with(old_text, new_text, survey_options, highlighters, None, None, Some(&mut alignment))?
+            .stats
+            .similarity;
+    let result = if similarity > 1.0 - min_divergence {
+        None
+    } else {
+        Some(compute_diff_with(old_text, new_text, options, highlighters, None, None, Some(&mut alignment))?)
+    };
+    Ok(BatchEntry {
+        path: path.to_string(),
+        similarity,
+        result,
+    })
+}
+
+/// Sum the stats of a batch of file diffs per language
+///
+/// Each entry pairs a file path with its diff. The language is the one the
+/// diff detected, falling back to the path's extension. Similarity is
+/// recomputed from the summed counts.
+pub fn aggregate_by_language(
+    results: &[(String, DiffResult)],
+    options: &DiffOptions,
+) -> HashMap<String, DiffStats> {
+    let mut counts: HashMap<String, (usize, usize, usize, usize)> = HashMap::new();
+
+    for (path, result) in results {
+        let language = result
+            .file_language
+            .clone()
+            .unwrap_or_else(|| {
+                crate::syntax::detect_language_with(path, "", &options.extension_languages)
+            });
+
+        let entry = counts.entry(language).or_default();
+        entry.0 += result.stats.total_lines;
+        entry.1 += result.stats.added_lines;
+        entry.2 += result.stats.removed_lines;
+        entry.3 += result.stats.modified_lines;
+    }
+
+    counts
+        .into_iter()
+        .map(|(language, (total, added, removed, modified))| {
+            (language, DiffStats::from_counts(total, added, removed, modified, options))
+        })
+        .collect()
+}
+
+/// Header matching the rows of `stats_to_csv_row`
+pub fn stats_csv_header() -> &'static str {
+    "path,added,removed,modified,similarity,churn"
+}
+
+/// One CSV row of a file's stats, without a line break, for reporting churn
+/// across a batch
+///
+/// Churn counts a modified line as one removal plus one addition, as
+/// `git diff --numstat` does. The path is quoted when it needs to be.
+pub fn stats_to_csv_row(path: &str, stats: &DiffStats) -> String {
+    let path = if path.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", path.replace('"', "\"\""))
+    } else {
+        path.to_string()
+    };
+    let churn = stats.added_lines + stats.removed_lines + 2 * stats.modified_lines;
+    format!(
+        "{},{},{},{},{:.4},{}",
+        path, stats.added_lines, stats.removed_lines, stats.modified_lines, stats.similarity, churn
+    )
+}
+
+/// Compute only the change density across the file, for a minimap
+///
+/// Lines are aligned as in `compute_diff`, but no hunks, content or tokens
+/// are built. The new file is split into `buckets` equal spans (the old file
+/// when the new one is empty), and each value is the fraction of lines in
+/// its span that changed. Removed lines count where they would have been in
+/// the new file, and a block replaced by another counts as its longer side.
+pub fn compute_heatmap(
+    old_text: &str,
+    new_text: &str,
+    buckets: usize,
+    options: &DiffOptions,
+) -> Result<Vec<f32>, DiffError> {
+    if old_text.len() > options.max_file_size || new_text.len() > options.max_file_size {
+        return Err(DiffError::FileTooLarge);
+    }
+    if buckets == 0 {
+        return Ok(Vec::new());
+    }
+
+    let (processed_old, processed_new) = preprocess_text(old_text, new_text, options, &mut HighlighterCache::new());
+    let old_lines = split_lines(&processed_old, options);
+    let new_lines = split_lines(&processed_new, options);
+
+    let use_old = new_lines.is_empty();
+    let span = if use_old { old_lines.len() } else { new_lines.len() }.max(1);
+    let bucket_of = |line: usize| (line.min(span - 1) * buckets / span).min(buckets - 1);
+
+    // Removals and additions are tallied apart so a replaced block counts once
+    let mut removed = vec![0usize; buckets];
+    let mut added = vec![0usize; buckets];
+    MyersDiff::new(&old_lines, &new_lines).for_each_change(|change_type, old_idx, new_idx| {
+        let bucket = bucket_of(if use_old { old_idx } else { new_idx });
+        match change_type {
+            ChangeType::Unchanged | ChangeType::Moved => {}
+            ChangeType::Removed => removed[bucket] += 1,
+            ChangeType::Added => added[bucket] += 1,
+            ChangeType::Modified | ChangeType::ReformatBlock => {
+                removed[bucket] += 1;
+                added[bucket] += 1;
+            }
+        }
+    });
+
+    let heatmap = removed
+        .iter()
+        .zip(&added)
+        .enumerate()
+        .map(|(bucket, (&removed, &added))| {
+            let count = removed.max(added);
+            // Lines covered by this bucket (at least one, for files shorter than `buckets`)
+            let start = (bucket * span).div_ceil(buckets);
+            let end = ((bucket + 1) * span).div_ceil(buckets);
+            (count as f32 / (end - start).max(1) as f32).min(1.0)
+        })
+        .collect();
+
+    Ok(heatmap)
+}
+
+/// Render `result`, the diff of `old_text` against `new_text`, as a patch
+/// `git apply` accepts
+///
+/// Hunks carry `context` lines of context, a file without a trailing newline
+/// gets the `\ No newline at end of file` marker, and an empty side is
+/// written as a created or deleted file. Identical inputs give an empty
+/// patch. Lines are written as in `to_unified_diff`, so the patch reproduces
+/// `new_text` wherever the diff found a change.
+pub fn to_git_patch(
+    result: &DiffResult,
+    old_text: &str,
+    new_text: &str,
+    old_path: &str,
+    new_path: &str,
+    context: usize,
+) -> String {
+    if old_text == new_text {
+        return String::new();
+    }
+
+    let mut patch = format!("diff --git a/{} b/{}\n", old_path, new_path);
+    if old_text.is_empty() {
+        patch.push_str("new file mode 100644\n--- /dev/null\n");
+    } else if new_text.is_empty() {
+        patch.push_str(&format!("deleted file mode 100644\n--- a/{}\n", old_path));
+    } else {
+        patch.push_str(&format!("--- a/{}\n", old_path));
+    }
+    if new_text.is_empty() {
+        patch.push_str("+++ /dev/null\n");
+    } else {
+        patch.push_str(&format!("+++ b/{}\n", new_path));
+    }
+
+    push_unified_hunks(&mut patch, result, old_text, new_text, context);
+    patch
+}
+
+/// Render `result`, the diff of `old_text` against `new_text`, as a plain
+/// unified diff
+///
+/// Like `to_git_patch` without the `diff --git` and file mode lines: the
+/// `---`/`+++` headers carry `old_name` and `new_name` as given, and hunks
+/// carry `context` lines of context. Identical inputs give an empty diff.
+///
+/// Lines come from the texts as written, so a pair the diff matched under
+/// `ignore_*` normalization, or one that only gained or lost its line break,
+/// is written as a removal and an addition when it falls inside a hunk. The
+/// result must cover the whole texts, without hunks cut by `max_payload_bytes`.
+pub fn to_unified_diff(
+    result: &DiffResult,
+    old_text: &str,
+    new_text: &str,
+    old_name: &str,
+    new_name: &str,
+    context: usize,
+) -> String {
+    if old_text == new_text {
+        return String::new();
+    }
+
+    let mut diff = format!("--- {}\n+++ {}\n", old_name, new_name);
+    push_unified_hunks(&mut diff, result, old_text, new_text, context);
+    diff
+}
+
+/// Append the `@@` hunks of `result` to `patch`
+fn push_unified_hunks(patch: &mut String, result: &DiffResult, old_text: &str, new_text: &str, context: usize) {
+    // Keep terminators so a line gaining or losing its newline counts as changed
+    let old_lines: Vec<&str> = old_text.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new_text.split_inclusive('\n').collect();
+    let ops = edit_script_from_result(result, &old_lines, &new_lines);
+
+    // Line positions on each side before each op
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for op in &ops {
+        positions.push((old_pos, new_pos));
+        match op {
+            EditOp::Keep(..) => {
+                old_pos += 1;
+                new_pos += 1;
+            }
+            EditOp::Delete(_) => old_pos += 1,
+            EditOp::Insert(_) => new_pos += 1,
+        }
+    }
+    positions.push((old_pos, new_pos));
+
+    let changed: Vec<usize> = (0..ops.len())
+        .filter(|&i| !matches!(ops[i], EditOp::Keep(..)))
+        .collect();
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        match groups.last_mut() {
+            Some((_, last)) if i - *last <= context * 2 + 1 => *last = i,
+            _ => groups.push((i, i)),
+        }
+    }
+
+    for (first, last) in groups {
+        let start = first.saturating_sub(context);
+        let end = (last + context + 1).min(ops.len());
+        let (old_start, new_start) = positions[start];
+        let old_count = positions[end].0 - old_start;
+        let new_count = positions[end].1 - new_start;
+
+        // An empty range names the line before it
+        let range = |start: usize, count: usize| {
+            format!("{},{}", if count == 0 { start } else { start + 1 }, count)
+        };
+        patch.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_count),
+            range(new_start, new_count)
+        ));
+
+        for op in &ops[start..end] {
+            let (prefix, line) = match *op {
+                EditOp::Keep(i, _) => (' ', old_lines[i]),
+                EditOp::Delete(i) => ('-', old_lines[i]),
+                EditOp::Insert(j) => ('+', new_lines[j]),
+            };
+            patch.push(prefix);
+            patch.push_str(line);
+            if !line.ends_with('\n') {
+                patch.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+}
+
+/// Edit script over the raw lines following the pairing in `result`'s hunks
+///
+/// Paired lines that differ as written become a deletion and an insertion,
+/// and within each run of changes deletions come before insertions.
+fn edit_script_from_result(result: &DiffResult, old_lines: &[&str], new_lines: &[&str]) -> Vec<EditOp> {
+    let map = result.line_map(old_lines.len(), new_lines.len());
+    let mut ops = Vec::with_capacity(old_lines.len().max(new_lines.len()));
+    let mut inserts = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < old_lines.len() || j < new_lines.len() {
+        if i < old_lines.len() && j < new_lines.len() && map.old_to_new[i] == Some(j) {
+            if old_lines[i] == new_lines[j] {
+                ops.append(&mut inserts);
+                ops.push(EditOp::Keep(i, j));
+            } else {
+                ops.push(EditOp::Delete(i));
+                inserts.push(EditOp::Insert(j));
+            }
+            i += 1;
+            j += 1;
+        } else if i < old_lines.len() && map.old_to_new[i].is_none_or(|k| k < j) {
+            ops.push(EditOp::Delete(i));
+            i += 1;
+        } else {
+            inserts.push(EditOp::Insert(j));
+            j += 1;
+        }
+    }
+    ops.append(&mut inserts);
+
+    ops
+}
+
+/// Find blocks of at least `min_lines` lines that repeat earlier ones in the
+/// file, such as a function pasted twice
+///
+/// Lines are compared ignoring trailing whitespace, and lines without any
+/// letters or digits (closing braces, blank lines) never start a block. Each
+/// repeat is grown as far as it matches and reported once, against the first
+/// occurrence; copies that overlap their original are not reported.
+pub fn find_duplicate_blocks(new_text: &str, min_lines: usize) -> Vec<DuplicateBlock> {
+    let lines: Vec<&str> = new_text.lines().map(str::trim_end).collect();
+    let min_lines = min_lines.max(1);
+    let mut first_seen: HashMap<&[&str], usize> = HashMap::new();
+    let mut blocks = Vec::new();
+
+    let mut i = 0;
+    while i + min_lines <= lines.len() {
+        if !lines[i].chars().any(char::is_alphanumeric) {
+            i += 1;
+            continue;
+        }
+        let window = &lines[i..i + min_lines];
+        match first_seen.get(window) {
+            Some(&first) if first + min_lines <= i => {
+                let mut line_count = min_lines;
+                while i + line_count < lines.len()
+                    && first + line_count < i
+                    && lines[first + line_count] == lines[i + line_count]
+                {
+                    line_count += 1;
+                }
+                blocks.push(DuplicateBlock {
+                    first_line: first + 1,
+                    duplicate_line: i + 1,
+                    line_count,
+                });
+                i += line_count;
+            }
+            Some(_) => i += 1,
+            None => {
+                first_seen.insert(window, i);
+                i += 1;
+            }
+        }
+    }
+
+    blocks
+}
+
+/// Build the result for inputs that are equal after preprocessing
+fn identical_result(
+    old_text: &str,
+    new_text: &str,
+    total_lines: usize,
+    options: &DiffOptions,
+) -> DiffResult {
+    let line_ending = LineEnding::of_inputs(old_text, new_text);
+    DiffResult {
+        hunks: Vec::new(),
+        stats: DiffStats::from_counts(total_lines, 0, 0, 0, options),
+        file_language: detect_language(old_text, new_text, options.language.as_deref()),
+        is_binary: is_binary(old_text) || is_binary(new_text),
+        is_large_file: old_text.len() > options.large_file_threshold
+            || new_text.len() > options.large_file_threshold,
+        payload_reduction: None,
+        highlighting_skipped: false,
+        line_ending: line_ending.unwrap_or_default(),
+        line_ending_detected: line_ending.is_some(),
+        bom_changed: bom_changed(old_text, new_text),
+        final_newline_changed: final_newline_changed(old_text, new_text),
+        has_conflict_markers: conflict_lines(old_text).contains(&true) || conflict_lines(new_text).contains(&true),
+        debug_anchors: None,
+        alignment_fallback: None,
+    }
+}
+
+/// Extract unchanged lines as context changes, for expanding a fold without re-diffing
+///
+/// Ranges are 1-based line numbers with an exclusive end. Lines are paired up
+/// in order, so the result is as long as the shorter of the two ranges.
+pub fn extract_context(
+    old_text: &str,
+    new_text: &str,
+    old_range: Range<usize>,
+    new_range: Range<usize>,
+) -> Vec<DiffChange> {
+    let new_lines: Vec<&str> = new_text.lines().collect();
+    let old_count = old_text.lines().count();
+
+    old_range
+        .zip(new_range)
+        .take_while(|&(old_num, new_num)| {
+            old_num >= 1 && new_num >= 1 && old_num <= old_count && new_num <= new_lines.len()
+        })
+        .map(|(old_num, new_num)| {
+            DiffChange::new(
+                ChangeType::Unchanged,
+                Some(old_num),
+                Some(new_num),
+                new_lines[new_num - 1].to_string(),
+            )
+        })
+        .collect()
+}
+
+/// Byte order mark, which only means anything at the start of a text
+const BOM: char = '\u{feff}';
+
+fn strip_bom(text: &str) -> &str {
+    text.strip_prefix(BOM).unwrap_or(text)
+}
+
+fn bom_changed(old_text: &str, new_text: &str) -> bool {
+    old_text.starts_with(BOM) != new_text.starts_with(BOM)
+}
+
+/// An empty input has no last line to end, so it never counts
+fn final_newline_changed(old_text: &str, new_text: &str) -> bool {
+    !old_text.is_empty() && !new_text.is_empty() && old_text.ends_with('\n') != new_text.ends_with('\n')
+}
+
+/// Whether preprocessing changes the text of lines, beyond the byte order
+/// mark and line breaks; it never adds, drops or reorders them
+fn rewrites_lines(options: &DiffOptions) -> bool {
+    options.ignore_whitespace
+        || options.ignore_case
+        || !options.case_insensitive_tokens.is_empty()
+        || options.ignore_formatting
+        || options.ignore_number_format
+}
+
+/// Preprocess text based on diff options
+fn preprocess_text(
+    old_text: &str,
+    new_text: &str,
+    options: &DiffOptions,
+    highlighters: &mut HighlighterCache,
+) -> (String, String) {
+    let mut old = strip_bom(old_text).to_string();
+    let mut new = strip_bom(new_text).to_string();
+
+    // Only CRLF is folded; treating a lone `\r` as a break would shift line
+    // numbers away from the inputs'
+    if !options.strict_line_endings {
+        old = old.replace("\r\n", "\n");
+        new = new.replace("\r\n", "\n");
+    }
+
+    if options.ignore_whitespace {
+        old = normalize_whitespace(&old);
+        new = normalize_whitespace(&new);
+    }
+
+    if options.ignore_case {
+        old = old.to_lowercase();
+        new = new.to_lowercase();
+    } else if !options.case_insensitive_tokens.is_empty() {
+        let language = detect_language(old_text, new_text, options.language.as_deref());
+        if let Some(highlighter) = language.and_then(|language| highlighters.get(&language, None)) {
+            old = lowercase_tokens(&old, highlighter, &options.case_insensitive_tokens);
+            new = lowercase_tokens(&new, highlighter, &options.case_insensitive_tokens);
+        }
+    }
+
+    if options.ignore_formatting {
+        let language = detect_language(old_text, new_text, options.language.as_deref());
+        if matches!(language.as_deref(), Some("javascript" | "typescript")) {
+            old = normalize_js_formatting(&old);
+            new = normalize_js_formatting(&new);
+        }
+    }
+
+    if options.ignore_number_format {
+        old = normalize_numbers(&old);
+        new = normalize_numbers(&new);
+    }
+
+    (old, new)
+}
+
+/// Lowercase the tokens of `text` whose type is one of `token_types`
+fn lowercase_tokens(text: &str, highlighter: &SyntaxHighlighter, token_types: &[String]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut position = 0;
+    for (token_type, _, start, end) in highlighter.highlight(text) {
+        if token_types.contains(&token_type) {
+            out.push_str(&text[position..start]);
+            out.push_str(&text[start..end].to_lowercase());
+            position = end;
+        }
+    }
+    out.push_str(&text[position..]);
+    out
+}
+
+/// Number of lines the diff sees in `text`, e.g. for `DiffResult::line_map`
+pub fn line_count(text: &str, options: &DiffOptions) -> usize {
+    split_lines(strip_bom(text), options).len()
+}
+
+/// Lines of preprocessed text; under `strict_line_endings` the `\r` of a
+/// CRLF stays on its line, so it differs from the same line ending in LF
+fn split_lines<'a>(text: &'a str, options: &DiffOptions) -> Vec<&'a str> {
+    if options.strict_line_endings {
+        text.split_terminator('\n').collect()
+    } else {
+        text.lines().collect()
+    }
+}
+
+/// Numeric literals: hex, octal and binary integers, and decimals with an
+/// optional fraction and exponent. Broader than the highlighter's number
+/// rule, which only knows plain integers.
+static NUMBER_LITERAL: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\b(?:0[xX][0-9a-fA-F_]+|0[oO][0-7_]+|0[bB][01_]+|\d[\d_]*(?:\.\d+)?(?:[eE][+-]?\d+)?)\b")
+        .unwrap()
+});
+
+/// Rewrite every numeric literal in its canonical decimal form
+///
+/// Radix-prefixed integers are parsed as `u128`; decimals are canonicalized
+/// as text so no precision is lost. Literals too large to parse are left as
+/// written.
+fn normalize_numbers(text: &str) -> String {
+    NUMBER_LITERAL
+        .replace_all(text, |caps: &regex::Captures| {
+            let literal = caps[0].replace('_', "");
+            let radix = match literal.get(..2) {
+                Some("0x" | "0X") => 16,
+                Some("0o" | "0O") => 8,
+                Some("0b" | "0B") => 2,
+                _ => 10,
+            };
+            if radix == 10 {
+                canonical_decimal(&literal).unwrap_or_else(|| caps[0].to_string())
+            } else {
+                u128::from_str_radix(&literal[2..], radix)
+                    .ok()
+                    .and_then(|value| canonical_decimal(&value.to_string()))
+                    .unwrap_or_else(|| caps[0].to_string())
+            }
+        })
+        .into_owned()
+}
+
+/// Canonical text for a decimal literal such as `1_000`, `1.50` or `15e-1`
+///
+/// The value is reduced to significant digits and a power of ten, then
+/// written out in full when the exponent is small and in `e` notation
+/// otherwise, so equal values always produce the same text.
+fn canonical_decimal(literal: &str) -> Option<String> {
+    const MAX_PLAIN_EXPONENT: i64 = 32;
+
+    let (mantissa, exponent) = match literal.find(['e', 'E']) {
+        Some(pos) => (&literal[..pos], literal[pos + 1..].parse::<i64>().ok()?),
+        None => (literal, 0),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+
+    let digits = format!("{whole}{fraction}");
+    let digits = digits.trim_start_matches('0');
+    let significant = digits.trim_end_matches('0');
+    if significant.is_empty() {
+        return Some("0".to_string());
+    }
+    let exponent = exponent
+        .checked_sub(fraction.len() as i64)?
+        .checked_add((digits.len() - significant.len()) as i64)?;
+
+    if (0..=MAX_PLAIN_EXPONENT).contains(&exponent) {
+        Some(format!("{significant}{}", "0".repeat(exponent as usize)))
+    } else if exponent < 0 && -exponent <= MAX_PLAIN_EXPONENT + significant.len() as i64 {
+        let shift = (-exponent) as usize;
+        if shift < significant.len() {
+            let (int_part, frac_part) = significant.split_at(significant.len() - shift);
+            Some(format!("{int_part}.{frac_part}"))
+        } else {
+            Some(format!("0.{}{significant}", "0".repeat(shift - significant.len())))
+        }
+    } else {
+        Some(format!("{significant}e{exponent}"))
+    }
+}
+
+/// Undo the superficial changes a JS formatter makes, line by line
+///
+/// Outside string literals, whitespace is dropped except where it separates
+/// two identifier characters, single-quoted strings become double-quoted, and
+/// a trailing semicolon is removed. String contents are left untouched.
+fn normalize_js_formatting(text: &str) -> String {
+    text.split('\n')
+        .map(normalize_js_line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn normalize_js_line(line: &str) -> String {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.trim().chars().peekable();
+    let mut pending_space = false;
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            pending_space = true;
+            continue;
+        }
+
+        if pending_space && out.ends_with(is_word) && is_word(c) {
+            out.push(' ');
+        }
+        pending_space = false;
+
+        if !matches!(c, '\'' | '"' | '`') {
+            out.push(c);
+            continue;
+        }
+
+        // Copy the string literal, switching single quotes to double when safe
+        let mut literal = String::new();
+        let mut closed = false;
+        while let Some(inner) = chars.next() {
+            if inner == '\\' {
+                literal.push(inner);
+                literal.extend(chars.next());
+            } else if inner == c {
+                closed = true;
+                break;
+            } else {
+                literal.push(inner);
+            }
+        }
+
+        let quote = if c == '\'' && !literal.contains('"') { '"' } else { c };
+        let literal = if quote != c { literal.replace("\\'", "'") } else { literal };
+        out.push(quote);
+        out.push_str(&literal);
+        if closed {
+            out.push(quote);
+        }
+    }
+
+    if out.ends_with(';') {
+        out.pop();
+    }
+    out
+}
+
+/// Indices of the lines `smart_blank_lines` keeps, or None if the language
+/// gives blank lines no special treatment
+fn significant_lines(lines: &[&str], language: Option<&str>) -> Option<Vec<usize>> {
+    let is_blank = |i: usize| lines[i].trim().is_empty();
+
+    match language? {
+        // A blank line before a top-level statement separates definitions,
+        // unless nothing precedes it
+        "python" => {
+            let mut keep = vec![false; lines.len()];
+            let mut next_top_level = false;
+            for i in (0..lines.len()).rev() {
+                keep[i] = is_blank(i) && next_top_level;
+                if !is_blank(i) {
+                    keep[i] = true;
+                    next_top_level = !lines[i].starts_with(char::is_whitespace);
+                }
+            }
+            let first_code = (0..lines.len()).find(|&i| !is_blank(i)).unwrap_or(lines.len());
+            Some((first_code..lines.len()).filter(|&i| keep[i]).collect())
+        }
+        "javascript" | "typescript" | "rust" | "java" | "c" | "cpp" | "csharp" | "go" | "json" => {
+            Some(non_blank_lines(lines))
+        }
+        _ => None,
+    }
+}
+
+/// The lines at `indices`, in order
+fn pick_lines<'a>(lines: &[&'a str], indices: &[usize]) -> Vec<&'a str> {
+    indices.iter().map(|&i| lines[i]).collect()
+}
+
+/// Indices of the lines with anything but whitespace on them
+fn non_blank_lines(lines: &[&str]) -> Vec<usize> {
+    (0..lines.len()).filter(|&i| !lines[i].trim().is_empty()).collect()
+}
+
+/// Map line numbers and hunk starts from the kept lines back to the inputs
+fn restore_line_numbers(hunks: &mut [DiffHunk], old_kept: &[usize], new_kept: &[usize]) {
+    // 1-based position among kept lines to 1-based input line; one past the
+    // last kept line maps one past the last input line it could be
+    let restore = |kept: &[usize], n: usize| match n.checked_sub(1).map(|i| kept.get(i)) {
+        None => 0,
+        Some(Some(&i)) => i + 1,
+        Some(None) => kept.last().map_or(n, |&i| i + 1 + n - kept.len()),
+    };
+
+    for hunk in hunks {
+        hunk.old_start = restore(old_kept, hunk.old_start);
+        hunk.new_start = restore(new_kept, hunk.new_start);
+        rewrite_header_range(hunk);
+        for change in &mut hunk.changes {
+            change.old_line_number = change.old_line_number.map(|n| restore(old_kept, n));
+            change.new_line_number = change.new_line_number.map(|n| restore(new_kept, n));
+        }
+    }
+}
+
+/// The lines of `text` in a 1-based range with an exclusive end, clamped to
+/// the text, and the number of lines before them
+fn line_range(text: &str, range: Option<(usize, usize)>) -> (&str, usize) {
+    let (start, end) = match range {
+        Some(range) => range,
+        None => return (text, 0),
+    };
+    let starts = line_starts(text);
+    let line_count = starts.len() - 1;
+    let first = start.saturating_sub(1).min(line_count);
+    let last = end.saturating_sub(1).clamp(first, line_count);
+    (&text[starts[first]..starts[last]], first)
+}
+
+/// Shift line numbers of hunks diffed from line ranges to the inputs' numbering
+fn offset_line_numbers(hunks: &mut [DiffHunk], old_skipped: usize, new_skipped: usize) {
+    for hunk in hunks {
+        hunk.old_start += old_skipped;
+        hunk.new_start += new_skipped;
+        rewrite_header_range(hunk);
+        for change in &mut hunk.changes {
+            change.old_line_number = change.old_line_number.map(|n| n + old_skipped);
+            change.new_line_number = change.new_line_number.map(|n| n + new_skipped);
+            change.moved_from = change.moved_from.map(|n| n + old_skipped);
+            change.moved_to = change.moved_to.map(|n| n + new_skipped);
+        }
+    }
+}
+
+/// Trim every line, keeping blank lines so line numbers still match
+fn normalize_whitespace(text: &str) -> String {
+    text.split('\n').map(|line| line.trim()).collect::<Vec<_>>().join("\n")
+}
+
+/// Tracks runs of unchanged lines as the diff produces changes
+#[derive(Default)]
+struct UnchangedRuns {
+    current: usize,
+    longest: usize,
+}
+
+impl UnchangedRuns {
+    fn record(&mut self, change_type: ChangeType) {
+        if change_type == ChangeType::Unchanged {
+            self.current += 1;
+            self.longest = self.longest.max(self.current);
+        } else {
+            self.current = 0;
+        }
+    }
+
+    fn longest(&self) -> usize {
+        self.longest
+    }
+}
+
+/// Groups changes into hunks as they are produced
+///
+/// Only the hunk being built and up to `context_lines` of pending context
+/// are held; unchanged lines between hunks are counted, not stored.
+struct HunkBuilder<'a> {
+    old_lines: &'a [&'a str],
+    new_lines: &'a [&'a str],
+    options: &'a DiffOptions,
+    /// Finds the string literals word spans keep whole
+    strings: Option<&'a SyntaxHighlighter>,
+    hunks: Vec<DiffHunk>,
+    current_hunk: Option<DiffHunk>,
+    /// Unchanged lines since the last change
+    unchanged_run: usize,
+    /// The latest unchanged lines not in a hunk, at most `context_lines` of
+    /// them, as leading context for the next change
+    pending_context: VecDeque<(usize, usize)>,
+    /// Finds enclosing signatures, when `include_enclosing_signature` is set
+    signatures: Option<SemanticAnalyzer>,
+    /// Old line index after the last change; lines from here on are unchanged
+    unchanged_from: usize,
+}
+
+impl<'a> HunkBuilder<'a> {
+    fn new(
+        old_lines: &'a [&'a str],
+        new_lines: &'a [&'a str],
+        options: &'a DiffOptions,
+        strings: Option<&'a SyntaxHighlighter>,
+    ) -> Self {
+        Self {
+            old_lines,
+            new_lines,
+            options,
+            strings,
+            hunks: Vec::new(),
+            current_hunk: None,
+            unchanged_run: 0,
+            pending_context: VecDeque::with_capacity(options.context_lines),
+            signatures: options
+                .include_enclosing_signature
+                .then(|| SemanticAnalyzer::new(options.language.as_deref())),
+            unchanged_from: 0,
+        }
+    }
+
+    /// Add the next change in diff order
+    ///
+    /// Up to `context_lines` unchanged lines are kept on each side of a change,
+    /// and changes at most twice that far apart share a hunk, with every line
+    /// between them.
+    fn push(&mut self, change_type: ChangeType, old_idx: usize, new_idx: usize) {
+        let context_lines = self.options.context_lines;
+
+        if change_type == ChangeType::Unchanged {
+            self.unchanged_run += 1;
+            if self.current_hunk.is_some() {
+                if self.unchanged_run <= context_lines {
+                    self.push_line(ChangeType::Unchanged, old_idx, new_idx);
+                    return;
+                }
+                // Too far from the last change for the next one to share its hunk
+                if self.unchanged_run > context_lines * 2 {
+                    self.close_hunk();
+                }
+            }
+            if context_lines > 0 {
+                if self.pending_context.len() == context_lines {
+                    self.pending_context.pop_front();
+                }
+                self.pending_context.push_back((old_idx, new_idx));
+            }
+            return;
+        }
+
+        self.unchanged_run = 0;
+        if self.current_hunk.is_none() {
+            let (first_old, first_new) = self.pending_context.front().copied().unwrap_or((old_idx, new_idx));
+            let mut hunk = create_new_hunk(first_old, first_new);
+            hunk.changes.extend(self.enclosing_signature(first_old, first_new));
+            self.current_hunk = Some(hunk);
+        }
+        while let Some((context_old, context_new)) = self.pending_context.pop_front() {
+            self.push_line(ChangeType::Unchanged, context_old, context_new);
+        }
+        self.push_line(change_type, old_idx, new_idx);
+
+        self.unchanged_from = match change_type {
+            ChangeType::Added => old_idx,
+            _ => old_idx + 1,
+        };
+    }
+
+    /// Append a line to the hunk being built, counting it in the hunk's ranges
+    fn push_line(&mut self, change_type: ChangeType, old_idx: usize, new_idx: usize) {
+        let change = line_change(
+            change_type,
+            old_idx,
+            new_idx,
+            self.old_lines,
+            self.new_lines,
+            self.options,
+            self.strings,
+        );
+        if let Some(hunk) = self.current_hunk.as_mut() {
+            push_to_hunk(hunk, change);
+        }
+    }
+
+    fn close_hunk(&mut self) {
+        if let Some(mut hunk) = self.current_hunk.take() {
+            finish_hunk_range(&mut hunk);
+            self.hunks.push(hunk);
+        }
+    }
+
+    /// The signature line enclosing a hunk starting at `old_idx`, as a context
+    /// change
+    ///
+    /// Only a signature in the unchanged run before the hunk qualifies, so
+    /// its new line number follows from the hunk's.
+    fn enclosing_signature(&self, old_idx: usize, new_idx: usize) -> Option<DiffChange> {
+        let signature_idx = self
+            .signatures
+            .as_ref()?
+            .enclosing_entity_line(self.old_lines, old_idx)
+            .filter(|&idx| idx >= self.unchanged_from)?;
+        let new_signature_idx = new_idx - (old_idx - signature_idx);
+        let content = self.old_lines[signature_idx];
+
+        Some(DiffChange {
+            semantic_info: Some(SemanticInfo {
+                entity_type: "enclosing_signature".to_string(),
+                entity_name: Some(content.trim().to_string()),
+                scope: None,
+                importance: 1.0,
+            }),
+            ..DiffChange::new(
+                ChangeType::Unchanged,
+                self.options.line_numbers.then_some(signature_idx + 1),
+                self.options.line_numbers.then_some(new_signature_idx + 1),
+                content.to_string(),
+            )
+        })
+    }
+
+    fn finish(mut self) -> Vec<DiffHunk> {
+        self.close_hunk();
+        self.hunks
+    }
+}
+
+/// The change for one line pair as Myers reports it
+fn line_change(
+    change_type: ChangeType,
+    old_idx: usize,
+    new_idx: usize,
+    old_lines: &[&str],
+    new_lines: &[&str],
+    options: &DiffOptions,
+    strings: Option<&SyntaxHighlighter>,
+) -> DiffChange {
+    let old_line = old_lines.get(old_idx).copied().unwrap_or("");
+    let new_line = new_lines.get(new_idx).copied().unwrap_or("");
+    let content = match change_type {
+        ChangeType::Removed => old_line,
+        _ => new_line,
+    };
+
+    let old_line_number = (options.line_numbers && change_type != ChangeType::Added).then_some(old_idx + 1);
+    let new_line_number = (options.line_numbers && change_type != ChangeType::Removed).then_some(new_idx + 1);
+
+    DiffChange {
+        whitespace_markers: markers_for(change_type, old_line, new_line, options),
+        intra_line_spans: intra_line_spans_for(change_type, old_line, new_line, options, strings),
+        whitespace_only: is_whitespace_only(change_type, old_line, new_line, options),
+        ..DiffChange::new(change_type, old_line_number, new_line_number, content.to_string())
+    }
+}
+
+/// Create a single hunk spanning every line of both files
+fn create_full_hunk(
+    changes: Vec<(ChangeType, usize, usize)>,
+    old_lines: &[&str],
+    new_lines: &[&str],
+    options: &DiffOptions,
+    strings: Option<&SyntaxHighlighter>,
+) -> Vec<DiffHunk> {
+    if changes.is_empty() {
+        return Vec::new();
+    }
+
+    let changes = changes
+        .into_iter()
+        .map(|(change_type, old_idx, new_idx)| {
+            line_change(change_type, old_idx, new_idx, old_lines, new_lines, options, strings)
+        })
+        .collect();
+
+    vec![DiffHunk {
+        old_start: 1,
+        old_lines: old_lines.len(),
+        new_start: 1,
+        new_lines: new_lines.len(),
+        changes,
+        header: format!("@@ -1,{} +1,{} @@", old_lines.len(), new_lines.len()),
+        collapsed_by_default: false,
+    }]
+}
+
+/// Changed words or characters of a modified line, when enabled
+fn intra_line_spans_for(
+    change_type: ChangeType,
+    old_line: &str,
+    new_line: &str,
+    options: &DiffOptions,
+    strings: Option<&SyntaxHighlighter>,
+) -> Option<Vec<WordSpan>> {
+    if change_type != ChangeType::Modified {
+        return None;
+    }
+    match options.intra_line {
+        IntraLineMode::None => None,
+        IntraLineMode::Word => Some(diff_words_with(old_line, new_line, strings, &options.word_boundary)),
+        IntraLineMode::Char => Some(diff_chars(old_line, new_line)),
+    }
+}
+
+/// Whether a modified line only changed whitespace at its ends, when flagging is enabled
+fn is_whitespace_only(change_type: ChangeType, old_line: &str, new_line: &str, options: &DiffOptions) -> bool {
+    options.flag_whitespace_only && change_type == ChangeType::Modified && old_line.trim() == new_line.trim()
+}
+
+/// Whitespace markers for a change, when enabled and there is whitespace worth showing
+///
+/// A modified line is marked only inside the span that differs from the old
+/// line; an added or removed line only when it is entirely whitespace.
+fn markers_for(
+    change_type: ChangeType,
+    old_line: &str,
+    new_line: &str,
+    options: &DiffOptions,
+) -> Option<Vec<(usize, WsKind)>> {
+    if !options.mark_whitespace || options.ignore_whitespace {
+        return None;
+    }
+
+    let (line, range) = match change_type {
+        ChangeType::Modified => (new_line, changed_span(old_line, new_line)),
+        ChangeType::Added if new_line.trim().is_empty() => (new_line, 0..new_line.len()),
+        ChangeType::Removed if old_line.trim().is_empty() => (old_line, 0..old_line.len()),
+        _ => return None,
+    };
+
+    let markers: Vec<(usize, WsKind)> = line[range.clone()]
+        .char_indices()
+        .filter_map(|(i, c)| match c {
+            '\t' => Some((range.start + i, WsKind::Tab)),
+            ' ' => Some((range.start + i, WsKind::Space)),
+            _ => None,
+        })
+        .collect();
+
+    (!markers.is_empty()).then_some(markers)
+}
+
+/// Byte range of `new_line` left after trimming the prefix and suffix it shares with `old_line`
+fn changed_span(old_line: &str, new_line: &str) -> Range<usize> {
+    let prefix: usize = old_line
+        .chars()
+        .zip(new_line.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum();
+
+    let suffix: usize = old_line[prefix..]
+        .chars()
+        .rev()
+        .zip(new_line[prefix..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c.len_utf8())
+        .sum();
+
+    prefix..new_line.len() - suffix
+}
+
+/// Create an empty hunk whose first line is at `old_idx` and `new_idx`;
+/// lines are added with `push_to_hunk` and the range settled with `finish_hunk_range`
+fn create_new_hunk(old_idx: usize, new_idx: usize) -> DiffHunk {
+    DiffHunk {
+        old_start: old_idx + 1,
+        old_lines: 0,
+        new_start: new_idx + 1,
+        new_lines: 0,
+        changes: Vec::new(),
+        header: String::new(),
+        collapsed_by_default: false,
+    }
+}
+
+/// Append a change to a hunk, counting it in the sides it covers
+fn push_to_hunk(hunk: &mut DiffHunk, change: DiffChange) {
+    hunk.old_lines += usize::from(change.change_type != ChangeType::Added);
+    hunk.new_lines += usize::from(change.change_type != ChangeType::Removed);
+    hunk.changes.push(change);
+}
+
+/// Point an empty side of a new hunk at the line before it, as unified diffs
+/// do, and write the header
+fn finish_hunk_range(hunk: &mut DiffHunk) {
+    if hunk.old_lines == 0 {
+        hunk.old_start -= 1;
+    }
+    if hunk.new_lines == 0 {
+        hunk.new_start -= 1;
+    }
+    rewrite_header_range(hunk);
+}
+
+/// 1-based lines a hunk side covers, with an exclusive end; an empty side's
+/// start names the line before it
+pub(crate) fn line_span(start: usize, count: usize) -> Range<usize> {
+    let first = if count == 0 { start + 1 } else { start };
+    first..first + count
+}
+
+/// Start and count of a hunk side covering `span`, the inverse of `line_span`
+fn span_range(span: Range<usize>) -> (usize, usize) {
+    let count = span.len();
+    (if count == 0 { span.start - 1 } else { span.start }, count)
+}
+
+/// Merge hunks whose line ranges, context included, touch or overlap
+///
+/// Hunks built in one pass are already split this way, but hunks diffed in
+/// separate pieces (streaming windows) can end exactly where the next begins,
+/// or share context lines, which are kept once.
+pub(crate) fn merge_adjacent_hunks(hunks: Vec<DiffHunk>) -> Vec<DiffHunk> {
+    let mut merged: Vec<DiffHunk> = Vec::with_capacity(hunks.len());
+
+    for hunk in hunks {
+        let prev = match merged.last_mut() {
+            Some(prev) => prev,
+            None => {
+                merged.push(hunk);
+                continue;
+            }
+        };
+        let (prev_old, prev_new) = (line_span(prev.old_start, prev.old_lines), line_span(prev.new_start, prev.new_lines));
+        let (next_old, next_new) = (line_span(hunk.old_start, hunk.old_lines), line_span(hunk.new_start, hunk.new_lines));
+        if next_new.start > prev_new.end {
+            merged.push(hunk);
+            continue;
+        }
+
+        // The earlier hunk already shows what a signature heading this one
+        // would, and the lines both hunks show as context
+        let mut shared = prev_new.end - next_new.start;
+        let changes = hunk.changes.into_iter().skip_while(|change| {
+            let is_signature = change
+                .semantic_info
+                .as_ref()
+                .is_some_and(|info| info.entity_type == "enclosing_signature");
+            let is_shared = shared > 0 && change.change_type == ChangeType::Unchanged;
+            shared -= usize::from(is_shared && !is_signature);
+            is_signature || is_shared
+        });
+        prev.changes.extend(changes);
+
+        (prev.old_start, prev.old_lines) = span_range(prev_old.start.min(next_old.start)..prev_old.end.max(next_old.end));
+        (prev.new_start, prev.new_lines) = span_range(prev_new.start..prev_new.end.max(next_new.end));
+        rewrite_header_range(prev);
+    }
+
+    merged
+}
+
+/// Regenerate the `@@` range of a hunk header from its fields, keeping any
+/// enclosing entity appended after it
+pub(crate) fn rewrite_header_range(hunk: &mut DiffHunk) {
+    let suffix = hunk.header.splitn(3, "@@").nth(2).unwrap_or("").to_string();
+    hunk.header = format!(
+        "@@ -{},{} +{},{} @@{}",
+        hunk.old_start, hunk.old_lines, hunk.new_start, hunk.new_lines, suffix
+    );
+}
+
+/// Fewest equal lines a removed and an added block must share to be a move
+const MIN_MOVED_LINES: usize = 3;
+
+/// Largest product of removed and added lines searched for moves
+const MAX_MOVE_CELLS: usize = 4 * 1024 * 1024;
+
+/// Re-tag removed blocks that reappear among the added lines as `Moved`,
+/// pointing each side at the other's line number
+///
+/// Each removed block is matched with the unclaimed added block sharing the
+/// most equal lines at one alignment. Only those lines move, so a line edited
+/// on the way stays a removal and an addition.
+fn mark_moved_blocks(hunks: &mut [DiffHunk]) {
+    let removed = change_runs(hunks, ChangeType::Removed);
+    let added = change_runs(hunks, ChangeType::Added);
+    let removed_lines: usize = removed.iter().map(Vec::len).sum();
+    let added_lines: usize = added.iter().map(Vec::len).sum();
+    if removed_lines.saturating_mul(added_lines) > MAX_MOVE_CELLS {
+        return;
+    }
+
+    let content = |(hunk, change): (usize, usize)| hunks[hunk].changes[change].content.as_str();
+
+    // Positions of each line within its added block, by hash
+    let block_index: Vec<HashMap<u64, Vec<usize>>> = added
+        .iter()
+        .map(|block| {
+            let mut index: HashMap<u64, Vec<usize>> = HashMap::new();
+            for (j, &change) in block.iter().enumerate() {
+                index.entry(line_hash(content(change))).or_default().push(j);
+            }
+            index
+        })
+        .collect();
+
+    let mut claimed = vec![false; added.len()];
+    let mut matches_by_shift = Vec::new();
+    let mut moves = Vec::new();
+    for run in &removed {
+        let run_hashes: Vec<u64> = run.iter().map(|&change| line_hash(content(change))).collect();
+
+        // The best added block, its alignment against the run, and how many
+        // lines match there
+        let mut best: Option<(usize, usize, usize)> = None;
+        for (block_idx, block) in added.iter().enumerate().filter(|&(i, _)| !claimed[i]) {
+            // Count the matching lines at every alignment of the block
+            // against the run, where run line i meets block line
+            // i + block.len() - 1 - shift
+            matches_by_shift.clear();
+            matches_by_shift.resize(run.len() + block.len() - 1, 0usize);
+            for (i, hash) in run_hashes.iter().enumerate() {
+                for &j in block_index[block_idx].get(hash).into_iter().flatten() {
+                    if content(run[i]) == content(block[j]) {
+                        matches_by_shift[i + block.len() - 1 - j] += 1;
+                    }
+                }
+            }
+            for (shift, &count) in matches_by_shift.iter().enumerate() {
+                if count >= MIN_MOVED_LINES && best.is_none_or(|(_, _, most)| count > most) {
+                    best = Some((block_idx, shift, count));
+                }
+            }
+        }
+        if let Some((block_idx, shift, _)) = best {
+            claimed[block_idx] = true;
+            let block = &added[block_idx];
+            moves.extend((0..run.len()).filter_map(|i| {
+                let j = (i + block.len() - 1).checked_sub(shift)?;
+                (j < block.len() && content(run[i]) == content(block[j])).then_some((run[i], block[j]))
+            }));
+        }
+    }
+
+    for ((old_hunk, old_change), (new_hunk, new_change)) in moves {
+        let old_line = hunks[old_hunk].changes[old_change].old_line_number;
+        let new_line = hunks[new_hunk].changes[new_change].new_line_number;
+
+        let source = &mut hunks[old_hunk].changes[old_change];
+        source.change_type = ChangeType::Moved;
+        source.moved_to = new_line;
+        let destination = &mut hunks[new_hunk].changes[new_change];
+        destination.change_type = ChangeType::Moved;
+        destination.moved_from = old_line;
+    }
+}
+
+/// Consecutive changes of `change_type` within each hunk, as (hunk, change)
+/// indices
+fn change_runs(hunks: &[DiffHunk], change_type: ChangeType) -> Vec<Vec<(usize, usize)>> {
+    let mut runs: Vec<Vec<(usize, usize)>> = Vec::new();
+    for (hunk_idx, hunk) in hunks.iter().enumerate() {
+        for (change_idx, change) in hunk.changes.iter().enumerate() {
+            if change.change_type != change_type {
+                continue;
+            }
+            match runs.last_mut() {
+                Some(run) if run.last() == Some(&(hunk_idx, change_idx.wrapping_sub(1))) => {
+                    run.push((hunk_idx, change_idx))
+                }
+                _ => runs.push(vec![(hunk_idx, change_idx)]),
+            }
+        }
+    }
+    runs
+}
+
+/// Replace lines that only changed indentation with `ReformatBlock` changes
+///
+/// Within each run of changes, the old and new lines are realigned ignoring
+/// leading whitespace. Two or more consecutive realigned pairs become one
+/// block; a lone pair stays a modified line, and lines that don't realign
+/// stay removed or added.
+fn collapse_reformat_blocks(
+    hunks: &mut [DiffHunk],
+    old_lines: &[&str],
+    new_lines: &[&str],
+    options: &DiffOptions,
+    strings: Option<&SyntaxHighlighter>,
+) {
+    let is_change = |change: &DiffChange| change.change_type != ChangeType::Unchanged;
+
+    for hunk in hunks {
+        let mut changes = Vec::with_capacity(hunk.changes.len());
+        for run in hunk.changes.chunk_by(|a, b| is_change(a) == is_change(b)) {
+            match reformat_run(run, old_lines, new_lines, options, strings) {
+                Some(rewritten) => changes.extend(rewritten),
+                None => changes.extend_from_slice(run),
+            }
+        }
+        hunk.changes = changes;
+    }
+}
+
+/// The run rewritten with reformat blocks, or None if it has none
+fn reformat_run(
+    run: &[DiffChange],
+    old_lines: &[&str],
+    new_lines: &[&str],
+    options: &DiffOptions,
+    strings: Option<&SyntaxHighlighter>,
+) -> Option<Vec<DiffChange>> {
+    if run[0].change_type == ChangeType::Unchanged {
+        return None;
+    }
+
+    let old_idxs: Vec<usize> = run
+        .iter()
+        .filter(|c| c.change_type != ChangeType::Added)
+        .map(|c| c.old_line_number.map(|n| n - 1))
+        .collect::<Option<_>>()?;
+    let new_idxs: Vec<usize> = run
+        .iter()
+        .filter(|c| c.change_type != ChangeType::Removed)
+        .map(|c| c.new_line_number.map(|n| n - 1))
+        .collect::<Option<_>>()?;
+
+    let old_keys: Vec<&str> = old_idxs.iter().map(|&i| old_lines[i].trim_start()).collect();
+    let new_keys: Vec<&str> = new_idxs.iter().map(|&i| new_lines[i].trim_start()).collect();
+    let aligned = MyersDiff::new(&old_keys, &new_keys).compute_diff();
+
+    let realigned = |k: usize| aligned.get(k).is_some_and(|c| c.0 == ChangeType::Unchanged);
+    if !(0..aligned.len()).any(|k| realigned(k) && realigned(k + 1)) {
+        return None;
+    }
+
+    let mut rewritten = Vec::new();
+    for group in aligned.chunk_by(|a, b| (a.0 == ChangeType::Unchanged) == (b.0 == ChangeType::Unchanged)) {
+        if group[0].0 == ChangeType::Unchanged && group.len() >= 2 {
+            let (old_idx, new_idx) = (old_idxs[group[0].1], new_idxs[group[0].2]);
+            let block: Vec<&str> = group.iter().map(|&(_, _, j)| new_lines[new_idxs[j]]).collect();
+            rewritten.push(DiffChange {
+                block_lines: block.len(),
+                ..DiffChange::new(
+                    ChangeType::ReformatBlock,
+                    options.line_numbers.then_some(old_idx + 1),
+                    options.line_numbers.then_some(new_idx + 1),
+                    block.join("\n"),
+                )
+            });
+            continue;
+        }
+
+        for &(change_type, i, j) in group {
+            let change_type = match change_type {
+                ChangeType::Unchanged => ChangeType::Modified,
+                other => other,
+            };
+            let old_idx = old_idxs.get(i).copied().unwrap_or(old_lines.len());
+            let new_idx = new_idxs.get(j).copied().unwrap_or(new_lines.len());
+            rewritten.push(line_change(change_type, old_idx, new_idx, old_lines, new_lines, options, strings));
+        }
+    }
+
+    Some(rewritten)
+}
+
+/// Append the enclosing function or type to each hunk header, like `git diff`
+///
+/// Only lines before the hunk's first old line are searched; a hunk with no
+/// old lines sits after `old_start`, so that line is searched too.
+fn add_header_context(hunks: &mut [DiffHunk], old_lines: &[&str], analyzer: &SemanticAnalyzer) {
+    for hunk in hunks {
+        let first_line = line_span(hunk.old_start, hunk.old_lines).start;
+        if let Some(entity) = analyzer.enclosing_entity(old_lines, first_line - 1) {
+            hunk.header = format!("{} {}", hunk.header, entity);
+        }
+    }
+}
+
+/// Hint which hunks can start collapsed: large ones and whitespace-only ones,
+/// unless they declare a structural entity
+///
+/// Modified lines count as whitespace-only when their old line, found by line
+/// number, differs only in whitespace; without line numbers they never do.
+fn suggest_collapsed(
+    hunks: &mut [DiffHunk],
+    old_lines: &[&str],
+    analyzer: Option<&SemanticAnalyzer>,
+    threshold: usize,
+) {
+    let without_whitespace = |line: &str| line.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+
+    for hunk in hunks {
+        let changes = || {
+            hunk.changes
+                .iter()
+                .filter(|change| change.change_type != ChangeType::Unchanged)
+        };
+        let declares_entity = analyzer.is_some_and(|analyzer| {
+            changes().any(|change| {
+                analyzer
+                    .extract_semantic_info(&change.content, &[])
+                    .is_some_and(|info| info.importance > 0.6)
+            })
+        });
+        let changed_lines: usize = changes().map(|change| change.block_lines.max(1)).sum();
+        let whitespace_only = changes().all(|change| match change.change_type {
+            ChangeType::Added | ChangeType::Removed | ChangeType::Moved => change.content.trim().is_empty(),
+            ChangeType::Modified => change
+                .old_line_number
+                .and_then(|number| old_lines.get(number - 1))
+                .is_some_and(|old| without_whitespace(old) == without_whitespace(&change.content)),
+            ChangeType::ReformatBlock | ChangeType::Unchanged => true,
+        });
+
+        hunk.collapsed_by_default = !declares_entity && (changed_lines >= threshold || whitespace_only);
+    }
+}
+
+/// Apply syntax highlighting to hunks, returning whether it was skipped
+/// because the language has no highlighter
+///
+/// A highlighter that fails is an error rather than a skip.
+fn apply_syntax_highlighting(
+    hunks: &mut [DiffHunk],
+    old_text: &str,
+    new_text: &str,
+    options: &DiffOptions,
+    highlighters: &mut HighlighterCache,
+) -> Result<bool, DiffError> {
+    let Some(lang) = options.language.as_deref() else {
+        return Ok(false);
+    };
+    let Some(highlighter) = highlighters.get(lang, options.class_name_map.as_ref()) else {
+        return Ok(true);
+    };
+
+    let mut old_file = CommentState::new(old_text);
+    let mut new_file = CommentState::new(new_text);
+    for hunk in hunks {
+        // Whether the old and new files are inside a block comment at this
+        // point of the hunk. A line found in its input starts from the state
+        // the lines above it leave, including ones outside the hunk or too
+        // long to highlight. Otherwise the state carries over from the line
+        // before, and a modified line's old side is taken to end where its
+        // new side does.
+        let (mut old_in_comment, mut new_in_comment) = (false, false);
+        for change in &mut hunk.changes {
+            let (in_comment, file, line_number) = match change.change_type {
+                ChangeType::Removed => (&mut old_in_comment, &mut old_file, change.old_line_number),
+                ChangeType::Moved if change.moved_to.is_some() => {
+                    (&mut old_in_comment, &mut old_file, change.old_line_number)
+                }
+                _ => (&mut new_in_comment, &mut new_file, change.new_line_number),
+            };
+            if let Some(state) = line_number.map(|n| file.before(n, highlighter)).transpose()?.flatten() {
+                *in_comment = state;
+            }
+            // Very long lines (minified code) are slow to scan and not worth coloring
+            if !change.content.is_empty() && change.content.len() <= options.max_highlight_line_length {
+                let (tokens, ends_in_comment) = highlighter.try_highlight_continuing(&change.content, *in_comment)?;
+                *in_comment = ends_in_comment;
+                change.tokens = Some(
+                    tokens
+                        .into_iter()
+                        .map(|(token_type, class_name, start, end)| SyntaxToken { start, end, token_type, class_name })
+                        .collect(),
+                );
+            }
+            if matches!(change.change_type, ChangeType::Unchanged | ChangeType::Modified) {
+                old_in_comment = new_in_comment;
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Whether an input is inside a block comment, followed line by line
+struct CommentState<'a> {
+    text: &'a str,
+    lines: std::str::Lines<'a>,
+    /// 1-based number of the line `lines` yields next
+    next_line: usize,
+    in_comment: bool,
+}
+
+impl<'a> CommentState<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            lines: text.lines(),
+            next_line: 1,
+            in_comment: false,
+        }
+    }
+
+    /// Whether line `line_number` starts inside a block comment, or `None`
+    /// past the end of the input
+    ///
+    /// Reads on from the line asked for last, so asking in order reads the
+    /// input once.
+    fn before(&mut self, line_number: usize, highlighter: &SyntaxHighlighter) -> Result<Option<bool>, DiffError> {
+        if line_number < self.next_line {
+            *self = Self::new(self.text);
+        }
+        while self.next_line < line_number {
+            let Some(line) = self.lines.next() else {
+                return Ok(None);
+            };
+            self.in_comment = highlighter.ends_in_block_comment(line, self.in_comment)?;
+            self.next_line += 1;
+        }
+        Ok(Some(self.in_comment))
+    }
+}
+
+/// Fill in each change's byte ranges in the original inputs from its line numbers
+///
+/// Preprocessing other than whitespace normalization keeps lines one to one,
+/// so line numbers index the original lines directly.
+fn add_byte_ranges(hunks: &mut [DiffHunk], old_text: &str, new_text: &str) {
+    let old_starts = line_starts(old_text);
+    let new_starts = line_starts(new_text);
+    // A reformat block spans all of its lines
+    let range_of = |starts: &[usize], line_number: usize, lines: usize| {
+        let end = line_number - 1 + lines;
+        (line_number >= 1 && end < starts.len()).then(|| starts[line_number - 1]..starts[end])
+    };
+
+    for change in hunks.iter_mut().flat_map(|hunk| &mut hunk.changes) {
+        let lines = change.block_lines.max(1);
+        change.old_byte_range = change.old_line_number.and_then(|n| range_of(&old_starts, n, lines));
+        change.new_byte_range = change.new_line_number.and_then(|n| range_of(&new_starts, n, lines));
+    }
+}
+
+/// Tag changes on lines inside merge conflict regions, returning whether
+/// either input has any
+fn mark_conflict_regions(hunks: &mut [DiffHunk], old_text: &str, new_text: &str, options: &DiffOptions) -> bool {
+    let old_conflicts = conflict_lines(old_text);
+    let new_conflicts = conflict_lines(new_text);
+    let has_conflicts = old_conflicts.contains(&true) || new_conflicts.contains(&true);
+    if !has_conflicts || !options.line_numbers {
+        return has_conflicts;
+    }
+
+    let in_region = |conflicts: &[bool], line_number: Option<usize>| {
+        line_number.is_some_and(|n| conflicts.get(n.wrapping_sub(1)).copied().unwrap_or(false))
+    };
+    for change in hunks.iter_mut().flat_map(|hunk| &mut hunk.changes) {
+        change.conflict_region = in_region(&old_conflicts, change.old_line_number)
+            || in_region(&new_conflicts, change.new_line_number);
+    }
+    true
+}
+
+/// Whether each line of `text` lies in a complete merge conflict region,
+/// markers included; a `<<<<<<<` without its `>>>>>>>` isn't one
+fn conflict_lines(text: &str) -> Vec<bool> {
+    let is_marker = |line: &str, marker: &str| {
+        line.strip_prefix(marker)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+    };
+
+    let mut conflicts = Vec::new();
+    let mut region_start = None;
+    for (index, line) in text.lines().enumerate() {
+        conflicts.push(false);
+        if is_marker(line, "<<<<<<<") {
+            region_start = Some(index);
+        } else if is_marker(line, ">>>>>>>") {
+            if let Some(start) = region_start.take() {
+                conflicts[start..=index].fill(true);
+            }
+        }
+    }
+    conflicts
+}
+
+/// Byte offset at which each line of `text` starts, followed by the end of the text
+fn line_starts(text: &str) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut end = 0;
+    for line in text.split_inclusive('\n') {
+        end += line.len();
+        starts.push(end);
+    }
+    starts
+}
+
+/// Encode change content as requested, after highlighting so tokens are
+/// found in the raw text, then shift token and marker offsets to match
+fn encode_content(hunks: &mut [DiffHunk], encoding: ContentEncoding) {
+    if encoding == ContentEncoding::Raw {
+        return;
+    }
+
+    for change in hunks.iter_mut().flat_map(|hunk| &mut hunk.changes) {
+        let (escaped, offsets) = escape_html(&change.content);
+        change.content = escaped;
+
+        for token in change.tokens.iter_mut().flatten() {
+            token.start = offsets[token.start];
+            token.end = offsets[token.end];
+        }
+        for (offset, _) in change.whitespace_markers.iter_mut().flatten() {
+            *offset = offsets[*offset];
+        }
+        for span in change.intra_line_spans.iter_mut().flatten() {
+            if span.change_type == ChangeType::Added {
+                span.start = offsets[span.start];
+                span.end = offsets[span.end];
+            }
+        }
+    }
+}
+
+/// Escape text for HTML, returning the escaped text and, for every byte
+/// offset of the input (and its end), the matching offset in the output
+pub(crate) fn escape_html(text: &str) -> (String, Vec<usize>) {
+    let mut escaped = String::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len() + 1);
+
+    for c in text.chars() {
+        let start = escaped.len();
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+        // Offsets inside a multi-byte character map to its start
+        offsets.extend(std::iter::repeat_n(start, c.len_utf8()));
+    }
+    offsets.push(escaped.len());
+
+    (escaped, offsets)
+}
+
+/// Calculate diff statistics
+fn calculate_stats(
+    hunks: &[DiffHunk],
+    old_total: usize,
+    new_total: usize,
+    options: &DiffOptions,
+) -> DiffStats {
+    let mut added_lines = 0;
+    let mut removed_lines = 0;
+    let mut modified_lines = 0;
+    let mut whitespace_changed_lines = 0;
+
+    for hunk in hunks {
+        for change in &hunk.changes {
+            whitespace_changed_lines += usize::from(change.whitespace_only);
+            match change.change_type {
+                ChangeType::Added => added_lines += 1,
+                ChangeType::Removed => removed_lines += 1,
+                ChangeType::Modified => modified_lines += 1,
+                ChangeType::ReformatBlock => modified_lines += change.block_lines,
+                ChangeType::Moved if change.moved_to.is_some() => removed_lines += 1,
+                ChangeType::Moved => added_lines += 1,
+                ChangeType::Unchanged => {}
+            }
+        }
+    }
+
+    let mut stats = DiffStats::from_counts(
+        old_total.max(new_total),
+        added_lines,
+        removed_lines,
+        modified_lines,
+        options,
+    );
+    stats.whitespace_changed_lines = whitespace_changed_lines;
+    stats
+}
+
+/// Language for `options.filename` when no language is set and the
+/// extension is one we know
+fn filename_language(options: &DiffOptions) -> Option<String> {
+    if options.language.is_some() {
+        return None;
     }
+    let filename = options.filename.as_deref()?;
+    let language = crate::syntax::detect_language_with(filename, "", &options.extension_languages);
+    (language != "text").then_some(language)
 }
 
 /// Detect language from file content
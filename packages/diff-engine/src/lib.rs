@@ -1,16 +1,423 @@
-use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
-use serde_json;
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+
+#[cfg(feature = "wee_alloc")]
+#[global_allocator]
+static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
+
+pub mod diff;
+pub mod merge;
+pub mod myers;
+pub mod notebook;
+pub mod patch;
+pub mod semantic;
+pub mod streaming;
+pub mod syntax;
+pub mod token_diff;
+pub mod utils;
+pub mod virtual_scroll;
+pub mod word_diff;
+
+use diff::{DiffOptions, DiffResult, DiffHunk, DiffChange, ChangeType, DuplicateBlock, SemanticInfo, compute_diff as compute_diff_internal, find_duplicate_blocks};
+use semantic::SemanticAnalyzer;
+use streaming::{StreamingConfig, StreamingDiff};
+use syntax::HighlighterCache;
+
+// Set panic hook for better error messages in wasm
+#[wasm_bindgen(start)]
+pub fn main() {
+    #[cfg(feature = "console_error_panic_hook")]
+    console_error_panic_hook::set_once();
+}
+
+/// Main entry point for computing diffs
+///
+/// Language tables and semantic patterns are statics shared by every engine
+/// in the same module instance, so a Web Worker pays for them once no matter
+/// how many engines it creates. An engine itself only holds options and
+/// highlighters cached per language and class name map; keep one per
+/// worker and switch it between jobs with `setOptions` or `resetOptions`
+/// rather than constructing a new one.
+#[wasm_bindgen]
+pub struct DiffEngine {
+    options: DiffOptions,
+    highlighters: RefCell<HighlighterCache>,
+    similarity: Option<js_sys::Function>,
+}
+
+#[wasm_bindgen]
+impl DiffEngine {
+    /// Create a new DiffEngine instance
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            options: DiffOptions::default(),
+            highlighters: RefCell::new(HighlighterCache::new()),
+            similarity: None,
+        }
+    }
+
+    /// Configure diff options
+    #[wasm_bindgen(js_name = setOptions)]
+    pub fn set_options(&mut self, options: JsValue) -> Result<(), JsValue> {
+        let options: DiffOptions = serde_wasm_bindgen::from_value(options)?;
+        self.options = options;
+        Ok(())
+    }
+
+    /// Return to the default options and drop any similarity callback
+    #[wasm_bindgen(js_name = resetOptions)]
+    pub fn reset_options(&mut self) {
+        self.options = DiffOptions::default();
+        self.similarity = None;
+    }
+
+    /// Map a custom file extension to a language for detection
+    ///
+    /// Applies to files named with `setFilename`. Stored in the options as
+    /// `extensionLanguages`, so `setOptions` and `resetOptions` replace it.
+    #[wasm_bindgen(js_name = registerExtension)]
+    pub fn register_extension(&mut self, extension: &str, language: &str) {
+        self.options.register_extension(extension, language);
+    }
+
+    /// Name of the file being diffed, whose extension picks the language
+    /// when none is set; `undefined` clears it
+    #[wasm_bindgen(js_name = setFilename)]
+    pub fn set_filename(&mut self, filename: Option<String>) {
+        self.options.filename = filename;
+    }
+
+    /// Set a `(oldLine, newLine) => number` callback deciding which removed and
+    /// added line pairs are modifications, or `undefined` to go back to
+    /// Levenshtein distance
+    ///
+    /// Pairs scoring above 0.5 are modifications. It is only called for a
+    /// removed line directly followed by an added one; throwing or returning
+    /// a non-number counts as 0.
+    #[wasm_bindgen(js_name = setSimilarity)]
+    pub fn set_similarity(&mut self, callback: Option<js_sys::Function>) {
+        self.similarity = callback;
+    }
+
+    /// Compute diff between two texts
+    #[wasm_bindgen(js_name = computeDiff)]
+    pub fn compute_diff(&self, old_text: &str, new_text: &str) -> Result<JsValue, JsValue> {
+        let mut highlighters = self.highlighters.borrow_mut();
+        let result = match &self.similarity {
+            Some(callback) => {
+                let similarity = |old_line: &str, new_line: &str| {
+                    callback
+                        .call2(&JsValue::NULL, &JsValue::from_str(old_line), &JsValue::from_str(new_line))
+                        .ok()
+                        .and_then(|score| score.as_f64())
+                        .unwrap_or(0.0) as f32
+                };
+                diff::compute_diff_with_similarity(
+                    old_text,
+                    new_text,
+                    &self.options,
+                    &mut highlighters,
+                    &similarity,
+                )
+            }
+            None => diff::compute_diff_cached(old_text, new_text, &self.options, &mut highlighters),
+        }
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        
+        serde_wasm_bindgen::to_value(&result).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Compute diff between two texts, returning the result as MessagePack
+    ///
+    /// Much cheaper to transfer and decode than `computeDiff` for large
+    /// diffs; the decoded object has the same shape.
+    #[wasm_bindgen(js_name = computeDiffBinary)]
+    pub fn compute_diff_binary(&self, old_text: &str, new_text: &str) -> Result<Vec<u8>, JsValue> {
+        let mut highlighters = self.highlighters.borrow_mut();
+        diff::compute_diff_cached(old_text, new_text, &self.options, &mut highlighters)
+            .and_then(|result| result.to_msgpack())
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Compute diff between two byte buffers, flagging lines with invalid UTF-8
+    #[wasm_bindgen(js_name = computeDiffBytes)]
+    pub fn compute_diff_bytes(&self, old_bytes: &[u8], new_bytes: &[u8]) -> Result<JsValue, JsValue> {
+        let result = diff::compute_diff_bytes(old_bytes, new_bytes, &self.options)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        serde_wasm_bindgen::to_value(&result).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Map each line to its counterpart in the other file, for scroll syncing
+    #[wasm_bindgen(js_name = lineMap)]
+    pub fn line_map(&self, old_text: &str, new_text: &str) -> Result<JsValue, JsValue> {
+        let result = diff::compute_diff(old_text, new_text, &self.options)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        let map = result.line_map(
+            diff::line_count(old_text, &self.options),
+            diff::line_count(new_text, &self.options),
+        );
+        serde_wasm_bindgen::to_value(&map).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Patch from the old to the new text that `git apply` accepts
+    #[wasm_bindgen(js_name = computeGitPatch)]
+    pub fn compute_git_patch(
+        &self,
+        old_text: &str,
+        new_text: &str,
+        old_path: &str,
+        new_path: &str,
+    ) -> Result<String, JsValue> {
+        let result = self.diff_for_patch(old_text, new_text)?;
+        Ok(diff::to_git_patch(&result, old_text, new_text, old_path, new_path, self.options.context_lines))
+    }
+
+    /// Plain unified diff from the old to the new text, with `context` lines
+    /// around each hunk
+    #[wasm_bindgen(js_name = computeUnifiedDiff)]
+    pub fn compute_unified_diff(
+        &self,
+        old_text: &str,
+        new_text: &str,
+        old_name: &str,
+        new_name: &str,
+        context: usize,
+    ) -> Result<String, JsValue> {
+        let result = self.diff_for_patch(old_text, new_text)?;
+        Ok(diff::to_unified_diff(&result, old_text, new_text, old_name, new_name, context))
+    }
 
-mod diff;
-mod myers;
-mod semantic;
-mod syntax;
-mod utils;
-mod streaming;
-mod virtual_scroll;
+    /// Fetch unchanged lines around a fold as context changes
+    #[wasm_bindgen(js_name = extractContext)]
+    pub fn extract_context(
+        &self,
+        old_text: &str,
+        new_text: &str,
+        old_start: usize,
+        new_start: usize,
+        count: usize,
+    ) -> Result<JsValue, JsValue> {
+        let context = diff::extract_context(
+            old_text,
+            new_text,
+            old_start..old_start + count,
+            new_start..new_start + count,
+        );
+        serde_wasm_bindgen::to_value(&context).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Diff two Jupyter notebooks cell by cell
+    #[wasm_bindgen(js_name = diffNotebooks)]
+    pub fn diff_notebooks(&self, old_json: &str, new_json: &str) -> Result<JsValue, JsValue> {
+        let result = notebook::diff_notebooks(old_json, new_json, &self.options)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
-use diff::{DiffOptions, DiffResult, DiffStats, DiffHunk, DiffChange, ChangeType, compute_diff as compute_diff_internal};
+        serde_wasm_bindgen::to_value(&result).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Change density across the file in `buckets` spans, for a minimap
+    #[wasm_bindgen(js_name = computeHeatmap)]
+    pub fn compute_heatmap(&self, old_text: &str, new_text: &str, buckets: usize) -> Result<Vec<f32>, JsValue> {
+        diff::compute_heatmap(old_text, new_text, buckets, &self.options)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Create a streaming diff processor for large files
+    #[wasm_bindgen(js_name = createStreamingDiff)]
+    pub fn create_streaming_diff(&self) -> StreamingDiffProcessor {
+        StreamingDiffProcessor::new(self.options.clone(), StreamingConfig::default())
+    }
+
+    /// Create a streaming diff processor with custom window sizes
+    #[wasm_bindgen(js_name = createStreamingDiffWithConfig)]
+    pub fn create_streaming_diff_with_config(&self, config: JsValue) -> Result<StreamingDiffProcessor, JsValue> {
+        let config: StreamingConfig = serde_wasm_bindgen::from_value(config)?;
+        Ok(StreamingDiffProcessor::new(self.options.clone(), config))
+    }
+
+    /// Get supported languages for syntax highlighting
+    #[wasm_bindgen(js_name = getSupportedLanguages)]
+    pub fn get_supported_languages(&self) -> Result<JsValue, JsValue> {
+        let languages = syntax::get_supported_languages();
+        serde_wasm_bindgen::to_value(&languages).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// JSON Schema for the results returned by `computeDiff`
+    #[wasm_bindgen(js_name = resultSchema)]
+    pub fn result_schema(&self) -> Result<JsValue, JsValue> {
+        let schema = diff::result_schema();
+        schema
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Precompile language tables and semantic patterns ahead of the first diff
+    #[wasm_bindgen(js_name = warmup)]
+    pub fn warmup(&self) {
+        syntax::warmup();
+        semantic::warmup();
+    }
+
+    /// Precompile the highlighting rules for a single language
+    #[wasm_bindgen(js_name = warmupLanguage)]
+    pub fn warmup_language(&self, language: &str) -> bool {
+        self.warmup();
+        syntax::warmup_language(language)
+    }
+
+    /// Diff with every hunk kept, whatever the payload budget, for rendering as a patch
+    fn diff_for_patch(&self, old_text: &str, new_text: &str) -> Result<DiffResult, JsValue> {
+        let options = DiffOptions {
+            max_payload_bytes: None,
+            ..self.options.clone()
+        };
+        diff::compute_diff(old_text, new_text, &options).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+}
+
+impl Default for DiffEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Streaming diff processor for handling large files
+#[wasm_bindgen]
+pub struct StreamingDiffProcessor {
+    processor: StreamingDiff,
+}
+
+#[wasm_bindgen]
+impl StreamingDiffProcessor {
+    fn new(options: DiffOptions, config: StreamingConfig) -> Self {
+        Self {
+            processor: StreamingDiff::new_with_config(options, config),
+        }
+    }
+
+    /// Process a chunk of the old file
+    #[wasm_bindgen(js_name = addOldChunk)]
+    pub fn add_old_chunk(&mut self, chunk: &str) -> Result<(), JsValue> {
+        self.processor
+            .add_old_chunk(chunk)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Mark the old file as complete; new-file chunks may come before or after
+    #[wasm_bindgen(js_name = startNewFile)]
+    pub fn start_new_file(&mut self) -> Result<(), JsValue> {
+        self.processor
+            .start_new_file()
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Process a chunk of the new file
+    #[wasm_bindgen(js_name = addNewChunk)]
+    pub fn add_new_chunk(&mut self, chunk: &str) -> Result<(), JsValue> {
+        self.processor
+            .add_new_chunk(chunk)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Finalize and get the diff result
+    #[wasm_bindgen(js_name = finalize)]
+    pub fn finalize(&mut self) -> Result<JsValue, JsValue> {
+        let result = self.processor
+            .finalize()
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        
+        serde_wasm_bindgen::to_value(&result).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Get intermediate results for progressive rendering
+    #[wasm_bindgen(js_name = getIntermediateResult)]
+    pub fn get_intermediate_result(&self) -> Result<JsValue, JsValue> {
+        let result = self.processor.get_intermediate_result();
+        serde_wasm_bindgen::to_value(&result).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+}
+
+/// Virtual scroll manager for large diffs
+#[wasm_bindgen]
+pub struct VirtualScrollManager {
+    manager: virtual_scroll::VirtualScroll,
+}
+
+#[wasm_bindgen]
+impl VirtualScrollManager {
+    /// Create a new virtual scroll manager
+    #[wasm_bindgen(constructor)]
+    pub fn new(total_lines: usize, viewport_height: usize) -> Self {
+        Self {
+            manager: virtual_scroll::VirtualScroll::new(total_lines, viewport_height),
+        }
+    }
+
+    /// Create a virtual scroll manager with a custom line height and buffer size
+    #[wasm_bindgen(js_name = withConfig)]
+    pub fn with_config(
+        total_lines: usize,
+        viewport_height: usize,
+        line_height: f64,
+        buffer_size: usize,
+    ) -> Self {
+        Self {
+            manager: virtual_scroll::VirtualScroll::with_config(
+                total_lines,
+                viewport_height,
+                line_height,
+                buffer_size,
+            ),
+        }
+    }
+
+    /// Set the number of extra lines rendered around the viewport
+    #[wasm_bindgen(js_name = setBufferSize)]
+    pub fn set_buffer_size(&mut self, buffer_size: usize) {
+        self.manager.set_buffer_size(buffer_size);
+    }
+
+    /// Total scrollable height in pixels
+    #[wasm_bindgen(js_name = totalHeight)]
+    pub fn total_height(&self) -> f64 {
+        self.manager.total_height()
+    }
+
+    /// Update viewport position
+    #[wasm_bindgen(js_name = updateViewport)]
+    pub fn update_viewport(&mut self, scroll_top: f64, viewport_height: usize) -> Result<JsValue, JsValue> {
+        let visible_range = self.manager.update_viewport(scroll_top, viewport_height);
+        serde_wasm_bindgen::to_value(&visible_range).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Update viewport position, buffering further ahead while scrolling fast
+    #[wasm_bindgen(js_name = updateViewportWithVelocity)]
+    pub fn update_viewport_with_velocity(
+        &mut self,
+        scroll_top: f64,
+        viewport_height: usize,
+        velocity_px_per_frame: f64,
+    ) -> Result<JsValue, JsValue> {
+        let visible_range =
+            self.manager
+                .update_viewport_with_velocity(scroll_top, viewport_height, velocity_px_per_frame);
+        serde_wasm_bindgen::to_value(&visible_range).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Get visible line range
+    #[wasm_bindgen(js_name = getVisibleRange)]
+    pub fn get_visible_range(&self) -> Result<JsValue, JsValue> {
+        let range = self.manager.get_visible_range();
+        serde_wasm_bindgen::to_value(&range).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+}
+
+/// Shortest repeated run of lines reported as a duplicate block
+const DUPLICATE_BLOCK_MIN_LINES: usize = 5;
 
 #[wasm_bindgen]
 extern "C" {
@@ -48,6 +455,9 @@ pub struct DiffInsights {
     pub hunks: usize,
     pub change_intensity: Vec<f32>,
     pub semantic: Option<SemanticInsights>,
+    /// Blocks the new text repeats verbatim, likely an accidental paste
+    #[serde(default)]
+    pub duplicate_blocks: Vec<DuplicateBlock>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -58,6 +468,21 @@ pub struct SemanticInsights {
     pub imports_changed: usize,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplyPatchRequest {
+    pub original: String,
+    pub patch: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplyPatchResponse {
+    /// The patched text, or `None` if the patch didn't apply
+    pub text: Option<String>,
+    pub error: Option<String>,
+}
+
 #[wasm_bindgen]
 pub fn init() {
     console_error_panic_hook::set_once();
@@ -79,6 +504,7 @@ pub fn compute_diff(request_json: &str) -> String {
                     hunks: 0,
                     change_intensity: vec![],
                     semantic: None,
+                    duplicate_blocks: vec![],
                 },
                 error: Some(format!("Failed to parse request: {}", e)),
             }).unwrap_or_else(|_| r#"{"error":"Failed to serialize error response"}"#.to_string());
@@ -89,7 +515,8 @@ pub fn compute_diff(request_json: &str) -> String {
     
     match compute_diff_internal(&request.left, &request.right, &options) {
         Ok(result) => {
-            let insights = calculate_insights(&result);
+            let mut insights = calculate_insights(&result, &options, &request.left);
+            insights.duplicate_blocks = find_duplicate_blocks(&request.right, DUPLICATE_BLOCK_MIN_LINES);
             let response = ComputeDiffResponse {
                 hunks: result.hunks,
                 insights,
@@ -109,6 +536,7 @@ pub fn compute_diff(request_json: &str) -> String {
                     hunks: 0,
                     change_intensity: vec![],
                     semantic: None,
+                    duplicate_blocks: vec![],
                 },
                 error: Some(format!("Diff computation failed: {}", e)),
             }).unwrap_or_else(|_| r#"{"error":"Failed to serialize error response"}"#.to_string())
@@ -116,7 +544,7 @@ pub fn compute_diff(request_json: &str) -> String {
     }
 }
 
-fn calculate_insights(result: &DiffResult) -> DiffInsights {
+fn calculate_insights(result: &DiffResult, options: &DiffOptions, left: &str) -> DiffInsights {
     let mut additions = 0;
     let mut deletions = 0;
     let mut modifications = 0;
@@ -141,6 +569,19 @@ fn calculate_insights(result: &DiffResult) -> DiffInsights {
                     hunk_adds += 1;
                     hunk_dels += 1;
                 }
+                ChangeType::ReformatBlock => {
+                    modifications += change.block_lines;
+                    hunk_adds += change.block_lines;
+                    hunk_dels += change.block_lines;
+                }
+                ChangeType::Moved if change.moved_to.is_some() => {
+                    deletions += 1;
+                    hunk_dels += 1;
+                }
+                ChangeType::Moved => {
+                    additions += 1;
+                    hunk_adds += 1;
+                }
                 ChangeType::Unchanged => {}
             }
         }
@@ -159,8 +600,82 @@ fn calculate_insights(result: &DiffResult) -> DiffInsights {
         similarity: result.stats.similarity,
         hunks: result.hunks.len(),
         change_intensity,
-        semantic: None, // TODO: Implement semantic insights extraction
+        semantic: semantic_insights(result, options.language.as_deref(), left),
+        duplicate_blocks: vec![],
+    }
+}
+
+/// Functions the changed lines declare or drop, and how many changed lines
+/// are imports; needs a language to know what a declaration looks like
+///
+/// A modified line's old side is read from `left` by line number. A function
+/// declared on both sides (a changed signature) is neither added nor removed.
+fn semantic_insights(result: &DiffResult, language: Option<&str>, left: &str) -> Option<SemanticInsights> {
+    let analyzer = SemanticAnalyzer::new(Some(language?));
+    let old_lines: Vec<&str> = left.lines().collect();
+
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut imports_changed = 0;
+    for change in result.hunks.iter().flat_map(|hunk| &hunk.changes) {
+        let (old_line, new_line) = match change.change_type {
+            ChangeType::Added => (None, Some(change.content.as_str())),
+            ChangeType::Removed => (Some(change.content.as_str()), None),
+            ChangeType::Modified => (
+                change.old_line_number.and_then(|n| old_lines.get(n - 1)).copied(),
+                Some(change.content.as_str()),
+            ),
+            // A moved function is still declared
+            ChangeType::Unchanged | ChangeType::ReformatBlock | ChangeType::Moved => continue,
+        };
+        let old_info = old_line.and_then(|line| analyzer.extract_semantic_info(line, &[]));
+        let new_info = new_line.and_then(|line| analyzer.extract_semantic_info(line, &[]));
+
+        let is_import = |info: &Option<SemanticInfo>| {
+            info.as_ref().is_some_and(|info| matches!(info.entity_type.as_str(), "import" | "use"))
+        };
+        if is_import(&old_info) || is_import(&new_info) {
+            imports_changed += 1;
+        }
+
+        let function_name = |info: Option<SemanticInfo>| {
+            info.filter(|info| info.entity_type.ends_with("function"))
+                .and_then(|info| info.entity_name)
+        };
+        removed.extend(function_name(old_info));
+        added.extend(function_name(new_info));
     }
+
+    Some(SemanticInsights {
+        functions_added: added.iter().filter(|name| !removed.contains(name)).cloned().collect(),
+        functions_removed: removed.iter().filter(|name| !added.contains(name)).cloned().collect(),
+        imports_changed,
+    })
+}
+
+/// Apply a unified diff to `original`, failing if it was made against
+/// different text
+#[wasm_bindgen]
+pub fn apply_patch(request_json: &str) -> String {
+    let response = match serde_json::from_str::<ApplyPatchRequest>(request_json) {
+        Ok(request) => match patch::apply_patch(&request.original, &request.patch) {
+            Ok(text) => ApplyPatchResponse {
+                text: Some(text),
+                error: None,
+            },
+            Err(e) => ApplyPatchResponse {
+                text: None,
+                error: Some(format!("Patch application failed: {}", e)),
+            },
+        },
+        Err(e) => ApplyPatchResponse {
+            text: None,
+            error: Some(format!("Failed to parse request: {}", e)),
+        },
+    };
+
+    serde_json::to_string(&response)
+        .unwrap_or_else(|e| format!(r#"{{"error":"Failed to serialize response: {}"}}"#, e))
 }
 
 // Simple diff computation for fallback (when the main engine fails)
@@ -180,28 +695,24 @@ pub fn simple_diff(left: &str, right: &str) -> String {
     for i in 0..max_len {
         let change = if i < left_lines.len() && i < right_lines.len() {
             if left_lines[i] == right_lines[i] {
-                DiffChange {
-                    change_type: ChangeType::Unchanged,
-                    old_line_number: Some(i + 1),
-                    new_line_number: Some(i + 1),
-                    content: left_lines[i].to_string(),
-                    tokens: None,
-                    semantic_info: None,
-                }
+                DiffChange::new(
+                    ChangeType::Unchanged,
+                    Some(i + 1),
+                    Some(i + 1),
+                    left_lines[i].to_string(),
+                )
             } else {
                 if !in_hunk {
                     in_hunk = true;
                     hunk_start_old = i;
                     hunk_start_new = i;
                 }
-                DiffChange {
-                    change_type: ChangeType::Modified,
-                    old_line_number: Some(i + 1),
-                    new_line_number: Some(i + 1),
-                    content: format!("-{}\n+{}", left_lines[i], right_lines[i]),
-                    tokens: None,
-                    semantic_info: None,
-                }
+                DiffChange::new(
+                    ChangeType::Modified,
+                    Some(i + 1),
+                    Some(i + 1),
+                    format!("-{}\n+{}", left_lines[i], right_lines[i]),
+                )
             }
         } else if i < left_lines.len() {
             if !in_hunk {
@@ -209,28 +720,14 @@ pub fn simple_diff(left: &str, right: &str) -> String {
                 hunk_start_old = i;
                 hunk_start_new = right_lines.len();
             }
-            DiffChange {
-                change_type: ChangeType::Removed,
-                old_line_number: Some(i + 1),
-                new_line_number: None,
-                content: left_lines[i].to_string(),
-                tokens: None,
-                semantic_info: None,
-            }
+            DiffChange::new(ChangeType::Removed, Some(i + 1), None, left_lines[i].to_string())
         } else {
             if !in_hunk {
                 in_hunk = true;
                 hunk_start_old = left_lines.len();
                 hunk_start_new = i;
             }
-            DiffChange {
-                change_type: ChangeType::Added,
-                old_line_number: None,
-                new_line_number: Some(i + 1),
-                content: right_lines[i].to_string(),
-                tokens: None,
-                semantic_info: None,
-            }
+            DiffChange::new(ChangeType::Added, None, Some(i + 1), right_lines[i].to_string())
         };
         
         changes.push(change);
@@ -246,12 +743,14 @@ pub fn simple_diff(left: &str, right: &str) -> String {
                 header: format!("@@ -{},{} +{},{} @@", 
                     hunk_start_old + 1, i - hunk_start_old + 1,
                     hunk_start_new + 1, i - hunk_start_new + 1),
+                collapsed_by_default: false,
             });
             changes.clear();
             in_hunk = false;
         }
     }
     
+    let hunk_count = hunks.len();
     let response = ComputeDiffResponse {
         hunks,
         insights: DiffInsights {
@@ -259,9 +758,10 @@ pub fn simple_diff(left: &str, right: &str) -> String {
             deletions: 0,
             modifications: 0,
             similarity: 0.0,
-            hunks: hunks.len(),
+            hunks: hunk_count,
             change_intensity: vec![],
             semantic: None,
+            duplicate_blocks: vec![],
         },
         error: None,
     };
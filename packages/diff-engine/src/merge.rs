@@ -0,0 +1,178 @@
+use crate::diff::ChangeType;
+use crate::myers::MyersDiff;
+
+/// Which side of a three-way merge a change came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Ours,
+    Theirs,
+}
+
+/// A contiguous change against the base, in line indices
+#[derive(Debug, Clone, Copy)]
+struct SideHunk {
+    side: Side,
+    base_start: usize,
+    base_end: usize,
+    side_start: usize,
+    side_end: usize,
+}
+
+/// Merge `ours` and `theirs` against their common `base`, producing the
+/// `diff3` conflict format used by git's merge driver (`merge.conflictStyle=diff3`)
+pub fn diff3(base: &str, ours: &str, theirs: &str) -> String {
+    let base_lines: Vec<&str> = base.lines().collect();
+    let our_lines: Vec<&str> = ours.lines().collect();
+    let their_lines: Vec<&str> = theirs.lines().collect();
+
+    let mut hunks = side_hunks(&base_lines, &our_lines, Side::Ours);
+    hunks.extend(side_hunks(&base_lines, &their_lines, Side::Theirs));
+    hunks.sort_by_key(|h| (h.base_start, h.side == Side::Theirs));
+
+    let mut output = Vec::new();
+    let mut base_pos = 0;
+    let mut i = 0;
+
+    while i < hunks.len() {
+        // Grow the region while the next hunk overlaps or touches it
+        let region_start = hunks[i].base_start;
+        let mut region_end = hunks[i].base_end;
+        let mut j = i + 1;
+        while j < hunks.len() && hunks[j].base_start <= region_end {
+            region_end = region_end.max(hunks[j].base_end);
+            j += 1;
+        }
+        let region = &hunks[i..j];
+
+        output.extend_from_slice(&base_lines[base_pos..region_start]);
+
+        let base_part = &base_lines[region_start..region_end];
+        let our_part = side_region(region, Side::Ours, region_start, region_end, &our_lines);
+        let their_part = side_region(region, Side::Theirs, region_start, region_end, &their_lines);
+
+        match (our_part, their_part) {
+            (Some(ours), None) => output.extend_from_slice(ours),
+            (None, Some(theirs)) => output.extend_from_slice(theirs),
+            (Some(ours), Some(theirs)) if ours == theirs => output.extend_from_slice(ours),
+            (Some(ours), Some(theirs)) => {
+                output.push("<<<<<<< ours");
+                output.extend_from_slice(ours);
+                output.push("||||||| base");
+                output.extend_from_slice(base_part);
+                output.push("=======");
+                output.extend_from_slice(theirs);
+                output.push(">>>>>>> theirs");
+            }
+            (None, None) => output.extend_from_slice(base_part),
+        }
+
+        base_pos = region_end;
+        i = j;
+    }
+
+    output.extend_from_slice(&base_lines[base_pos..]);
+
+    output.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Collect the runs of changes between `base` and one side
+fn side_hunks(base: &[&str], other: &[&str], side: Side) -> Vec<SideHunk> {
+    let changes = MyersDiff::new(base, other).compute_diff();
+
+    let mut hunks = Vec::new();
+    let mut current: Option<SideHunk> = None;
+    let mut base_pos = 0;
+    let mut side_pos = 0;
+
+    for (change_type, _, _) in changes {
+        if change_type == ChangeType::Unchanged {
+            if let Some(hunk) = current.take() {
+                hunks.push(hunk);
+            }
+            base_pos += 1;
+            side_pos += 1;
+            continue;
+        }
+
+        let hunk = current.get_or_insert(SideHunk {
+            side,
+            base_start: base_pos,
+            base_end: base_pos,
+            side_start: side_pos,
+            side_end: side_pos,
+        });
+
+        match change_type {
+            ChangeType::Removed => base_pos += 1,
+            ChangeType::Added => side_pos += 1,
+            _ => {
+                base_pos += 1;
+                side_pos += 1;
+            }
+        }
+
+        hunk.base_end = base_pos;
+        hunk.side_end = side_pos;
+    }
+
+    if let Some(hunk) = current {
+        hunks.push(hunk);
+    }
+
+    hunks
+}
+
+/// Lines of one side covering the base region, or None if that side left it untouched
+fn side_region<'a>(
+    region: &[SideHunk],
+    side: Side,
+    region_start: usize,
+    region_end: usize,
+    lines: &'a [&'a str],
+) -> Option<&'a [&'a str]> {
+    let first = region.iter().find(|h| h.side == side)?;
+    let last = region.iter().rev().find(|h| h.side == side)?;
+
+    // Widen the side's span by the unchanged base lines the other side pulled in
+    let start = first.side_start - (first.base_start - region_start);
+    let end = last.side_end + (region_end - last.base_end);
+
+    Some(&lines[start..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_merge() {
+        let base = "a\nb\nc\nd\ne\n";
+        let ours = "a\nB\nc\nd\ne\n";
+        let theirs = "a\nb\nc\nd\nE\n";
+
+        let merged = diff3(base, ours, theirs);
+        assert_eq!(merged, "a\nB\nc\nd\nE\n");
+        assert!(!merged.contains("<<<<<<<"));
+    }
+
+    #[test]
+    fn test_conflict_includes_base_section() {
+        let base = "a\nb\nc\n";
+        let ours = "a\nours\nc\n";
+        let theirs = "a\ntheirs\nc\n";
+
+        let merged = diff3(base, ours, theirs);
+        assert_eq!(
+            merged,
+            "a\n<<<<<<< ours\nours\n||||||| base\nb\n=======\ntheirs\n>>>>>>> theirs\nc\n"
+        );
+    }
+
+    #[test]
+    fn test_identical_changes_merge_cleanly() {
+        let base = "a\nb\nc\n";
+        let both = "a\nx\nc\n";
+
+        assert_eq!(diff3(base, both, both), "a\nx\nc\n");
+    }
+}
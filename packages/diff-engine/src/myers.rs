@@ -1,11 +1,25 @@
-use crate::diff::ChangeType;
+use crate::diff::{AlignmentFallback, ChangeType};
+use std::cell::Cell;
 use std::cmp::{max, min};
-use std::collections::HashMap;
+
+/// Largest LCS table the fallback alignment will allocate
+const MAX_LCS_CELLS: usize = 4 * 1024 * 1024;
+
+/// Largest line pair, in byte products, compared by Levenshtein distance
+const MAX_LEVENSHTEIN_CELLS: usize = 4 * 1024 * 1024;
+
+/// Line similarity in `0.0..=1.0`, used to decide whether a removed line and
+/// the added line after it are one modified line
+pub type LineSimilarity<'a> = &'a dyn Fn(&str, &str) -> f32;
 
 /// Myers diff algorithm implementation
 pub struct MyersDiff<'a> {
     old_lines: &'a [&'a str],
     new_lines: &'a [&'a str],
+    similarity: Option<LineSimilarity<'a>>,
+    max_edits: Option<usize>,
+    pair_trimmed: bool,
+    fallback: Cell<Option<AlignmentFallback>>,
 }
 
 impl<'a> MyersDiff<'a> {
@@ -14,36 +28,203 @@ impl<'a> MyersDiff<'a> {
         Self {
             old_lines,
             new_lines,
+            similarity: None,
+            max_edits: None,
+            pair_trimmed: false,
+            fallback: Cell::new(None),
         }
     }
 
+    /// Judge modifications with `similarity` instead of Levenshtein distance
+    ///
+    /// It is only asked about a removed line directly followed by an added
+    /// one, so it runs at most once per removal.
+    pub fn with_similarity(mut self, similarity: LineSimilarity<'a>) -> Self {
+        self.similarity = Some(similarity);
+        self
+    }
+
+    /// Give up the search for the shortest script after `max_edits` removed
+    /// and added lines, and align the lines the fallback way instead
+    pub fn with_max_edits(mut self, max_edits: usize) -> Self {
+        self.max_edits = Some(max_edits);
+        self
+    }
+
+    /// Also count a removed and added line that are equal once trimmed as a
+    /// modification, however short they are
+    pub fn pair_trimmed(mut self) -> Self {
+        self.pair_trimmed = true;
+        self
+    }
+
+    /// How the last diff was aligned if the search gave up, or `None`
+    pub fn fallback(&self) -> Option<AlignmentFallback> {
+        self.fallback.get()
+    }
+
     /// Compute the diff using Myers algorithm
     pub fn compute_diff(&self) -> Vec<(ChangeType, usize, usize)> {
-        if self.old_lines.is_empty() && self.new_lines.is_empty() {
-            return vec![];
+        let mut changes = Vec::new();
+        self.for_each_change(|change_type, old_idx, new_idx| {
+            changes.push((change_type, old_idx, new_idx));
+        });
+        changes
+    }
+
+    /// Compute the diff, passing each change to `emit` in order instead of
+    /// collecting them
+    ///
+    /// Similar removed/added pairs are folded into modifications as they go by.
+    pub fn for_each_change(&self, mut emit: impl FnMut(ChangeType, usize, usize)) {
+        // A removed line held back in case the next change adds a similar one
+        let mut pending_removal: Option<(usize, usize)> = None;
+
+        self.for_each_edit(|change_type, old, new| {
+            if let Some((removed_old, removed_new)) = pending_removal.take() {
+                if change_type == ChangeType::Added && self.are_lines_similar(removed_old, new) {
+                    emit(ChangeType::Modified, removed_old, new);
+                    return;
+                }
+                emit(ChangeType::Removed, removed_old, removed_new);
+            }
+
+            if change_type == ChangeType::Removed {
+                pending_removal = Some((old, new));
+            } else {
+                emit(change_type, old, new);
+            }
+        });
+
+        if let Some((removed_old, removed_new)) = pending_removal {
+            emit(ChangeType::Removed, removed_old, removed_new);
         }
+    }
 
+    /// The raw edit script: every line kept, deleted or inserted, without
+    /// folding delete/insert pairs into modifications
+    ///
+    /// Useful for debugging alignment and for plain `+`/`-` renderers.
+    pub fn edit_script(&self) -> Vec<EditOp> {
+        let mut ops = Vec::new();
+        self.for_each_edit(|change_type, old_idx, new_idx| {
+            ops.push(match change_type {
+                ChangeType::Removed => EditOp::Delete(old_idx),
+                ChangeType::Added => EditOp::Insert(new_idx),
+                _ => EditOp::Keep(old_idx, new_idx),
+            });
+        });
+        ops
+    }
+
+    /// Walk the edit script in order, as unchanged, removed and added lines
+    fn for_each_edit(&self, mut emit: impl FnMut(ChangeType, usize, usize)) {
         if self.old_lines.is_empty() {
-            return self
-                .new_lines
-                .iter()
-                .enumerate()
-                .map(|(i, _)| (ChangeType::Added, 0, i))
-                .collect();
+            for i in 0..self.new_lines.len() {
+                emit(ChangeType::Added, 0, i);
+            }
+            return;
         }
 
         if self.new_lines.is_empty() {
-            return self
-                .old_lines
-                .iter()
-                .enumerate()
-                .map(|(i, _)| (ChangeType::Removed, i, 0))
-                .collect();
+            for i in 0..self.old_lines.len() {
+                emit(ChangeType::Removed, i, 0);
+            }
+            return;
         }
 
         // Run Myers algorithm
-        let ses = self.shortest_edit_script();
-        self.ses_to_changes(ses)
+        let ses = self.checked_ses(self.shortest_edit_script());
+        self.ses_to_edits(ses, &mut emit);
+    }
+
+    /// Fall back to a plain LCS alignment if the search produced no script,
+    /// recording the fallback for `fallback`
+    ///
+    /// Both inputs are non-empty here, so even identical ones yield diagonal
+    /// moves; an empty script means the search gave up, and converting it
+    /// as-is would report every line as removed and re-added.
+    fn checked_ses(&self, ses: Vec<SnakeMove>) -> Vec<SnakeMove> {
+        if !ses.is_empty() {
+            self.fallback.set(None);
+            return ses;
+        }
+
+        let n = self.old_lines.len();
+        let m = self.new_lines.len();
+        if (n + 1).saturating_mul(m + 1) > MAX_LCS_CELLS {
+            self.fallback.set(Some(AlignmentFallback::Unaligned));
+            return self.shared_ends_script();
+        }
+
+        self.fallback.set(Some(AlignmentFallback::Lcs));
+        self.lcs_edit_script()
+    }
+
+    /// Diagonal moves for the lines shared at the start and end only, for
+    /// inputs too large for the LCS table
+    fn shared_ends_script(&self) -> Vec<SnakeMove> {
+        let n = self.old_lines.len();
+        let m = self.new_lines.len();
+        let prefix = self
+            .old_lines
+            .iter()
+            .zip(self.new_lines)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = self.old_lines[prefix..]
+            .iter()
+            .rev()
+            .zip(self.new_lines[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let mut moves: Vec<SnakeMove> = (0..prefix).map(|i| SnakeMove::Diagonal(i, i)).collect();
+        if n - suffix > prefix {
+            moves.push(SnakeMove::Down(n - suffix - 1));
+        }
+        if m - suffix > prefix {
+            moves.push(SnakeMove::Right(m - suffix - 1));
+        }
+        moves.extend((1..=suffix).rev().map(|i| SnakeMove::Diagonal(n - i, m - i)));
+        moves
+    }
+
+    /// Edit script from a dynamic-programming LCS table, quadratic in memory
+    fn lcs_edit_script(&self) -> Vec<SnakeMove> {
+        let n = self.old_lines.len();
+        let m = self.new_lines.len();
+
+        // lcs[i * (m + 1) + j] is the LCS length of old[i..] and new[j..]
+        let width = m + 1;
+        let mut lcs = vec![0u32; (n + 1) * width];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i * width + j] = if self.old_lines[i] == self.new_lines[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+
+        let mut moves = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < n && j < m {
+            if self.old_lines[i] == self.new_lines[j] {
+                moves.push(SnakeMove::Diagonal(i, j));
+                i += 1;
+                j += 1;
+            } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+                moves.push(SnakeMove::Down(i));
+                i += 1;
+            } else {
+                moves.push(SnakeMove::Right(j));
+                j += 1;
+            }
+        }
+
+        moves
     }
 
     /// Find the shortest edit script using Myers algorithm
@@ -55,7 +236,7 @@ impl<'a> MyersDiff<'a> {
         let mut v = vec![0i32; 2 * max_d + 1];
         let mut trace = Vec::new();
 
-        for d in 0..=max_d {
+        for d in 0..=self.max_edits.map_or(max_d, |max_edits| max_edits.min(max_d)) {
             let mut v_snapshot = v.clone();
             
             for k in (-(d as i32)..=(d as i32)).step_by(2) {
@@ -70,8 +251,6 @@ impl<'a> MyersDiff<'a> {
                 let mut y = x - k;
 
                 // Extend the snake
-                let start_x = x;
-                let start_y = y;
                 while (x as usize) < n && (y as usize) < m && self.old_lines[x as usize] == self.new_lines[y as usize] {
                     x += 1;
                     y += 1;
@@ -101,6 +280,16 @@ impl<'a> MyersDiff<'a> {
         let mut y = m as i32;
 
         for (d, v) in trace.iter().rev() {
+            if *d == 0 {
+                // The first snake starts at the origin, so only diagonals remain
+                while x > 0 && y > 0 {
+                    x -= 1;
+                    y -= 1;
+                    moves.push(SnakeMove::Diagonal(x as usize, y as usize));
+                }
+                break;
+            }
+
             let k = x - y;
             let idx = (k + (n + m) as i32) as usize;
 
@@ -122,14 +311,10 @@ impl<'a> MyersDiff<'a> {
 
             if x > prev_x {
                 x -= 1;
-                moves.push(SnakeMove::Down(x as usize, y as usize));
+                moves.push(SnakeMove::Down(x as usize));
             } else if y > prev_y {
                 y -= 1;
-                moves.push(SnakeMove::Right(x as usize, y as usize));
-            }
-
-            if *d == 0 {
-                break;
+                moves.push(SnakeMove::Right(y as usize));
             }
         }
 
@@ -137,9 +322,8 @@ impl<'a> MyersDiff<'a> {
         moves
     }
 
-    /// Convert snake moves to change list
-    fn ses_to_changes(&self, moves: Vec<SnakeMove>) -> Vec<(ChangeType, usize, usize)> {
-        let mut changes = Vec::new();
+    /// Convert snake moves to per-line edits
+    fn ses_to_edits(&self, moves: Vec<SnakeMove>, push: &mut impl FnMut(ChangeType, usize, usize)) {
         let mut old_idx = 0;
         let mut new_idx = 0;
 
@@ -148,30 +332,30 @@ impl<'a> MyersDiff<'a> {
                 SnakeMove::Diagonal(x, y) => {
                     while old_idx < x || new_idx < y {
                         if old_idx < x && new_idx < y {
-                            changes.push((ChangeType::Unchanged, old_idx, new_idx));
+                            push(ChangeType::Unchanged, old_idx, new_idx);
                             old_idx += 1;
                             new_idx += 1;
                         } else if old_idx < x {
-                            changes.push((ChangeType::Removed, old_idx, new_idx));
+                            push(ChangeType::Removed, old_idx, new_idx);
                             old_idx += 1;
                         } else {
-                            changes.push((ChangeType::Added, old_idx, new_idx));
+                            push(ChangeType::Added, old_idx, new_idx);
                             new_idx += 1;
                         }
                     }
-                    changes.push((ChangeType::Unchanged, old_idx, new_idx));
+                    push(ChangeType::Unchanged, old_idx, new_idx);
                     old_idx += 1;
                     new_idx += 1;
                 }
-                SnakeMove::Down(x, _) => {
+                SnakeMove::Down(x) => {
                     while old_idx <= x {
-                        changes.push((ChangeType::Removed, old_idx, new_idx));
+                        push(ChangeType::Removed, old_idx, new_idx);
                         old_idx += 1;
                     }
                 }
-                SnakeMove::Right(_, y) => {
+                SnakeMove::Right(y) => {
                     while new_idx <= y {
-                        changes.push((ChangeType::Added, old_idx, new_idx));
+                        push(ChangeType::Added, old_idx, new_idx);
                         new_idx += 1;
                     }
                 }
@@ -180,45 +364,14 @@ impl<'a> MyersDiff<'a> {
 
         // Handle remaining lines
         while old_idx < self.old_lines.len() {
-            changes.push((ChangeType::Removed, old_idx, new_idx));
+            push(ChangeType::Removed, old_idx, new_idx);
             old_idx += 1;
         }
 
         while new_idx < self.new_lines.len() {
-            changes.push((ChangeType::Added, old_idx, new_idx));
+            push(ChangeType::Added, old_idx, new_idx);
             new_idx += 1;
         }
-
-        // Post-process to detect modifications
-        self.detect_modifications(changes)
-    }
-
-    /// Detect line modifications (changed lines rather than add/remove pairs)
-    fn detect_modifications(&self, changes: Vec<(ChangeType, usize, usize)>) -> Vec<(ChangeType, usize, usize)> {
-        let mut result = Vec::new();
-        let mut i = 0;
-
-        while i < changes.len() {
-            if i + 1 < changes.len() {
-                let (type1, old1, new1) = changes[i];
-                let (type2, old2, new2) = changes[i + 1];
-
-                // Look for remove followed by add pattern
-                if type1 == ChangeType::Removed && type2 == ChangeType::Added {
-                    // Check if lines are similar enough to be considered modifications
-                    if self.are_lines_similar(old1, new2) {
-                        result.push((ChangeType::Modified, old1, new2));
-                        i += 2;
-                        continue;
-                    }
-                }
-            }
-
-            result.push(changes[i]);
-            i += 1;
-        }
-
-        result
     }
 
     /// Check if two lines are similar enough to be considered a modification
@@ -230,25 +383,62 @@ impl<'a> MyersDiff<'a> {
         let old_line = self.old_lines[old_idx];
         let new_line = self.new_lines[new_idx];
 
-        // Calculate similarity using Levenshtein distance
-        let distance = levenshtein_distance(old_line, new_line);
-        let max_len = max(old_line.len(), new_line.len());
+        if let Some(similarity) = self.similarity {
+            return similarity(old_line, new_line) > 0.5;
+        }
 
+        if self.pair_trimmed && old_line.trim() == new_line.trim() {
+            return true;
+        }
+
+        let max_len = max(old_line.len(), new_line.len());
         if max_len == 0 {
             return true;
         }
 
+        // Calculate similarity using Levenshtein distance, which is quadratic;
+        // very long lines (minified files, say) only count their shared ends
+        let distance = if old_line.len().saturating_mul(new_line.len()) > MAX_LEVENSHTEIN_CELLS {
+            max_len - shared_ends(old_line, new_line)
+        } else {
+            levenshtein_distance(old_line, new_line)
+        };
+
         let similarity = 1.0 - (distance as f32 / max_len as f32);
         similarity > 0.5 // Consider lines similar if more than 50% similar
     }
 }
 
+/// One step of a raw edit script, with 0-based line indices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditOp {
+    /// Old line kept as the given new line
+    Keep(usize, usize),
+    /// Old line deleted
+    Delete(usize),
+    /// New line inserted
+    Insert(usize),
+}
+
 /// Snake moves in the edit graph
 #[derive(Debug, Clone, Copy)]
 enum SnakeMove {
     Diagonal(usize, usize), // Match
-    Down(usize, usize),     // Delete
-    Right(usize, usize),    // Insert
+    Down(usize),            // Delete old line
+    Right(usize),           // Insert new line
+}
+
+/// Bytes in the common prefix of two lines plus, after it, their common suffix
+fn shared_ends(s1: &str, s2: &str) -> usize {
+    let (s1, s2) = (s1.as_bytes(), s2.as_bytes());
+    let prefix = s1.iter().zip(s2).take_while(|(a, b)| a == b).count();
+    let suffix = s1[prefix..]
+        .iter()
+        .rev()
+        .zip(s2[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    prefix + suffix
 }
 
 /// Calculate Levenshtein distance between two strings
@@ -325,4 +515,120 @@ mod tests {
         assert!(changes.iter().any(|(t, _, _)| *t == ChangeType::Unchanged));
         assert!(changes.iter().any(|(t, _, _)| *t == ChangeType::Modified || *t == ChangeType::Removed || *t == ChangeType::Added));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_modifications_folded_while_streaming() {
+        let old_lines = vec!["keep", "value = 1", "tail", "gone entirely"];
+        let new_lines = vec!["keep", "value = 2", "tail"];
+        let diff = MyersDiff::new(&old_lines, &new_lines);
+
+        let mut streamed = Vec::new();
+        diff.for_each_change(|change_type, old_idx, new_idx| {
+            streamed.push((change_type, old_idx, new_idx));
+        });
+
+        assert_eq!(
+            streamed,
+            vec![
+                (ChangeType::Unchanged, 0, 0),
+                (ChangeType::Modified, 1, 1),
+                (ChangeType::Unchanged, 2, 2),
+                (ChangeType::Removed, 3, 3),
+            ]
+        );
+        assert_eq!(streamed, diff.compute_diff());
+    }
+
+    #[test]
+    fn test_custom_similarity() {
+        let old_lines = vec!["1.000", "keep", "2.0"];
+        let new_lines = vec!["1.001", "keep", "7.5"];
+
+        // Numerically close values are the same reading, far ones are not
+        let close = |a: &str, b: &str| match (a.parse::<f32>(), b.parse::<f32>()) {
+            (Ok(a), Ok(b)) if (a - b).abs() < 0.01 => 1.0,
+            _ => 0.0,
+        };
+        let changes = MyersDiff::new(&old_lines, &new_lines)
+            .with_similarity(&close)
+            .compute_diff();
+
+        assert_eq!(changes[0], (ChangeType::Modified, 0, 0));
+        assert!(changes.contains(&(ChangeType::Removed, 2, 2)));
+        assert!(changes.contains(&(ChangeType::Added, 3, 2)));
+    }
+
+    #[test]
+    fn test_search_budget_falls_back_to_lcs() {
+        let old_lines = vec!["a", "b", "c", "d", "e"];
+        let new_lines = vec!["a", "x", "c", "e", "f"];
+
+        // The shortest script has 4 edits, so a budget of 2 runs out
+        let full = MyersDiff::new(&old_lines, &new_lines);
+        let limited = MyersDiff::new(&old_lines, &new_lines).with_max_edits(2);
+        let script = limited.edit_script();
+        assert_eq!(limited.fallback(), Some(AlignmentFallback::Lcs));
+        assert_eq!(script, full.edit_script());
+        assert_eq!(full.fallback(), None);
+
+        // A budget the script fits in doesn't fall back
+        let enough = MyersDiff::new(&old_lines, &new_lines).with_max_edits(4);
+        enough.edit_script();
+        assert_eq!(enough.fallback(), None);
+    }
+
+    #[test]
+    fn test_search_budget_over_lcs_limit_keeps_shared_ends() {
+        let old: Vec<String> = (0..2100).map(|i| format!("old {}", i)).collect();
+        let mut new: Vec<String> = (0..2100).map(|i| format!("new {}", i)).collect();
+        new[..3].clone_from_slice(&old[..3]);
+        new[2098..].clone_from_slice(&old[2098..]);
+        let old_lines: Vec<&str> = old.iter().map(String::as_str).collect();
+        let new_lines: Vec<&str> = new.iter().map(String::as_str).collect();
+
+        let diff = MyersDiff::new(&old_lines, &new_lines).with_max_edits(10);
+        let changes = diff.compute_diff();
+        assert_eq!(diff.fallback(), Some(AlignmentFallback::Unaligned));
+
+        let unchanged: Vec<_> = changes
+            .iter()
+            .filter(|(t, _, _)| *t == ChangeType::Unchanged)
+            .map(|&(_, old_idx, new_idx)| (old_idx, new_idx))
+            .collect();
+        assert_eq!(unchanged, vec![(0, 0), (1, 1), (2, 2), (2098, 2098), (2099, 2099)]);
+        assert_eq!(changes.len(), 5 + 2 * 2095);
+    }
+
+    #[test]
+    fn test_edit_script_reconstructs_new() {
+        let old_lines = vec!["a", "b", "value = 1", "c", "d", "e"];
+        let new_lines = vec!["a", "value = 2", "c", "x", "e", "f"];
+        let diff = MyersDiff::new(&old_lines, &new_lines);
+        let script = diff.edit_script();
+
+        let mut rebuilt = Vec::new();
+        let mut next_old = 0;
+        for op in &script {
+            match *op {
+                EditOp::Keep(old_idx, new_idx) => {
+                    assert_eq!(old_idx, next_old);
+                    assert_eq!(old_lines[old_idx], new_lines[new_idx]);
+                    rebuilt.push(old_lines[old_idx]);
+                    next_old += 1;
+                }
+                EditOp::Delete(old_idx) => {
+                    assert_eq!(old_idx, next_old);
+                    next_old += 1;
+                }
+                EditOp::Insert(new_idx) => rebuilt.push(new_lines[new_idx]),
+            }
+        }
+        assert_eq!(rebuilt, new_lines);
+        assert_eq!(next_old, old_lines.len());
+
+        // The similar pair stays a delete and an insert
+        assert!(script.contains(&EditOp::Delete(2)));
+        assert!(script.contains(&EditOp::Insert(1)));
+        assert!(diff.compute_diff().contains(&(ChangeType::Modified, 2, 1)));
+    }
+}
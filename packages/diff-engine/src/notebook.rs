@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use serde_json::Value;
+
+use crate::diff::{compute_diff, ChangeType, DiffError, DiffOptions, DiffResult};
+use crate::myers::MyersDiff;
+
+/// Deepest nesting serde_json parses before hitting its recursion limit
+const PARSER_DEPTH_LIMIT: usize = 127;
+
+/// A notebook cell reduced to what reviewers care about
+#[derive(Debug, Clone, PartialEq)]
+struct Cell<'a> {
+    cell_type: String,
+    source: String,
+    outputs: String,
+    /// The cell as written in the notebook
+    json: &'a str,
+    /// Nested too deeply to parse; `source` is the raw JSON
+    too_deep: bool,
+}
+
+/// A notebook's metadata and the unparsed JSON of each cell
+struct RawNotebook<'a> {
+    metadata: Value,
+    cells: Vec<&'a RawValue>,
+}
+
+/// Change to a single notebook cell
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CellDiff {
+    pub change_type: ChangeType,
+    /// Index of the cell in the old notebook
+    pub old_index: Option<usize>,
+    /// Index of the cell in the new notebook
+    pub new_index: Option<usize>,
+    pub cell_type: String,
+    /// Line diff of the cell source, present when the source changed
+    pub source_diff: Option<DiffResult>,
+    pub outputs_changed: bool,
+    /// The cell was nested deeper than `notebook_max_depth`, so
+    /// `source_diff` is a line diff of its raw JSON
+    pub too_deep: bool,
+}
+
+/// Cell-wise diff of two Jupyter notebooks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookDiff {
+    pub language: Option<String>,
+    /// Changed cells only; unchanged cells are omitted
+    pub cells: Vec<CellDiff>,
+}
+
+/// Diff two `.ipynb` documents cell by cell
+///
+/// Cells are aligned on their source, and each changed cell's source is diffed
+/// as text in the notebook's kernel language (markdown cells as markdown).
+/// Execution counts, cell ids and metadata are ignored; outputs are compared
+/// by their text only.
+///
+/// Cells nested deeper than `options.notebook_max_depth` levels (at most the
+/// JSON parser's limit of 127) are not parsed: they are aligned and
+/// line-diffed as their raw JSON and flagged `too_deep`. Metadata nested that
+/// deeply is ignored.
+pub fn diff_notebooks(
+    old_json: &str,
+    new_json: &str,
+    options: &DiffOptions,
+) -> Result<NotebookDiff, DiffError> {
+    let max_depth = options.notebook_max_depth.min(PARSER_DEPTH_LIMIT);
+    let old_notebook = parse_notebook(old_json, max_depth)?;
+    let new_notebook = parse_notebook(new_json, max_depth)?;
+
+    let language = options
+        .language
+        .clone()
+        .or_else(|| notebook_language(&new_notebook.metadata))
+        .or_else(|| notebook_language(&old_notebook.metadata));
+
+    let old_cells = notebook_cells(&old_notebook, max_depth);
+    let new_cells = notebook_cells(&new_notebook, max_depth);
+
+    let old_keys: Vec<&str> = old_cells.iter().map(|c| c.source.as_str()).collect();
+    let new_keys: Vec<&str> = new_cells.iter().map(|c| c.source.as_str()).collect();
+
+    let mut cells = Vec::new();
+    for (change_type, old_idx, new_idx) in MyersDiff::new(&old_keys, &new_keys).compute_diff() {
+        let cell_diff = match change_type {
+            ChangeType::Unchanged => {
+                let (old, new) = (&old_cells[old_idx], &new_cells[new_idx]);
+                if old.outputs == new.outputs {
+                    continue;
+                }
+                CellDiff {
+                    change_type: ChangeType::Modified,
+                    old_index: Some(old_idx),
+                    new_index: Some(new_idx),
+                    cell_type: new.cell_type.clone(),
+                    source_diff: None,
+                    outputs_changed: true,
+                    too_deep: old.too_deep || new.too_deep,
+                }
+            }
+            ChangeType::Modified | ChangeType::ReformatBlock => {
+                let (old, new) = (&old_cells[old_idx], &new_cells[new_idx]);
+                let too_deep = old.too_deep || new.too_deep;
+                let source_diff = if too_deep {
+                    diff_cell_json(old, new, options)?
+                } else {
+                    diff_cell_source(old, new, language.as_deref(), options)?
+                };
+                CellDiff {
+                    change_type: ChangeType::Modified,
+                    old_index: Some(old_idx),
+                    new_index: Some(new_idx),
+                    cell_type: new.cell_type.clone(),
+                    source_diff: Some(source_diff),
+                    outputs_changed: old.outputs != new.outputs,
+                    too_deep,
+                }
+            }
+            ChangeType::Removed => CellDiff {
+                change_type,
+                old_index: Some(old_idx),
+                new_index: None,
+                cell_type: old_cells[old_idx].cell_type.clone(),
+                source_diff: None,
+                outputs_changed: false,
+                too_deep: old_cells[old_idx].too_deep,
+            },
+            ChangeType::Added => CellDiff {
+                change_type,
+                old_index: None,
+                new_index: Some(new_idx),
+                cell_type: new_cells[new_idx].cell_type.clone(),
+                source_diff: None,
+                outputs_changed: false,
+                too_deep: new_cells[new_idx].too_deep,
+            },
+            // Moves are only found in line diffs
+            ChangeType::Moved => continue,
+        };
+        cells.push(cell_diff);
+    }
+
+    Ok(NotebookDiff { language, cells })
+}
+
+/// Split a notebook into its cells without parsing them, so that one deeply
+/// nested cell can't fail the whole diff
+fn parse_notebook(json: &str, max_depth: usize) -> Result<RawNotebook<'_>, DiffError> {
+    let fields: HashMap<String, &RawValue> = serde_json::from_str(json)
+        .map_err(|e| DiffError::InvalidFormat(format!("notebook is not valid JSON: {}", e)))?;
+
+    let cells = fields
+        .get("cells")
+        .and_then(|cells| serde_json::from_str(cells.get()).ok())
+        .ok_or_else(|| DiffError::InvalidFormat("notebook has no cells array".to_string()))?;
+    let metadata = fields
+        .get("metadata")
+        .and_then(|metadata| parse_within(metadata.get(), max_depth))
+        .unwrap_or(Value::Null);
+
+    Ok(RawNotebook { metadata, cells })
+}
+
+/// Parse `json` if it nests no deeper than `max_depth`
+fn parse_within(json: &str, max_depth: usize) -> Option<Value> {
+    if json_depth(json) > max_depth {
+        return None;
+    }
+    serde_json::from_str(json).ok()
+}
+
+/// Deepest nesting of arrays and objects in valid JSON, found without recursing
+fn json_depth(json: &str) -> usize {
+    let (mut depth, mut deepest) = (0, 0);
+    let (mut in_string, mut escaped) = (false, false);
+    for byte in json.bytes() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+            b']' | b'}' => depth -= 1,
+            _ => {}
+        }
+    }
+    deepest
+}
+
+/// Kernel language from the notebook metadata
+fn notebook_language(metadata: &Value) -> Option<String> {
+    metadata["kernelspec"]["language"]
+        .as_str()
+        .or_else(|| metadata["language_info"]["name"].as_str())
+        .map(|s| s.to_lowercase())
+}
+
+fn notebook_cells<'a>(notebook: &RawNotebook<'a>, max_depth: usize) -> Vec<Cell<'a>> {
+    notebook
+        .cells
+        .iter()
+        .map(|raw| {
+            let json = raw.get();
+            match parse_within(json, max_depth) {
+                Some(cell) => Cell {
+                    cell_type: cell["cell_type"].as_str().unwrap_or("code").to_string(),
+                    source: multiline_text(&cell["source"]),
+                    outputs: cell["outputs"]
+                        .as_array()
+                        .map(|outputs| outputs.iter().map(output_text).collect())
+                        .unwrap_or_default(),
+                    json,
+                    too_deep: false,
+                },
+                None => Cell {
+                    cell_type: String::new(),
+                    source: json.to_string(),
+                    outputs: String::new(),
+                    json,
+                    too_deep: true,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Text content of an output, skipping execution counts and rich media
+fn output_text(output: &Value) -> String {
+    let text = if output["text"].is_null() {
+        &output["data"]["text/plain"]
+    } else {
+        &output["text"]
+    };
+    format!("{}{}\n", output["output_type"].as_str().unwrap_or(""), multiline_text(text))
+}
+
+/// nbformat stores text either as one string or as a list of lines
+fn multiline_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(parts) => parts.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+fn diff_cell_source(
+    old: &Cell,
+    new: &Cell,
+    language: Option<&str>,
+    options: &DiffOptions,
+) -> Result<DiffResult, DiffError> {
+    let cell_language = match new.cell_type.as_str() {
+        "markdown" => Some("markdown"),
+        "code" => language,
+        _ => None,
+    };
+
+    let cell_options = DiffOptions {
+        language: cell_language.map(str::to_string),
+        ..options.clone()
+    };
+
+    compute_diff(&old.source, &new.source, &cell_options)
+}
+
+/// Line diff of two cells as written, for cells too deep to parse
+fn diff_cell_json(old: &Cell, new: &Cell, options: &DiffOptions) -> Result<DiffResult, DiffError> {
+    let json_options = DiffOptions {
+        language: Some("json".to_string()),
+        ..options.clone()
+    };
+
+    compute_diff(old.json, new.json, &json_options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notebook(cells: &str) -> String {
+        format!(
+            r#"{{"metadata": {{"kernelspec": {{"language": "python", "name": "python3"}}}}, "nbformat": 4, "nbformat_minor": 5, "cells": [{}]}}"#,
+            cells
+        )
+    }
+
+    #[test]
+    fn test_only_source_change_reported() {
+        let old = notebook(
+            r##"{"cell_type": "markdown", "metadata": {}, "source": ["# Title\n"]},
+                {"cell_type": "code", "execution_count": 1, "metadata": {}, "outputs": [], "source": ["x = 1\n", "print(x)\n"]},
+                {"cell_type": "code", "execution_count": 2, "metadata": {"collapsed": false}, "outputs": [{"output_type": "stream", "name": "stdout", "text": ["hi\n"]}], "source": "print('hi')"}"##,
+        );
+        let new = notebook(
+            r##"{"cell_type": "markdown", "metadata": {}, "source": ["# Title\n"]},
+                {"cell_type": "code", "execution_count": 7, "metadata": {}, "outputs": [], "source": ["x = 2\n", "print(x)\n"]},
+                {"cell_type": "code", "execution_count": 8, "metadata": {"collapsed": true}, "outputs": [{"output_type": "stream", "name": "stdout", "text": ["hi\n"]}], "source": "print('hi')"}"##,
+        );
+
+        let diff = diff_notebooks(&old, &new, &DiffOptions::default()).unwrap();
+        assert_eq!(diff.language.as_deref(), Some("python"));
+        assert_eq!(diff.cells.len(), 1);
+
+        let cell = &diff.cells[0];
+        assert_eq!(cell.change_type, ChangeType::Modified);
+        assert_eq!((cell.old_index, cell.new_index), (Some(1), Some(1)));
+        assert!(!cell.outputs_changed);
+
+        let source_diff = cell.source_diff.as_ref().unwrap();
+        assert_eq!(source_diff.file_language.as_deref(), Some("python"));
+        assert_eq!(source_diff.stats.modified_lines, 1);
+        assert_eq!(source_diff.stats.unchanged_lines, 1);
+    }
+
+    #[test]
+    fn test_added_and_removed_cells() {
+        let old = notebook(
+            r#"{"cell_type": "code", "metadata": {}, "outputs": [], "source": "import os"},
+                {"cell_type": "code", "metadata": {}, "outputs": [], "source": "os.getcwd()"}"#,
+        );
+        let new = notebook(
+            r#"{"cell_type": "code", "metadata": {}, "outputs": [], "source": "import os"},
+                {"cell_type": "markdown", "metadata": {}, "source": "Notes about the working directory"}"#,
+        );
+
+        let diff = diff_notebooks(&old, &new, &DiffOptions::default()).unwrap();
+        let kinds: Vec<_> = diff.cells.iter().map(|c| c.change_type).collect();
+        assert_eq!(kinds, vec![ChangeType::Removed, ChangeType::Added]);
+        assert_eq!(diff.cells[1].cell_type, "markdown");
+    }
+
+    #[test]
+    fn test_invalid_notebook() {
+        assert!(diff_notebooks("not json", "{}", &DiffOptions::default()).is_err());
+        assert!(diff_notebooks("{}", "{}", &DiffOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_deeply_nested_notebook() {
+        let deep = format!("{}1{}", "[".repeat(200), "]".repeat(200));
+        let deep_cell = |source: &str| {
+            format!(
+                r#"{{"cell_type": "code", "metadata": {{"deep": {}}}, "outputs": [], "source": "{}"}}"#,
+                deep, source
+            )
+        };
+        let old = notebook(&deep_cell("x"));
+        let new = notebook(&deep_cell("y"));
+
+        let diff = diff_notebooks(&old, &new, &DiffOptions::default()).unwrap();
+        assert_eq!(diff.language.as_deref(), Some("python"));
+        assert_eq!(diff.cells.len(), 1);
+
+        let cell = &diff.cells[0];
+        assert_eq!(cell.change_type, ChangeType::Modified);
+        assert!(cell.too_deep);
+        let source_diff = cell.source_diff.as_ref().unwrap();
+        assert_eq!(source_diff.file_language.as_deref(), Some("json"));
+        assert_eq!(source_diff.stats.modified_lines, 1);
+
+        // Only the deep side of a replaced cell is flagged
+        let new = notebook(r#"{"cell_type": "code", "metadata": {}, "outputs": [], "source": "y"}"#);
+        let diff = diff_notebooks(&old, &new, &DiffOptions::default()).unwrap();
+        let cells: Vec<_> = diff.cells.iter().map(|c| (c.change_type, c.too_deep)).collect();
+        assert_eq!(cells, vec![(ChangeType::Removed, true), (ChangeType::Added, false)]);
+
+        // Shallow cells around a deep one still diff by source
+        let old = notebook(&format!(
+            r#"{{"cell_type": "code", "metadata": {{}}, "outputs": [], "source": "a = 1"}},
+                {{"cell_type": "code", "metadata": {{"deep": {}}}, "outputs": [], "source": "x"}}"#,
+            deep
+        ));
+        let new = notebook(&format!(
+            r#"{{"cell_type": "code", "metadata": {{}}, "outputs": [], "source": "a = 2"}},
+                {{"cell_type": "code", "metadata": {{"deep": {}}}, "outputs": [], "source": "x"}}"#,
+            deep
+        ));
+        let diff = diff_notebooks(&old, &new, &DiffOptions::default()).unwrap();
+        assert_eq!(diff.cells.len(), 1);
+        assert!(!diff.cells[0].too_deep);
+        assert_eq!(diff.cells[0].cell_type, "code");
+    }
+
+    #[test]
+    fn test_notebook_max_depth() {
+        let nested = |levels: usize| format!("{}1{}", "[".repeat(levels), "]".repeat(levels));
+        let cell = |levels: usize, source: &str| {
+            notebook(&format!(
+                r#"{{"cell_type": "code", "metadata": {{"n": {}}}, "outputs": [], "source": "{}"}}"#,
+                nested(levels),
+                source
+            ))
+        };
+        let too_deep = |levels: usize, options: &DiffOptions| {
+            diff_notebooks(&cell(levels, "x"), &cell(levels, "y"), options).unwrap().cells[0].too_deep
+        };
+
+        // The cell object and its metadata add two levels
+        let options = DiffOptions {
+            notebook_max_depth: 10,
+            ..DiffOptions::default()
+        };
+        assert!(!too_deep(8, &options));
+        assert!(too_deep(9, &options));
+
+        // Depths past the parser's limit are capped rather than failing
+        let options = DiffOptions {
+            notebook_max_depth: usize::MAX,
+            ..DiffOptions::default()
+        };
+        assert!(!too_deep(PARSER_DEPTH_LIMIT - 2, &options));
+        assert!(too_deep(PARSER_DEPTH_LIMIT - 1, &options));
+    }
+
+    #[test]
+    fn test_json_depth() {
+        assert_eq!(json_depth("1"), 0);
+        assert_eq!(json_depth(r#"{"a": [1, {"b": []}]}"#), 4);
+        assert_eq!(json_depth(r#"["[[{", "\"{"]"#), 1);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::diff::DiffError;
+
+/// A line of a hunk body, with its line terminator if it has one
+#[derive(Debug, Clone, Copy)]
+enum PatchLine<'a> {
+    Context(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// One `@@` hunk of a unified diff
+#[derive(Debug)]
+struct Hunk<'a> {
+    /// Index of the first original line the hunk covers, or of the line it
+    /// inserts before when it removes nothing
+    old_start: usize,
+    lines: Vec<PatchLine<'a>>,
+}
+
+/// Apply a unified diff, such as one from `to_unified_diff` or
+/// `to_git_patch`, to `original`
+///
+/// Hunks apply at the line numbers in their headers; there is no searching
+/// for moved context the way `patch` does. A context or removed line that
+/// doesn't match the original fails with `DiffError::PatchMismatch`, so a
+/// patch made against another version of the text is caught rather than
+/// misapplied. Headers and other lines outside hunks are skipped, so the
+/// patch should cover a single file.
+pub fn apply_patch(original: &str, patch: &str) -> Result<String, DiffError> {
+    let original_lines: Vec<&str> = original.split_inclusive('\n').collect();
+    let mut patched = String::with_capacity(original.len());
+    let mut next = 0;
+
+    for hunk in parse_hunks(patch)? {
+        if hunk.old_start < next {
+            return Err(DiffError::InvalidFormat("patch hunks overlap or are out of order".to_string()));
+        }
+        if hunk.old_start > original_lines.len() {
+            return Err(DiffError::PatchMismatch(hunk.old_start + 1));
+        }
+        for line in &original_lines[next..hunk.old_start] {
+            patched.push_str(line);
+        }
+        next = hunk.old_start;
+
+        for line in hunk.lines {
+            let expected = match line {
+                PatchLine::Added(text) => {
+                    patched.push_str(text);
+                    continue;
+                }
+                PatchLine::Context(text) | PatchLine::Removed(text) => text,
+            };
+            let found = original_lines.get(next).copied();
+            if found.map(without_newline) != Some(without_newline(expected)) {
+                return Err(DiffError::PatchMismatch(next + 1));
+            }
+            if let (PatchLine::Context(_), Some(found)) = (line, found) {
+                patched.push_str(found);
+            }
+            next += 1;
+        }
+    }
+
+    for line in &original_lines[next..] {
+        patched.push_str(line);
+    }
+    Ok(patched)
+}
+
+/// The hunks of `patch` in order, checked against their header counts
+fn parse_hunks(patch: &str) -> Result<Vec<Hunk<'_>>, DiffError> {
+    let mut hunks = Vec::new();
+    let mut lines = patch.split_inclusive('\n').peekable();
+
+    while let Some(line) = lines.next() {
+        let Some(header) = line.strip_prefix("@@ -") else {
+            continue;
+        };
+        let (old_start, old_count, new_count) = parse_header(header)?;
+        if old_start == 0 && old_count > 0 {
+            return Err(invalid_hunk(line));
+        }
+
+        // An empty old range names the line before the insertion
+        let old_start = if old_count == 0 { old_start } else { old_start - 1 };
+        let mut hunk = Hunk { old_start, lines: Vec::new() };
+        let (mut old_left, mut new_left) = (old_count, new_count);
+
+        while old_left > 0 || new_left > 0 {
+            let body = lines
+                .next()
+                .ok_or_else(|| DiffError::InvalidFormat("patch ends inside a hunk".to_string()))?;
+            // Some tools strip the space from empty context lines
+            let (prefix, text) = match body.chars().next() {
+                Some(prefix @ (' ' | '-' | '+')) => (prefix, &body[1..]),
+                Some('\n') => (' ', body),
+                _ => return Err(invalid_hunk(body)),
+            };
+            let (uses_old, uses_new) = match prefix {
+                ' ' => (true, true),
+                '-' => (true, false),
+                _ => (false, true),
+            };
+            if (uses_old && old_left == 0) || (uses_new && new_left == 0) {
+                return Err(invalid_hunk(line));
+            }
+            old_left -= usize::from(uses_old);
+            new_left -= usize::from(uses_new);
+
+            let text = match lines.next_if(|next| next.starts_with('\\')) {
+                Some(_) => without_newline(text),
+                None => text,
+            };
+            hunk.lines.push(match prefix {
+                ' ' => PatchLine::Context(text),
+                '-' => PatchLine::Removed(text),
+                _ => PatchLine::Added(text),
+            });
+        }
+
+        hunks.push(hunk);
+    }
+
+    Ok(hunks)
+}
+
+/// Old start and count and new count from a hunk header, after its `@@ -`
+fn parse_header(header: &str) -> Result<(usize, usize, usize), DiffError> {
+    let invalid = || invalid_hunk(header);
+    let (ranges, _) = header.split_once(" @@").ok_or_else(invalid)?;
+    let (old_range, new_range) = ranges.split_once(" +").ok_or_else(invalid)?;
+
+    // A range without a count covers one line
+    let parse_range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = parse_range(old_range).ok_or_else(invalid)?;
+    let (_, new_count) = parse_range(new_range).ok_or_else(invalid)?;
+
+    Ok((old_start, old_count, new_count))
+}
+
+fn invalid_hunk(line: &str) -> DiffError {
+    DiffError::InvalidFormat(format!("malformed patch hunk at `{}`", line.trim_end()))
+}
+
+fn without_newline(line: &str) -> &str {
+    line.strip_suffix('\n').unwrap_or(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::{compute_diff, to_unified_diff, DiffOptions};
+
+    fn unified_diff(old_text: &str, new_text: &str) -> String {
+        let result = compute_diff(old_text, new_text, &DiffOptions::default()).unwrap();
+        to_unified_diff(&result, old_text, new_text, "a", "b", 3)
+    }
+
+    #[test]
+    fn test_clean_apply() {
+        let original = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let patch = "--- a/f\n+++ b/f\n@@ -2,3 +2,3 @@\n b\n-c\n+C\n d\n@@ -8,3 +8,2 @@\n h\n-i\n j\n";
+        assert_eq!(apply_patch(original, patch).unwrap(), "a\nb\nC\nd\ne\nf\ng\nh\nj\n");
+
+        // Round trip through the engine's own output, newline marker included
+        let old_text: String = (0..40).map(|i| format!("line {}\n", i)).collect();
+        let new_text = old_text.replacen("line 5\n", "", 1).replacen("line 20\n", "line 20\nnew\n", 1) + "tail";
+        let patch = unified_diff(&old_text, &new_text);
+        assert_eq!(apply_patch(&old_text, &patch).unwrap(), new_text);
+        assert_eq!(apply_patch(&new_text, &unified_diff(&new_text, &old_text)).unwrap(), old_text);
+    }
+
+    #[test]
+    fn test_context_mismatch() {
+        let patch = "@@ -2,3 +2,3 @@\n b\n-c\n+C\n d\n";
+        let stale = "a\nb\nx\nd\n";
+
+        let result = apply_patch(stale, patch);
+        assert!(matches!(result, Err(DiffError::PatchMismatch(3))));
+        assert!(matches!(apply_patch("a\n", patch), Err(DiffError::PatchMismatch(_))));
+        assert!(matches!(apply_patch("a\nb\n", "@@ -1 +1 @@\n-a\n"), Err(DiffError::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_append_at_eof() {
+        let original = "one\ntwo\n";
+        let patch = "@@ -2,0 +3,2 @@\n+three\n+four\n";
+        assert_eq!(apply_patch(original, patch).unwrap(), "one\ntwo\nthree\nfour\n");
+
+        // Appending to a last line without a newline rewrites that line
+        let patch = unified_diff("one\ntwo", "one\ntwo\nthree\n");
+        assert_eq!(apply_patch("one\ntwo", &patch).unwrap(), "one\ntwo\nthree\n");
+    }
+}
@@ -1,12 +1,16 @@
-use crate::diff::{ChangeType, SemanticInfo};
+use crate::diff::{ChangeType, DiffChange, SemanticInfo};
 use regex::Regex;
 use std::collections::HashMap;
 use once_cell::sync::Lazy;
 
+/// Lines above a change searched for its enclosing scope
+const SCOPE_CONTEXT_LINES: usize = 200;
+
 /// Semantic analyzer for understanding code structure
 pub struct SemanticAnalyzer {
-    language: Option<String>,
-    patterns: HashMap<String, Vec<Pattern>>,
+    /// The language's entry in `PATTERNS`; empty when there is no language
+    /// or it has no patterns
+    patterns: &'static [Pattern],
 }
 
 /// Pattern for matching semantic entities
@@ -152,34 +156,55 @@ static PATTERNS: Lazy<HashMap<String, Vec<Pattern>>> = Lazy::new(|| {
     patterns
 });
 
+/// Force the pattern tables to initialize so the first analysis doesn't pay for it
+pub fn warmup() {
+    Lazy::force(&PATTERNS);
+}
+
 impl SemanticAnalyzer {
     /// Create a new semantic analyzer
+    ///
+    /// Borrows the language's compiled patterns from the shared table, so
+    /// creating one per diff costs nothing beyond the first lookup.
     pub fn new(language: Option<&str>) -> Self {
         Self {
-            language: language.map(|s| s.to_string()),
-            patterns: PATTERNS.clone(),
+            patterns: language
+                .and_then(|language| PATTERNS.get(language))
+                .map_or(&[], Vec::as_slice),
         }
     }
 
-    /// Analyze changes and add semantic information
-    pub fn analyze_changes(
-        &self,
-        changes: Vec<(ChangeType, usize, usize)>,
-        old_lines: &[&str],
-        new_lines: &[&str],
-    ) -> Vec<(ChangeType, usize, usize)> {
-        // For now, just return the changes as-is
-        // In a full implementation, we would analyze the context
-        // and add semantic information to each change
-        changes
+    /// Fill in `semantic_info` for each changed line that declares an entity
+    ///
+    /// Lines are read by line number from the side the change is on, removed
+    /// lines from `old_lines` and the rest from `new_lines`, so the lines
+    /// above can supply the scope. Changes without line numbers are analyzed
+    /// from their content alone. Unchanged lines are left as they are.
+    pub fn analyze_changes(&self, changes: &mut [DiffChange], old_lines: &[&str], new_lines: &[&str]) {
+        if self.patterns.is_empty() {
+            return;
+        }
+
+        for change in changes {
+            let (lines, line_number) = match change.change_type {
+                ChangeType::Unchanged => continue,
+                ChangeType::Removed => (old_lines, change.old_line_number),
+                ChangeType::Moved if change.moved_to.is_some() => (old_lines, change.old_line_number),
+                _ => (new_lines, change.new_line_number),
+            };
+            let context = match line_number {
+                Some(number) if (1..=lines.len()).contains(&number) => {
+                    &lines[number.saturating_sub(SCOPE_CONTEXT_LINES)..number]
+                }
+                _ => &[],
+            };
+            change.semantic_info = self.extract_semantic_info(&change.content, context);
+        }
     }
 
     /// Extract semantic information from a line
     pub fn extract_semantic_info(&self, line: &str, context: &[&str]) -> Option<SemanticInfo> {
-        let language = self.language.as_ref()?;
-        let patterns = self.patterns.get(language)?;
-
-        for pattern in patterns {
+        for pattern in self.patterns {
             if let Some(captures) = pattern.regex.captures(line) {
                 let entity_name = pattern
                     .name_group
@@ -198,6 +223,39 @@ impl SemanticAnalyzer {
         None
     }
 
+    /// Find the nearest entity declared above `line_index`, as git does for
+    /// hunk header context
+    ///
+    /// Only structural entities (functions, types, classes) qualify; imports,
+    /// variables and decorators are skipped. Returns the matched declaration,
+    /// e.g. `pub fn process_data`.
+    pub fn enclosing_entity(&self, lines: &[&str], line_index: usize) -> Option<String> {
+        self.find_enclosing(lines, line_index).map(|(_, entity)| entity)
+    }
+
+    /// Index of the line declaring the entity `enclosing_entity` would return
+    pub fn enclosing_entity_line(&self, lines: &[&str], line_index: usize) -> Option<usize> {
+        self.find_enclosing(lines, line_index).map(|(index, _)| index)
+    }
+
+    fn find_enclosing(&self, lines: &[&str], line_index: usize) -> Option<(usize, String)> {
+        if self.patterns.is_empty() {
+            return None;
+        }
+
+        lines[..line_index.min(lines.len())]
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, line)| {
+                self.patterns
+                    .iter()
+                    .filter(|pattern| pattern.importance > 0.6)
+                    .find_map(|pattern| pattern.regex.find(line))
+                    .map(|m| (index, m.as_str().trim().to_string()))
+            })
+    }
+
     /// Determine the scope of a change
     fn determine_scope(&self, _line: &str, context: &[&str]) -> Option<String> {
         // Simple scope detection based on indentation
@@ -233,8 +291,10 @@ impl SemanticAnalyzer {
         for &(change_type, old_idx, new_idx) in changes {
             let line = match change_type {
                 ChangeType::Removed => lines.get(old_idx).copied(),
-                ChangeType::Added | ChangeType::Modified => lines.get(new_idx).copied(),
-                ChangeType::Unchanged => None,
+                ChangeType::Added | ChangeType::Modified | ChangeType::ReformatBlock => {
+                    lines.get(new_idx).copied()
+                }
+                ChangeType::Unchanged | ChangeType::Moved => None,
             };
 
             if let Some(line) = line {
@@ -306,7 +366,8 @@ pub fn analyze_change_impact(
                     impact.analyze_modified_line(old_line, new_line);
                 }
             }
-            ChangeType::Unchanged => {}
+            // Indentation or position alone has no impact
+            ChangeType::Unchanged | ChangeType::ReformatBlock | ChangeType::Moved => {}
         }
     }
 
@@ -386,4 +447,17 @@ mod tests {
         assert_eq!(info.entity_type, "function");
         assert_eq!(info.entity_name, Some("process_data".to_string()));
     }
+
+    #[test]
+    fn test_patterns_borrowed_per_language() {
+        let first = SemanticAnalyzer::new(Some("rust"));
+        let second = SemanticAnalyzer::new(Some("rust"));
+        assert!(std::ptr::eq(first.patterns, second.patterns));
+        assert!(std::ptr::eq(first.patterns, PATTERNS["rust"].as_slice()));
+
+        // Other languages' patterns aren't consulted
+        assert!(first.extract_semantic_info("class MyClass(BaseClass):", &[]).is_none());
+        assert!(SemanticAnalyzer::new(Some("cobol")).patterns.is_empty());
+        assert!(SemanticAnalyzer::new(None).extract_semantic_info("fn main() {", &[]).is_none());
+    }
 }
\ No newline at end of file
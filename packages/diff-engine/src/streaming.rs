@@ -1,7 +1,9 @@
-use crate::diff::{DiffOptions, DiffResult, DiffHunk, DiffChange, ChangeType, DiffStats};
+use crate::diff::{line_span, DiffOptions, DiffResult, DiffHunk, DiffChange, ChangeType, DiffStats, DiffError, LineEnding};
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use std::error::Error;
 use std::fmt;
+use std::ops::Range;
 
 /// Error type for streaming operations
 #[derive(Debug)]
@@ -9,6 +11,10 @@ pub enum StreamingError {
     InvalidState(String),
     BufferOverflow,
     EncodingError,
+    /// The diff was cancelled with `StreamingDiff::cancel`
+    Cancelled,
+    /// Diffing a window failed
+    Diff(DiffError),
 }
 
 impl fmt::Display for StreamingError {
@@ -17,29 +23,102 @@ impl fmt::Display for StreamingError {
             StreamingError::InvalidState(msg) => write!(f, "Invalid streaming state: {}", msg),
             StreamingError::BufferOverflow => write!(f, "Buffer overflow - file too large"),
             StreamingError::EncodingError => write!(f, "Invalid text encoding in stream"),
+            StreamingError::Cancelled => write!(f, "Streaming diff was cancelled"),
+            StreamingError::Diff(err) => write!(f, "{}", err),
         }
     }
 }
 
-impl Error for StreamingError {}
+impl Error for StreamingError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StreamingError::Diff(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<DiffError> for StreamingError {
+    fn from(err: DiffError) -> Self {
+        StreamingError::Diff(err)
+    }
+}
+
+/// For callers that handle batch and streaming diffs alike; streaming
+/// failures without a batch counterpart become `AlgorithmError`
+impl From<StreamingError> for DiffError {
+    fn from(err: StreamingError) -> Self {
+        match err {
+            StreamingError::Diff(err) => err,
+            StreamingError::BufferOverflow => DiffError::FileTooLarge,
+            StreamingError::EncodingError => DiffError::InvalidEncoding,
+            StreamingError::InvalidState(_) | StreamingError::Cancelled => DiffError::AlgorithmError(err.to_string()),
+        }
+    }
+}
+
+/// Number of lines diffed per processing window
+const CHUNK_LINES: usize = 1000;
+
+/// Lines held back at the end of each window and re-diffed with the next one
+const OVERLAP_LINES: usize = 50;
+
+/// Window sizes for a streaming diff, trading memory for latency
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StreamingConfig {
+    /// Lines diffed per processing window
+    pub chunk_lines: usize,
+    /// Complete lines each file must have buffered before a window is diffed
+    pub process_threshold_lines: usize,
+    /// Lines held back at the end of each window and re-diffed with the next one
+    pub overlap_lines: usize,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            chunk_lines: CHUNK_LINES,
+            process_threshold_lines: CHUNK_LINES,
+            overlap_lines: OVERLAP_LINES,
+        }
+    }
+}
 
 /// Streaming diff processor for handling large files
 pub struct StreamingDiff {
     options: DiffOptions,
+    config: StreamingConfig,
     old_buffer: LineBuffer,
     new_buffer: LineBuffer,
     processed_old_lines: usize,
     processed_new_lines: usize,
+    processed_old_bytes: usize,
+    processed_new_bytes: usize,
     current_hunks: Vec<DiffHunk>,
     state: StreamingState,
+    stats_only: bool,
+    old_bytes: usize,
+    new_bytes: usize,
+    added_lines: usize,
+    removed_lines: usize,
+    modified_lines: usize,
+    whitespace_changed_lines: usize,
+    /// Longest unchanged run within a single window; runs spanning windows
+    /// are only counted in part
+    longest_unchanged_run: usize,
+    /// Conflict regions are only found within a single window
+    has_conflict_markers: bool,
 }
 
+/// Old-file chunks are only accepted in `ReceivingOld`; new-file chunks in
+/// either receiving state, so both files can be fed interleaved
 #[derive(Debug, PartialEq)]
 enum StreamingState {
     ReceivingOld,
     ReceivingNew,
-    Processing,
     Finalized,
+    Cancelled,
 }
 
 /// Buffer for storing lines with efficient memory usage
@@ -68,7 +147,7 @@ impl LineBuffer {
         let mut lines = chunk.lines().peekable();
         
         // Handle continuation of previous line
-        if !self.lines.is_empty() && !chunk.starts_with('\n') {
+        if !self.lines.is_empty() {
             if let Some(last_line) = self.lines.back_mut() {
                 if let Some(first_new_line) = lines.next() {
                     last_line.push_str(first_new_line);
@@ -106,6 +185,33 @@ impl LineBuffer {
         self.lines.len()
     }
 
+    /// Lines safe to diff: all of them once the file is complete, otherwise
+    /// all but the last, which may still be continued by the next chunk
+    fn available(&self, complete: bool) -> usize {
+        if complete {
+            self.lines.len()
+        } else {
+            self.lines.len().saturating_sub(1)
+        }
+    }
+
+    /// Whether `threshold` lines are available to diff, or the buffer is
+    /// filling up and should be drained even with a short window
+    fn is_ready(&self, complete: bool, threshold: usize) -> bool {
+        let available = self.available(complete);
+        complete
+            || available >= threshold
+            || (available > 0 && self.total_size > self.max_size / 2)
+    }
+
+    /// Drop lines from the front, returning how many bytes they took up
+    fn drain_front(&mut self, count: usize) -> usize {
+        let count = count.min(self.lines.len());
+        let drained: usize = self.lines.drain(0..count).map(|line| line.len() + 1).sum();
+        self.total_size = self.total_size.saturating_sub(drained);
+        drained
+    }
+
     fn clear(&mut self) {
         self.lines.clear();
         self.total_size = 0;
@@ -115,21 +221,65 @@ impl LineBuffer {
 impl StreamingDiff {
     /// Create a new streaming diff processor
     pub fn new(options: DiffOptions) -> Self {
+        Self::new_with_config(options, StreamingConfig::default())
+    }
+
+    /// Create a streaming diff processor with custom window sizes
+    pub fn new_with_config(options: DiffOptions, config: StreamingConfig) -> Self {
         let max_buffer_size = options.max_file_size / 2; // Split buffer between old and new
-        
+
+        // A budget applied window by window would thin out arbitrary parts of
+        // the result, and moves between windows would go unnoticed
+        let options = DiffOptions {
+            max_payload_bytes: None,
+            detect_moves: false,
+            ..options
+        };
+
+        let config = StreamingConfig {
+            chunk_lines: config.chunk_lines.max(1),
+            ..config
+        };
+
         Self {
             options,
+            config,
             old_buffer: LineBuffer::new(max_buffer_size),
             new_buffer: LineBuffer::new(max_buffer_size),
             processed_old_lines: 0,
             processed_new_lines: 0,
+            processed_old_bytes: 0,
+            processed_new_bytes: 0,
             current_hunks: Vec::new(),
             state: StreamingState::ReceivingOld,
+            stats_only: false,
+            old_bytes: 0,
+            new_bytes: 0,
+            added_lines: 0,
+            removed_lines: 0,
+            modified_lines: 0,
+            whitespace_changed_lines: 0,
+            longest_unchanged_run: 0,
+            has_conflict_markers: false,
+        }
+    }
+
+    /// Create a streaming diff processor that only keeps running statistics
+    ///
+    /// Hunks are discarded once their changes are counted. Memory stays
+    /// bounded by the window size only when old and new chunks are fed
+    /// interleaved; an old file added in full before `start_new_file` is
+    /// buffered in full.
+    pub fn new_stats_only(options: DiffOptions) -> Self {
+        Self {
+            stats_only: true,
+            ..Self::new(options)
         }
     }
 
     /// Add a chunk of the old file
     pub fn add_old_chunk(&mut self, chunk: &str) -> Result<(), StreamingError> {
+        self.check_not_cancelled()?;
         if self.state != StreamingState::ReceivingOld {
             return Err(StreamingError::InvalidState(
                 "Not in old file receiving state".to_string()
@@ -137,11 +287,18 @@ impl StreamingDiff {
         }
 
         self.old_buffer.add_chunk(chunk)?;
-        Ok(())
+        self.old_bytes += chunk.len();
+        self.process_ready_windows()
     }
 
-    /// Signal end of old file and start receiving new file
+    /// Signal the end of the old file
+    ///
+    /// New-file chunks may already have been interleaved with the old ones;
+    /// after this only new-file chunks are accepted. Calling this before any
+    /// `add_old_chunk` diffs against an empty old file; finalizing without any
+    /// `add_new_chunk` treats the new file as empty.
     pub fn start_new_file(&mut self) -> Result<(), StreamingError> {
+        self.check_not_cancelled()?;
         if self.state != StreamingState::ReceivingOld {
             return Err(StreamingError::InvalidState(
                 "Can only transition from old file state".to_string()
@@ -149,53 +306,121 @@ impl StreamingDiff {
         }
 
         self.state = StreamingState::ReceivingNew;
-        Ok(())
+        self.process_ready_windows()
     }
 
     /// Add a chunk of the new file
+    ///
+    /// May be interleaved with `add_old_chunk`, so neither file has to be
+    /// buffered in full; windows are diffed as soon as both sides have enough.
     pub fn add_new_chunk(&mut self, chunk: &str) -> Result<(), StreamingError> {
-        if self.state != StreamingState::ReceivingNew {
+        self.check_not_cancelled()?;
+        if self.state != StreamingState::ReceivingOld && self.state != StreamingState::ReceivingNew {
             return Err(StreamingError::InvalidState(
-                "Not in new file receiving state".to_string()
+                "Not in a receiving state".to_string()
             ));
         }
 
         self.new_buffer.add_chunk(chunk)?;
-        
-        // Process chunks if we have enough data
-        if self.should_process_chunk() {
-            self.process_available_chunks()?;
+        self.new_bytes += chunk.len();
+        self.process_ready_windows()
+    }
+
+    /// Stop the diff and free its buffers; every later call fails with
+    /// `StreamingError::Cancelled`
+    pub fn cancel(&mut self) {
+        self.state = StreamingState::Cancelled;
+        self.old_buffer.clear();
+        self.new_buffer.clear();
+        self.current_hunks = Vec::new();
+    }
+
+    fn check_not_cancelled(&self) -> Result<(), StreamingError> {
+        match self.state {
+            StreamingState::Cancelled => Err(StreamingError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    fn old_complete(&self) -> bool {
+        self.state != StreamingState::ReceivingOld
+    }
+
+    /// Diff windows while both sides have enough data
+    fn process_ready_windows(&mut self) -> Result<(), StreamingError> {
+        // The new file is only known to be complete at `finalize`
+        let threshold = self.config.process_threshold_lines;
+        while self.old_buffer.is_ready(self.old_complete(), threshold)
+            && self.new_buffer.is_ready(false, threshold)
+        {
+            if self.process_available_chunks(false)? == 0 {
+                break;
+            }
         }
 
         Ok(())
     }
 
-    /// Check if we should process available chunks
-    fn should_process_chunk(&self) -> bool {
-        // Process when we have at least 1000 lines or buffer is getting full
-        self.old_buffer.len() > 1000 || self.new_buffer.len() > 1000 ||
-        self.old_buffer.total_size > self.old_buffer.max_size / 2 ||
-        self.new_buffer.total_size > self.new_buffer.max_size / 2
-    }
+    /// Diff the next window of both buffers, returning how many lines were consumed
+    ///
+    /// Changes are only committed up to the last matched line that leaves
+    /// `overlap_lines` behind in both windows; the rest stays buffered and is
+    /// diffed again with the next window, so a change straddling the window
+    /// edge is aligned as a whole rather than cut in two.
+    fn process_available_chunks(&mut self, is_final: bool) -> Result<usize, StreamingError> {
+        let old_complete = is_final || self.old_complete();
+        let chunk_lines = self.config.chunk_lines;
+        let old_count = chunk_lines.min(self.old_buffer.available(old_complete));
+        let new_count = chunk_lines.min(self.new_buffer.available(is_final));
+        let old_lines = self.old_buffer.get_lines(0, old_count);
+        let new_lines = self.new_buffer.get_lines(0, new_count);
+
+        let last_window =
+            is_final && old_count == self.old_buffer.len() && new_count == self.new_buffer.len();
+
+        // Diff the whole window once, keeping the newline after its last line
+        // when more follows so byte ranges cover it. Line numbers locate the
+        // commit point and are dropped again below if unwanted; passes that
+        // need them stay off as they would without.
+        let mut old_text = old_lines.join("\n");
+        if old_count > 0 && old_count < self.old_buffer.len() {
+            old_text.push('\n');
+        }
+        let mut new_text = new_lines.join("\n");
+        if new_count > 0 && new_count < self.new_buffer.len() {
+            new_text.push('\n');
+        }
+        let window_options = DiffOptions {
+            line_numbers: true,
+            reformat_blocks: self.options.reformat_blocks && self.options.line_numbers,
+            ..self.options.clone()
+        };
+        let mut chunk_result = crate::diff::compute_diff(&old_text, &new_text, &window_options)?;
 
-    /// Process available chunks
-    fn process_available_chunks(&mut self) -> Result<(), StreamingError> {
-        let chunk_size = 1000; // Process 1000 lines at a time
-        
-        let old_lines = self.old_buffer.get_lines(0, chunk_size.min(self.old_buffer.len()));
-        let new_lines = self.new_buffer.get_lines(0, chunk_size.min(self.new_buffer.len()));
+        // With one side exhausted nothing can match, so there is no point looking
+        let commit_point = if last_window || old_count == 0 || new_count == 0 {
+            None
+        } else {
+            self.find_commit_point(&chunk_result.hunks, old_count, new_count)
+        };
+        let (commit_old, commit_new) = match commit_point {
+            Some((commit_old, commit_new)) => {
+                truncate_hunks(&mut chunk_result.hunks, commit_old, commit_new);
+                (commit_old, commit_new)
+            }
+            None => (old_count, new_count),
+        };
 
-        // Run diff on this chunk
-        let chunk_result = crate::diff::compute_diff(
-            &old_lines.join("\n"),
-            &new_lines.join("\n"),
-            &self.options,
-        ).map_err(|e| StreamingError::InvalidState(e.to_string()))?;
+        self.longest_unchanged_run = self
+            .longest_unchanged_run
+            .max(chunk_result.stats.longest_unchanged_run);
+        self.has_conflict_markers |= chunk_result.has_conflict_markers;
 
         // Adjust line numbers and add to current hunks
         for mut hunk in chunk_result.hunks {
             hunk.old_start += self.processed_old_lines;
             hunk.new_start += self.processed_new_lines;
+            crate::diff::rewrite_header_range(&mut hunk);
             
             for change in &mut hunk.changes {
                 if let Some(old_line) = change.old_line_number.as_mut() {
@@ -204,25 +429,94 @@ impl StreamingDiff {
                 if let Some(new_line) = change.new_line_number.as_mut() {
                     *new_line += self.processed_new_lines;
                 }
+                if let Some(range) = change.old_byte_range.as_mut() {
+                    *range = range.start + self.processed_old_bytes..range.end + self.processed_old_bytes;
+                }
+                if let Some(range) = change.new_byte_range.as_mut() {
+                    *range = range.start + self.processed_new_bytes..range.end + self.processed_new_bytes;
+                }
+
+                match change.change_type {
+                    ChangeType::Added => self.added_lines += 1,
+                    ChangeType::Removed => self.removed_lines += 1,
+                    ChangeType::Modified => self.modified_lines += 1,
+                    ChangeType::ReformatBlock => self.modified_lines += change.block_lines,
+                    ChangeType::Moved if change.moved_to.is_some() => self.removed_lines += 1,
+                    ChangeType::Moved => self.added_lines += 1,
+                    ChangeType::Unchanged => {}
+                }
+                self.whitespace_changed_lines += usize::from(change.whitespace_only);
+
+                if !self.options.line_numbers {
+                    change.old_line_number = None;
+                    change.new_line_number = None;
+                    change.conflict_region = false;
+                }
             }
             
-            self.current_hunks.push(hunk);
+            if !self.stats_only {
+                self.current_hunks.push(hunk);
+            }
         }
 
         // Update processed counts
-        self.processed_old_lines += old_lines.len();
-        self.processed_new_lines += new_lines.len();
+        self.processed_old_lines += commit_old;
+        self.processed_new_lines += commit_new;
 
-        // Clear processed lines from buffers
-        // Note: In a real implementation, we'd keep some overlap for context
-        self.old_buffer.lines.drain(0..old_lines.len().min(self.old_buffer.len()));
-        self.new_buffer.lines.drain(0..new_lines.len().min(self.new_buffer.len()));
+        // Clear committed lines from buffers, keeping the overlap for the next window
+        self.processed_old_bytes += self.old_buffer.drain_front(commit_old);
+        self.processed_new_bytes += self.new_buffer.drain_front(commit_new);
 
-        Ok(())
+        Ok(commit_old + commit_new)
+    }
+
+    /// Find the last unchanged line pair of a window's diff that leaves
+    /// enough overlap in both windows, as 1-based line numbers
+    fn find_commit_point(
+        &self,
+        hunks: &[DiffHunk],
+        old_count: usize,
+        new_count: usize,
+    ) -> Option<(usize, usize)> {
+        let overlap = self.config.overlap_lines;
+        let old_limit = old_count.checked_sub(overlap)?;
+        let new_limit = new_count.checked_sub(overlap)?;
+
+        // Lines between hunks are unchanged and pair up in order, so the
+        // latest pair of a gap that fits is found by stepping back from its end
+        let gap_point = |old: Range<usize>, new: Range<usize>| {
+            let (old_last, new_last) = (old.end.checked_sub(1)?, new.end.checked_sub(1)?);
+            let back = old_last.saturating_sub(old_limit).max(new_last.saturating_sub(new_limit));
+            (back < old.len() && back < new.len()).then(|| (old_last - back, new_last - back))
+        };
+
+        let (mut old_end, mut new_end) = (old_count + 1, new_count + 1);
+        for hunk in hunks.iter().rev() {
+            let old = line_span(hunk.old_start, hunk.old_lines);
+            let new = line_span(hunk.new_start, hunk.new_lines);
+            if let Some(point) = gap_point(old.end..old_end, new.end..new_end) {
+                return Some(point);
+            }
+
+            let context_point = hunk
+                .changes
+                .iter()
+                .rev()
+                .filter(|change| change.change_type == ChangeType::Unchanged)
+                .filter_map(|change| Some((change.old_line_number?, change.new_line_number?)))
+                .find(|&(old_num, new_num)| old_num <= old_limit && new_num <= new_limit);
+            if context_point.is_some() {
+                return context_point;
+            }
+
+            (old_end, new_end) = (old.start, new.start);
+        }
+        gap_point(1..old_end, 1..new_end)
     }
 
     /// Finalize the diff computation
     pub fn finalize(&mut self) -> Result<DiffResult, StreamingError> {
+        self.check_not_cancelled()?;
         if self.state == StreamingState::Finalized {
             return Err(StreamingError::InvalidState(
                 "Already finalized".to_string()
@@ -230,8 +524,10 @@ impl StreamingDiff {
         }
 
         // Process any remaining chunks
-        if !self.old_buffer.lines.is_empty() || !self.new_buffer.lines.is_empty() {
-            self.process_available_chunks()?;
+        while !self.old_buffer.lines.is_empty() || !self.new_buffer.lines.is_empty() {
+            if self.process_available_chunks(true)? == 0 {
+                break;
+            }
         }
 
         self.state = StreamingState::Finalized;
@@ -239,12 +535,25 @@ impl StreamingDiff {
         // Calculate final statistics
         let stats = self.calculate_stats();
 
+        // Windows are diffed separately, so a change run cut at a window edge
+        // comes out as two touching hunks
+        let hunks = crate::diff::merge_adjacent_hunks(std::mem::take(&mut self.current_hunks));
+
         Ok(DiffResult {
-            hunks: std::mem::take(&mut self.current_hunks),
+            hunks,
             stats,
             file_language: self.options.language.clone(),
             is_binary: false,
-            is_large_file: true, // Since we're using streaming
+            is_large_file: self.is_large_file(),
+            payload_reduction: None,
+            highlighting_skipped: false,
+            line_ending: LineEnding::default(),
+            line_ending_detected: false,
+            bom_changed: false,
+            final_newline_changed: false,
+            has_conflict_markers: self.has_conflict_markers,
+            debug_anchors: None,
+            alignment_fallback: None,
         })
     }
 
@@ -255,58 +564,51 @@ impl StreamingDiff {
             stats: self.calculate_stats(),
             file_language: self.options.language.clone(),
             is_binary: false,
-            is_large_file: true,
+            is_large_file: self.is_large_file(),
+            payload_reduction: None,
+            highlighting_skipped: false,
+            line_ending: LineEnding::default(),
+            line_ending_detected: false,
+            bom_changed: false,
+            final_newline_changed: false,
+            has_conflict_markers: self.has_conflict_markers,
+            debug_anchors: None,
+            alignment_fallback: None,
         }
     }
 
-    /// Calculate statistics from current hunks
-    fn calculate_stats(&self) -> DiffStats {
-        let mut added_lines = 0;
-        let mut removed_lines = 0;
-        let mut modified_lines = 0;
-
-        for hunk in &self.current_hunks {
-            for change in &hunk.changes {
-                match change.change_type {
-                    ChangeType::Added => added_lines += 1,
-                    ChangeType::Removed => removed_lines += 1,
-                    ChangeType::Modified => modified_lines += 1,
-                    ChangeType::Unchanged => {}
-                }
-            }
-        }
-
-        let total_lines = self.processed_old_lines.max(self.processed_new_lines);
-        let total_changes = added_lines + removed_lines + modified_lines;
-        let similarity = if total_lines > 0 {
-            1.0 - (total_changes as f32 / total_lines as f32)
-        } else {
-            1.0
-        };
+    /// Whether the input received so far exceeds the large file threshold
+    fn is_large_file(&self) -> bool {
+        self.old_bytes > self.options.large_file_threshold
+            || self.new_bytes > self.options.large_file_threshold
+    }
 
-        DiffStats {
-            total_lines,
-            added_lines,
-            removed_lines,
-            modified_lines,
-            unchanged_lines: total_lines.saturating_sub(total_changes),
-            similarity: similarity.max(0.0).min(1.0),
+    /// Calculate statistics from the running change counts
+    fn calculate_stats(&self) -> DiffStats {
+        let mut stats = DiffStats::from_counts(
+            self.processed_old_lines.max(self.processed_new_lines),
+            self.added_lines,
+            self.removed_lines,
+            self.modified_lines,
+            &self.options,
+        );
+        if stats.has_changes {
+            stats.longest_unchanged_run = self.longest_unchanged_run;
         }
+        stats.whitespace_changed_lines = self.whitespace_changed_lines;
+        stats
     }
 }
 
 /// Async chunk processor for web workers
+#[derive(Default)]
 pub struct AsyncChunkProcessor {
-    chunk_size: usize,
     pending_chunks: VecDeque<String>,
 }
 
 impl AsyncChunkProcessor {
-    pub fn new(chunk_size: usize) -> Self {
-        Self {
-            chunk_size,
-            pending_chunks: VecDeque::new(),
-        }
+    pub fn new() -> Self {
+        Self::default()
     }
 
     pub fn add_chunk(&mut self, data: String) {
@@ -322,6 +624,29 @@ impl AsyncChunkProcessor {
     }
 }
 
+/// Drop the changes of a window's diff that come after its commit point,
+/// an unchanged pair the rest of the window is diffed again from
+fn truncate_hunks(hunks: &mut Vec<DiffHunk>, commit_old: usize, commit_new: usize) {
+    let committed = |change: &DiffChange| {
+        change.old_line_number.is_none_or(|n| n <= commit_old)
+            && change.new_line_number.is_none_or(|n| n <= commit_new)
+    };
+
+    for hunk in hunks.iter_mut() {
+        let kept = hunk.changes.iter().take_while(|change| committed(change)).count();
+        if kept == 0 {
+            hunk.changes.clear();
+        } else if kept < hunk.changes.len() {
+            // The hunk now ends on the commit point's context line
+            hunk.changes.truncate(kept);
+            hunk.old_lines = commit_old + 1 - hunk.old_start;
+            hunk.new_lines = commit_new + 1 - hunk.new_start;
+            crate::diff::rewrite_header_range(hunk);
+        }
+    }
+    hunks.retain(|hunk| hunk.changes.iter().any(|change| change.change_type != ChangeType::Unchanged));
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -356,4 +681,335 @@ mod tests {
         
         assert_eq!(diff.state, StreamingState::Finalized);
     }
+
+    #[test]
+    fn test_change_across_window_boundary() {
+        let old_text: String = (0..2000).map(|i| format!("line {}\n", i)).collect();
+        let mut new_lines: Vec<String> = (0..2000).map(|i| format!("line {}", i)).collect();
+        new_lines.insert(999, "inserted a".to_string());
+        new_lines.insert(1000, "inserted b".to_string());
+        let new_text: String = new_lines.iter().map(|l| format!("{}\n", l)).collect();
+
+        let mut diff = StreamingDiff::new(DiffOptions::default());
+        diff.add_old_chunk(&old_text).unwrap();
+        diff.start_new_file().unwrap();
+        for chunk in new_text.as_bytes().chunks(500) {
+            diff.add_new_chunk(std::str::from_utf8(chunk).unwrap()).unwrap();
+        }
+
+        let result = diff.finalize().unwrap();
+        assert_eq!(result.hunks.len(), 1);
+        assert_eq!(result.stats.added_lines, 2);
+        assert_eq!(result.stats.removed_lines, 0);
+        assert_eq!(result.stats.modified_lines, 0);
+
+        let added: Vec<_> = result.hunks[0]
+            .changes
+            .iter()
+            .filter(|c| c.change_type == ChangeType::Added)
+            .collect();
+        assert_eq!(added[0].content, "inserted a");
+        assert_eq!(added[0].new_line_number, Some(1000));
+        assert_eq!(added[1].new_line_number, Some(1001));
+    }
+
+    #[test]
+    fn test_interleaved_windows_match_batch() {
+        let old_lines: Vec<String> = (0..5000).map(|i| format!("line {}", i)).collect();
+        let mut new_lines = old_lines.clone();
+        for i in (100..5000).step_by(397) {
+            new_lines[i] = format!("line {} changed", i);
+        }
+        new_lines.remove(2345);
+        new_lines.insert(3210, "inserted".to_string());
+        let old_text: String = old_lines.iter().map(|l| format!("{}\n", l)).collect();
+        let new_text: String = new_lines.iter().map(|l| format!("{}\n", l)).collect();
+
+        let mut diff = StreamingDiff::new(DiffOptions::default());
+        let mut old_chunks = old_text.as_bytes().chunks(300);
+        let mut new_chunks = new_text.as_bytes().chunks(300);
+        loop {
+            let old_chunk = old_chunks.next();
+            let new_chunk = new_chunks.next();
+            if old_chunk.is_none() && new_chunk.is_none() {
+                break;
+            }
+            if let Some(chunk) = old_chunk {
+                diff.add_old_chunk(std::str::from_utf8(chunk).unwrap()).unwrap();
+            }
+            if let Some(chunk) = new_chunk {
+                diff.add_new_chunk(std::str::from_utf8(chunk).unwrap()).unwrap();
+            }
+
+            // Neither file is ever buffered in full
+            assert!(diff.old_buffer.len() <= 2 * CHUNK_LINES);
+            assert!(diff.new_buffer.len() <= 2 * CHUNK_LINES);
+        }
+        diff.start_new_file().unwrap();
+        let streamed = diff.finalize().unwrap();
+
+        let batch = crate::diff::compute_diff(&old_text, &new_text, &DiffOptions::default()).unwrap();
+
+        let changes = |result: &DiffResult| -> Vec<(ChangeType, Option<usize>, Option<usize>, String)> {
+            result
+                .hunks
+                .iter()
+                .flat_map(|hunk| &hunk.changes)
+                .filter(|change| change.change_type != ChangeType::Unchanged)
+                .map(|change| {
+                    (change.change_type, change.old_line_number, change.new_line_number, change.content.clone())
+                })
+                .collect()
+        };
+
+        assert_eq!(changes(&streamed), changes(&batch));
+        assert_eq!(streamed.stats.added_lines, batch.stats.added_lines);
+        assert_eq!(streamed.stats.removed_lines, batch.stats.removed_lines);
+        assert_eq!(streamed.stats.modified_lines, batch.stats.modified_lines);
+    }
+
+    #[test]
+    fn test_small_chunk_config() {
+        let old_text: String = (0..600).map(|i| format!("line {}\n", i)).collect();
+        let new_text: String = (0..600)
+            .map(|i| if i % 40 == 0 { format!("line {}!\n", i) } else { format!("line {}\n", i) })
+            .collect();
+        let half = new_text.len() / 2;
+
+        let streamed_hunks = |config: StreamingConfig| {
+            let mut diff = StreamingDiff::new_with_config(DiffOptions::default(), config);
+            diff.add_old_chunk(&old_text).unwrap();
+            diff.start_new_file().unwrap();
+            diff.add_new_chunk(&new_text[..half]).unwrap();
+            let partial = diff.get_intermediate_result().hunks.len();
+            diff.add_new_chunk(&new_text[half..]).unwrap();
+            (partial, diff.finalize().unwrap())
+        };
+
+        // With the default windows nothing is diffed until the end
+        let (partial, result) = streamed_hunks(StreamingConfig::default());
+        assert_eq!(partial, 0);
+        assert_eq!(result.stats.modified_lines, 15);
+
+        let config = StreamingConfig {
+            chunk_lines: 50,
+            process_threshold_lines: 50,
+            overlap_lines: 5,
+        };
+        let (partial, result) = streamed_hunks(config);
+        assert!(partial >= 5);
+        assert_eq!(result.stats.modified_lines, 15);
+        assert_eq!(result.stats.added_lines + result.stats.removed_lines, 0);
+    }
+
+    #[test]
+    fn test_windows_follow_diff_options() {
+        // Reindented lines are equal under ignore_whitespace, so windows
+        // commit through them rather than treating them as changes
+        let old_lines: Vec<String> = (0..300).map(|i| format!("line {}", i)).collect();
+        let new_lines: Vec<String> = old_lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| match i % 30 {
+                0 => format!("line {} changed", i),
+                1..=12 => format!("    {}", line),
+                _ => line.clone(),
+            })
+            .collect();
+        let old_text: String = old_lines.iter().map(|l| format!("{}\n", l)).collect();
+        let new_text: String = new_lines.iter().map(|l| format!("{}\n", l)).collect();
+
+        let options = DiffOptions {
+            ignore_whitespace: true,
+            ..DiffOptions::default()
+        };
+        let config = StreamingConfig {
+            chunk_lines: 40,
+            process_threshold_lines: 40,
+            overlap_lines: 5,
+        };
+        let mut diff = StreamingDiff::new_with_config(options.clone(), config);
+        diff.add_old_chunk(&old_text).unwrap();
+        diff.start_new_file().unwrap();
+        diff.add_new_chunk(&new_text).unwrap();
+        let streamed = diff.finalize().unwrap();
+
+        let batch = crate::diff::compute_diff(&old_text, &new_text, &options).unwrap();
+        let numbers = |result: &DiffResult| -> Vec<(ChangeType, Option<usize>, Option<usize>)> {
+            result
+                .hunks
+                .iter()
+                .flat_map(|hunk| &hunk.changes)
+                .filter(|change| change.change_type != ChangeType::Unchanged)
+                .map(|change| (change.change_type, change.old_line_number, change.new_line_number))
+                .collect()
+        };
+        assert_eq!(numbers(&streamed), numbers(&batch));
+        assert_eq!(numbers(&streamed).len(), 20);
+        assert_eq!(streamed.stats.added_lines, batch.stats.added_lines);
+        assert_eq!(streamed.stats.removed_lines, batch.stats.removed_lines);
+    }
+
+    #[test]
+    fn test_window_edge_hunks_merged() {
+        let old_text: String = (0..30).map(|i| format!("line {}\n", i)).collect();
+        let new_text: String = (0..30)
+            .map(|i| if (5..25).contains(&i) { format!("line {}!\n", i) } else { format!("line {}\n", i) })
+            .collect();
+
+        // The changed run spans two windows with no matched line to commit at
+        let config = StreamingConfig {
+            chunk_lines: 10,
+            process_threshold_lines: 10,
+            overlap_lines: 2,
+        };
+        let mut diff = StreamingDiff::new_with_config(DiffOptions::default(), config);
+        diff.add_old_chunk(&old_text).unwrap();
+        diff.start_new_file().unwrap();
+        diff.add_new_chunk(&new_text).unwrap();
+        assert!(diff.get_intermediate_result().hunks.len() >= 2);
+
+        let result = diff.finalize().unwrap();
+        assert_eq!(result.hunks.len(), 1);
+        let hunk = &result.hunks[0];
+        assert_eq!(hunk.changes.first().unwrap().old_line_number, Some(6));
+        assert_eq!(hunk.changes.last().unwrap().new_line_number, Some(25));
+        assert!(hunk.header.starts_with(&format!("@@ -{},", hunk.old_start)));
+    }
+
+    #[test]
+    fn test_empty_new_file() {
+        let old_text: String = (0..2500).map(|i| format!("line {}\n", i)).collect();
+
+        let mut diff = StreamingDiff::new(DiffOptions::default());
+        diff.add_old_chunk(&old_text).unwrap();
+        diff.start_new_file().unwrap();
+
+        let result = diff.finalize().unwrap();
+        assert_eq!(result.stats.removed_lines, 2500);
+        assert_eq!(result.stats.added_lines, 0);
+        assert_eq!(result.stats.modified_lines, 0);
+        assert!(diff.old_buffer.lines.is_empty());
+
+        let removed: Vec<_> = result.hunks.iter().flat_map(|h| &h.changes).collect();
+        assert_eq!(removed.len(), 2500);
+        assert!(removed.iter().all(|c| c.change_type == ChangeType::Removed));
+        assert_eq!(removed[1234].old_line_number, Some(1235));
+        assert_eq!(removed[1234].content, "line 1234");
+    }
+
+    #[test]
+    fn test_empty_old_file() {
+        let new_text: String = (0..2500).map(|i| format!("line {}\n", i)).collect();
+
+        let mut diff = StreamingDiff::new(DiffOptions::default());
+        diff.start_new_file().unwrap();
+        for chunk in new_text.as_bytes().chunks(700) {
+            diff.add_new_chunk(std::str::from_utf8(chunk).unwrap()).unwrap();
+        }
+
+        let result = diff.finalize().unwrap();
+        assert_eq!(result.stats.added_lines, 2500);
+        assert_eq!(result.stats.removed_lines, 0);
+        assert_eq!(result.stats.modified_lines, 0);
+        assert!(diff.new_buffer.lines.is_empty());
+
+        let added: Vec<_> = result.hunks.iter().flat_map(|h| &h.changes).collect();
+        assert_eq!(added.len(), 2500);
+        assert!(added.iter().all(|c| c.change_type == ChangeType::Added));
+        assert_eq!(added[2499].new_line_number, Some(2500));
+    }
+
+    #[test]
+    fn test_streaming_large_file_threshold() {
+        let options = DiffOptions {
+            large_file_threshold: 10,
+            ..DiffOptions::default()
+        };
+
+        let mut diff = StreamingDiff::new(options.clone());
+        diff.add_old_chunk("0123456789").unwrap();
+        diff.start_new_file().unwrap();
+        diff.add_new_chunk("0123456789").unwrap();
+        assert!(!diff.finalize().unwrap().is_large_file);
+
+        let mut diff = StreamingDiff::new(options);
+        diff.add_old_chunk("0123456789").unwrap();
+        diff.start_new_file().unwrap();
+        diff.add_new_chunk("0123456789a").unwrap();
+        assert!(diff.finalize().unwrap().is_large_file);
+    }
+
+    #[test]
+    fn test_stats_only_streaming() {
+        let mut diff = StreamingDiff::new_stats_only(DiffOptions::default());
+        let total = 100_000;
+        let chunk = 1000;
+
+        // Interleaved, so neither file is ever buffered in full
+        for start in (0..total).step_by(chunk) {
+            let old_text: String = (start..start + chunk).map(|i| format!("line {}\n", i)).collect();
+            diff.add_old_chunk(&old_text).unwrap();
+
+            // One modified line per chunk
+            let new_text: String = (start..start + chunk)
+                .map(|i| {
+                    if i % chunk == 500 {
+                        format!("line {}!\n", i)
+                    } else {
+                        format!("line {}\n", i)
+                    }
+                })
+                .collect();
+            diff.add_new_chunk(&new_text).unwrap();
+            assert!(diff.current_hunks.is_empty());
+            // At most the window being held back plus the chunk just added
+            let bound = diff.config.chunk_lines + chunk;
+            assert!(diff.old_buffer.len() <= bound);
+            assert!(diff.new_buffer.len() <= bound);
+        }
+        diff.start_new_file().unwrap();
+
+        let result = diff.finalize().unwrap();
+        assert!(result.hunks.is_empty());
+        assert_eq!(result.stats.modified_lines, total / chunk);
+        assert_eq!(result.stats.added_lines, 0);
+        assert_eq!(result.stats.removed_lines, 0);
+        assert!(result.stats.total_lines >= total);
+        assert!(result.stats.similarity > 0.99);
+    }
+
+    #[test]
+    fn test_error_conversions() {
+        let options = DiffOptions {
+            max_file_size: 64,
+            ..DiffOptions::default()
+        };
+        let mut diff = StreamingDiff::new(options);
+        let err = diff.add_old_chunk(&"x".repeat(100)).unwrap_err();
+        assert!(matches!(err, StreamingError::BufferOverflow));
+        assert!(matches!(DiffError::from(err), DiffError::FileTooLarge));
+
+        let err = StreamingError::from(DiffError::InvalidFormat("bad".to_string()));
+        assert!(err.source().is_some());
+        assert!(matches!(DiffError::from(err), DiffError::InvalidFormat(msg) if msg == "bad"));
+        assert!(matches!(DiffError::from(StreamingError::EncodingError), DiffError::InvalidEncoding));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut diff = StreamingDiff::new(DiffOptions::default());
+        diff.add_old_chunk("old content\n").unwrap();
+        diff.cancel();
+
+        assert_eq!(diff.old_buffer.len(), 0);
+        assert!(matches!(diff.add_old_chunk("more\n"), Err(StreamingError::Cancelled)));
+        assert!(matches!(diff.start_new_file(), Err(StreamingError::Cancelled)));
+        assert!(matches!(diff.add_new_chunk("new\n"), Err(StreamingError::Cancelled)));
+        assert!(matches!(diff.finalize(), Err(StreamingError::Cancelled)));
+        assert!(matches!(
+            DiffError::from(StreamingError::Cancelled),
+            DiffError::AlgorithmError(_)
+        ));
+    }
 }
\ No newline at end of file
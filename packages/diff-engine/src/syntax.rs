@@ -1,3 +1,4 @@
+use crate::diff::{escape_html, DiffError};
 use once_cell::sync::Lazy;
 use regex::Regex;
 use std::collections::HashMap;
@@ -10,11 +11,27 @@ pub struct SyntaxRule {
     pub priority: u8,
 }
 
+/// A highlighted span as (token type, class name, start, end)
+pub type HighlightToken = (String, String, usize, usize);
+
 #[derive(Debug, Clone)]
 pub struct SyntaxHighlighter {
     rules: Vec<SyntaxRule>,
+    /// Delimiters of the language's block comments, which can span lines
+    /// and so are tracked outside the per-line rules
+    block_comment: Option<BlockComment>,
 }
 
+#[derive(Debug, Clone)]
+struct BlockComment {
+    open: &'static str,
+    close: &'static str,
+    class_name: String,
+}
+
+/// Languages with `/* */` block comments
+const C_STYLE_BLOCK_COMMENTS: &[&str] = &["c", "cpp", "go", "java", "javascript", "rust", "sql", "typescript"];
+
 // Simplified language definitions
 static LANGUAGE_DEFINITIONS: Lazy<HashMap<String, Vec<SyntaxRule>>> = Lazy::new(|| {
     let mut languages = HashMap::new();
@@ -50,6 +67,52 @@ static LANGUAGE_DEFINITIONS: Lazy<HashMap<String, Vec<SyntaxRule>>> = Lazy::new(
         ],
     );
 
+    // TypeScript: the JavaScript rules plus its own keywords and types
+    languages.insert(
+        "typescript".to_string(),
+        vec![
+            SyntaxRule {
+                regex: Regex::new(r"//.*").unwrap(),
+                token_type: "comment".to_string(),
+                class_name: "comment".to_string(),
+                priority: 90,
+            },
+            SyntaxRule {
+                regex: Regex::new(r#""(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*'|`(?:[^`\\]|\\.)*`"#).unwrap(),
+                token_type: "string".to_string(),
+                class_name: "string".to_string(),
+                priority: 80,
+            },
+            SyntaxRule {
+                regex: Regex::new(r"@\w+").unwrap(),
+                token_type: "decorator".to_string(),
+                class_name: "decorator".to_string(),
+                priority: 75,
+            },
+            SyntaxRule {
+                regex: Regex::new(
+                    r"\b(?:const|let|var|function|class|if|else|for|while|return|async|await|interface|type|enum|implements|extends|readonly|public|private|protected|abstract|declare|namespace|import|export|from|as|new|keyof|typeof)\b",
+                )
+                .unwrap(),
+                token_type: "keyword".to_string(),
+                class_name: "keyword".to_string(),
+                priority: 70,
+            },
+            SyntaxRule {
+                regex: Regex::new(r"\b(?:string|number|boolean|bigint|symbol|object|any|unknown|never|void|undefined|null)\b").unwrap(),
+                token_type: "type".to_string(),
+                class_name: "type".to_string(),
+                priority: 65,
+            },
+            SyntaxRule {
+                regex: Regex::new(r"\b\d+\b").unwrap(),
+                token_type: "number".to_string(),
+                class_name: "number".to_string(),
+                priority: 60,
+            },
+        ],
+    );
+
     // Python
     languages.insert(
         "python".to_string(),
@@ -112,79 +175,403 @@ static LANGUAGE_DEFINITIONS: Lazy<HashMap<String, Vec<SyntaxRule>>> = Lazy::new(
         ],
     );
 
+    // SQL, whose keywords are case-insensitive
+    languages.insert(
+        "sql".to_string(),
+        vec![
+            SyntaxRule {
+                regex: Regex::new(r"--.*").unwrap(),
+                token_type: "comment".to_string(),
+                class_name: "comment".to_string(),
+                priority: 90,
+            },
+            SyntaxRule {
+                regex: Regex::new(r"'(?:[^']|'')*'").unwrap(),
+                token_type: "string".to_string(),
+                class_name: "string".to_string(),
+                priority: 80,
+            },
+            SyntaxRule {
+                regex: Regex::new(r"(?i)\b(?:select|from|where|insert|into|values|update|set|delete|create|alter|drop|table|index|view|join|inner|left|right|outer|on|and|or|not|null|is|in|as|order|group|by|having|limit|offset|distinct|union|all|case|when|then|else|end|exists|between|like|primary|foreign|key|references|default)\b").unwrap(),
+                token_type: "keyword".to_string(),
+                class_name: "keyword".to_string(),
+                priority: 70,
+            },
+            SyntaxRule {
+                regex: Regex::new(r"\b\d+(?:\.\d+)?\b").unwrap(),
+                token_type: "number".to_string(),
+                class_name: "number".to_string(),
+                priority: 60,
+            },
+        ],
+    );
+
+    // C-family languages differ mostly in keywords
+    const C_KEYWORDS: &str = "auto|break|case|char|const|continue|default|do|double|else|enum|extern|float|for|goto|if|inline|int|long|register|restrict|return|short|signed|sizeof|static|struct|switch|typedef|union|unsigned|void|volatile|while";
+    const CPP_KEYWORDS: &str = "alignas|alignof|bool|catch|class|constexpr|const_cast|decltype|delete|dynamic_cast|explicit|export|false|friend|mutable|namespace|new|noexcept|nullptr|operator|override|private|protected|public|reinterpret_cast|static_assert|static_cast|template|this|throw|true|try|typeid|typename|using|virtual";
+    const QUOTED: &str = r#""(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*'"#;
+
+    languages.insert(
+        "go".to_string(),
+        c_family_rules(
+            "break|case|chan|const|continue|default|defer|else|fallthrough|for|func|go|goto|if|import|interface|map|package|range|return|select|struct|switch|type|var",
+            // Raw strings too
+            &format!("{}|`[^`]*`", QUOTED),
+        ),
+    );
+    languages.insert(
+        "java".to_string(),
+        c_family_rules(
+            "abstract|assert|boolean|break|byte|case|catch|char|class|const|continue|default|do|double|else|enum|extends|final|finally|float|for|goto|if|implements|import|instanceof|int|interface|long|native|new|package|private|protected|public|record|return|short|static|strictfp|super|switch|synchronized|this|throw|throws|transient|try|var|void|volatile|while|yield",
+            QUOTED,
+        ),
+    );
+    languages.insert("c".to_string(), c_family_rules(C_KEYWORDS, QUOTED));
+    languages.insert(
+        "cpp".to_string(),
+        c_family_rules(&format!("{}|{}", C_KEYWORDS, CPP_KEYWORDS), QUOTED),
+    );
+
     languages
 });
 
+/// Rules for a language with C-style comments, given its keywords and its
+/// string and character literals as regex alternatives
+fn c_family_rules(keywords: &str, strings: &str) -> Vec<SyntaxRule> {
+    vec![
+        SyntaxRule {
+            regex: Regex::new(r"//.*").unwrap(),
+            token_type: "comment".to_string(),
+            class_name: "comment".to_string(),
+            priority: 90,
+        },
+        SyntaxRule {
+            regex: Regex::new(strings).unwrap(),
+            token_type: "string".to_string(),
+            class_name: "string".to_string(),
+            priority: 80,
+        },
+        SyntaxRule {
+            regex: Regex::new(&format!(r"\b(?:{})\b", keywords)).unwrap(),
+            token_type: "keyword".to_string(),
+            class_name: "keyword".to_string(),
+            priority: 70,
+        },
+        // Hex, decimal and floating point, with digit separators and suffixes
+        SyntaxRule {
+            regex: Regex::new(r"\b(?:0[xX][0-9a-fA-F_']+|\d[\d_']*(?:\.\d+)?(?:[eE][+-]?\d+)?)[uUlLfF]*\b").unwrap(),
+            token_type: "number".to_string(),
+            class_name: "number".to_string(),
+            priority: 60,
+        },
+    ]
+}
+
 impl SyntaxHighlighter {
-    pub fn new(language: &str) -> Self {
+    /// Highlighter for one of `get_supported_languages`; any other language
+    /// is a `DiffError::SyntaxError`, so a misspelled name isn't mistaken
+    /// for a language with nothing to highlight
+    pub fn new(language: &str) -> Result<Self, DiffError> {
         let rules = LANGUAGE_DEFINITIONS
             .get(language)
-            .or_else(|| LANGUAGE_DEFINITIONS.get("text"))
             .cloned()
-            .unwrap_or_default();
+            .ok_or_else(|| DiffError::SyntaxError(format!("unsupported language `{}`", language)))?;
+        let block_comment = C_STYLE_BLOCK_COMMENTS.contains(&language).then(|| BlockComment {
+            open: "/*",
+            close: "*/",
+            class_name: "comment".to_string(),
+        });
+
+        Ok(Self { rules, block_comment })
+    }
+
+    /// Override the class name emitted for each token type in `class_names`
+    pub fn with_class_names(mut self, class_names: &HashMap<String, String>) -> Self {
+        for rule in &mut self.rules {
+            if let Some(class_name) = class_names.get(&rule.token_type) {
+                rule.class_name = class_name.clone();
+            }
+        }
+        if let (Some(block_comment), Some(class_name)) = (&mut self.block_comment, class_names.get("comment")) {
+            block_comment.class_name = class_name.clone();
+        }
+        self
+    }
+
+    /// Highlighter with custom rules; where several match at a position the
+    /// highest `priority` wins, then the one listed first
+    pub fn from_rules(rules: Vec<SyntaxRule>) -> Self {
+        Self {
+            rules,
+            block_comment: None,
+        }
+    }
+
+    /// Tokens of `text` as (token type, class name, start, end)
+    ///
+    /// Start and end are byte offsets into `text`, always on character
+    /// boundaries, like the other spans the engine reports; convert them
+    /// before indexing a JavaScript string.
+    pub fn highlight(&self, text: &str) -> Vec<HighlightToken> {
+        self.try_highlight(text).unwrap_or_default()
+    }
 
-        Self { rules }
+    /// Like `highlight`, but failing on a rule that matches the empty string,
+    /// which would never advance past its match
+    ///
+    /// Block comments are followed across lines, so every line from the one
+    /// opening a comment to the one closing it is tokenized as comment.
+    pub fn try_highlight(&self, text: &str) -> Result<Vec<HighlightToken>, DiffError> {
+        self.try_highlight_continuing(text, false).map(|(tokens, _)| tokens)
     }
 
-    pub fn highlight(&self, text: &str) -> Vec<(String, String, usize, usize)> {
+    /// Like `try_highlight` for text that may start inside a block comment,
+    /// for highlighting a file a line at a time; also returns whether the
+    /// text ends inside one
+    pub fn try_highlight_continuing(
+        &self,
+        text: &str,
+        mut in_block_comment: bool,
+    ) -> Result<(Vec<HighlightToken>, bool), DiffError> {
         let mut tokens = Vec::new();
         let mut char_indices: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
         char_indices.push(text.len());
 
-        for (line_num, line) in text.lines().enumerate() {
-            let line_start = if line_num == 0 {
-                0
-            } else {
-                text.lines()
-                    .take(line_num)
-                    .map(|l| l.len() + 1)
-                    .sum::<usize>()
-            };
+        let mut next_line_start = 0;
+        for raw_line in text.split_inclusive('\n') {
+            let line_start = next_line_start;
+            next_line_start += raw_line.len();
+            let line = raw_line.strip_suffix('\n').unwrap_or(raw_line);
+            let line = line.strip_suffix('\r').unwrap_or(line);
 
             let mut position = 0;
             while position < line.len() {
-                let mut found = false;
                 let remaining = &line[position..];
 
+                if let Some(block_comment) = &self.block_comment {
+                    if in_block_comment || remaining.starts_with(block_comment.open) {
+                        // The opening delimiter can't also close the comment
+                        let search_from = if in_block_comment { 0 } else { block_comment.open.len() };
+                        let end = match remaining[search_from..].find(block_comment.close) {
+                            Some(close) => {
+                                in_block_comment = false;
+                                search_from + close + block_comment.close.len()
+                            }
+                            None => {
+                                in_block_comment = true;
+                                remaining.len()
+                            }
+                        };
+                        let start = line_start + position;
+                        tokens.push((
+                            "comment".to_string(),
+                            block_comment.class_name.clone(),
+                            start,
+                            start + end,
+                        ));
+                        position += end;
+                        continue;
+                    }
+                }
+
+                // Of the rules matching here, the highest priority wins and
+                // ties go to the rule declared first
+                let mut best: Option<(&SyntaxRule, usize)> = None;
                 for rule in &self.rules {
-                    if let Some(mat) = rule.regex.find(remaining) {
-                        if mat.start() == 0 {
-                            let start = line_start + position;
-                            let end = start + mat.len();
-                            tokens.push((
-                                rule.token_type.clone(),
-                                rule.class_name.clone(),
-                                start,
-                                end,
-                            ));
-                            position += mat.len();
-                            found = true;
-                            break;
-                        }
+                    let Some(mat) = rule.regex.find(remaining) else {
+                        continue;
+                    };
+                    if mat.start() != 0 {
+                        continue;
+                    }
+                    if mat.is_empty() {
+                        return Err(DiffError::SyntaxError(format!(
+                            "{} rule matches the empty string",
+                            rule.token_type
+                        )));
+                    }
+                    if best.is_none_or(|(best_rule, _)| rule.priority > best_rule.priority) {
+                        best = Some((rule, mat.len()));
                     }
                 }
 
-                if !found {
-                    position += 1;
+                // The whole match is consumed, so nothing inside a string or
+                // comment is tokenized again
+                match best {
+                    Some((rule, len)) => {
+                        let start = line_start + position;
+                        tokens.push((rule.token_type.clone(), rule.class_name.clone(), start, start + len));
+                        position += len;
+                    }
+                    None => position += remaining.chars().next().map_or(1, char::len_utf8),
                 }
             }
         }
 
-        tokens
+        Ok((merge_adjacent_tokens(tokens), in_block_comment))
+    }
+
+    /// Whether `text` ends inside a block comment, as `try_highlight_continuing`
+    /// reports, without tokenizing text that can't open or close one
+    pub(crate) fn ends_in_block_comment(&self, text: &str, in_block_comment: bool) -> Result<bool, DiffError> {
+        let Some(block_comment) = &self.block_comment else {
+            return Ok(false);
+        };
+        let delimiter = if in_block_comment { block_comment.close } else { block_comment.open };
+        if !text.contains(delimiter) {
+            return Ok(in_block_comment);
+        }
+        self.try_highlight_continuing(text, in_block_comment)
+            .map(|(_, in_block_comment)| in_block_comment)
+    }
+
+    /// Highlight a line as HTML, each token wrapped in a `<span>` carrying its
+    /// class name and everything escaped
+    pub fn highlight_to_html(&self, line: &str) -> String {
+        let mut html = String::with_capacity(line.len());
+        let mut position = 0;
+        for (_, class_name, start, end) in self.highlight(line) {
+            html.push_str(&escape_html(&line[position..start]).0);
+            html.push_str("<span class=\"");
+            html.push_str(&escape_html(&class_name).0);
+            html.push_str("\">");
+            html.push_str(&escape_html(&line[start..end]).0);
+            html.push_str("</span>");
+            position = end;
+        }
+        html.push_str(&escape_html(&line[position..]).0);
+        html
     }
 }
 
+/// A language's highlighters, one per class name map they were built with
+type HighlighterVariants = Vec<(Option<HashMap<String, String>>, SyntaxHighlighter)>;
+
+/// Highlighters kept per language and class name map, so repeated diffs
+/// reuse their rules instead of cloning them from the language table each time
+#[derive(Debug, Default)]
+pub struct HighlighterCache {
+    highlighters: HashMap<String, HighlighterVariants>,
+    inserted: HashMap<String, SyntaxHighlighter>,
+    created: usize,
+}
+
+impl HighlighterCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Highlighter for `language` with `class_names`, built on first use;
+    /// `None` if the language has no rules and none was inserted for it
+    pub fn get(
+        &mut self,
+        language: &str,
+        class_names: Option<&HashMap<String, String>>,
+    ) -> Option<&SyntaxHighlighter> {
+        if let Some(highlighter) = self.inserted.get(language) {
+            return Some(highlighter);
+        }
+
+        let variants = self.highlighters.entry(language.to_string()).or_default();
+        let index = match variants.iter().position(|(names, _)| names.as_ref() == class_names) {
+            Some(index) => index,
+            None => {
+                let highlighter = SyntaxHighlighter::new(language).ok()?;
+                let highlighter = match class_names {
+                    Some(class_names) => highlighter.with_class_names(class_names),
+                    None => highlighter,
+                };
+                self.created += 1;
+                variants.push((class_names.cloned(), highlighter));
+                variants.len() - 1
+            }
+        };
+        Some(&variants[index].1)
+    }
+
+    /// Use `highlighter` for `language` whatever the class name map, e.g. one
+    /// built from custom rules
+    pub fn insert(&mut self, language: &str, highlighter: SyntaxHighlighter) {
+        self.inserted.insert(language.to_string(), highlighter);
+    }
+
+    /// Number of highlighters built so far, i.e. cache misses
+    pub fn created(&self) -> usize {
+        self.created
+    }
+
+    pub fn clear(&mut self) {
+        self.highlighters.clear();
+        self.inserted.clear();
+    }
+}
+
+/// Collapse contiguous tokens of the same type and class into one span
+fn merge_adjacent_tokens(tokens: Vec<HighlightToken>) -> Vec<HighlightToken> {
+    let mut merged: Vec<HighlightToken> = Vec::with_capacity(tokens.len());
+
+    for token in tokens {
+        let (token_type, class_name, start, end) = &token;
+        match merged.last_mut() {
+            Some((last_type, last_class, _, last_end))
+                if last_end == start && last_type == token_type && last_class == class_name =>
+            {
+                *last_end = *end;
+            }
+            _ => merged.push(token),
+        }
+    }
+
+    merged
+}
+
+/// Force the language tables to initialize so the first highlight doesn't pay for it
+pub fn warmup() {
+    Lazy::force(&LANGUAGE_DEFINITIONS);
+}
+
+/// Warm up the rules for a single language, returning false if it has none
+pub fn warmup_language(language: &str) -> bool {
+    match LANGUAGE_DEFINITIONS.get(language) {
+        Some(rules) => {
+            for rule in rules {
+                rule.regex.is_match("");
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Names of the languages with highlighting rules, sorted alphabetically
 pub fn get_supported_languages() -> Vec<String> {
-    LANGUAGE_DEFINITIONS.keys().cloned().collect()
+    let mut languages: Vec<String> = LANGUAGE_DEFINITIONS.keys().cloned().collect();
+    languages.sort();
+    languages.dedup();
+    languages
 }
 
+/// Language for a file name, falling back to sniffing `content`
 pub fn detect_language(filename: &str, content: &str) -> String {
+    detect_language_with(filename, content, &HashMap::new())
+}
+
+/// Like `detect_language`, checking `extensions` (see
+/// `DiffOptions::extension_languages`) before the built-in table
+pub fn detect_language_with(filename: &str, content: &str, extensions: &HashMap<String, String>) -> String {
     let extension = filename
         .rsplit('.')
         .next()
         .unwrap_or("")
         .to_lowercase();
 
+    let custom = extensions
+        .iter()
+        .find(|(key, _)| key.trim_start_matches('.').eq_ignore_ascii_case(&extension));
+    if let Some((_, language)) = custom {
+        return language.clone();
+    }
+
     match extension.as_str() {
         "js" | "jsx" | "mjs" => "javascript",
         "ts" | "tsx" => "typescript",
@@ -210,6 +597,8 @@ pub fn detect_language(filename: &str, content: &str) -> String {
         "css" => "css",
         "scss" | "sass" => "scss",
         "md" | "markdown" => "markdown",
+        // No dedicated single-file component support yet; script blocks are the bulk of them
+        "vue" | "svelte" => "javascript",
         _ => detect_from_content(content),
     }
     .to_string()
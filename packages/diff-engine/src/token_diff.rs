@@ -0,0 +1,128 @@
+use crate::diff::ChangeType;
+use crate::myers::{EditOp, MyersDiff};
+use crate::syntax::SyntaxHighlighter;
+use crate::word_diff::{atomic_ranges, tokenize};
+
+/// A token's text and the line it sits on
+struct LineToken<'a> {
+    text: &'a str,
+    line: usize,
+}
+
+/// Experimental: align two files by their tokens instead of their lines
+///
+/// Whitespace between tokens is ignored, so reflowing code (moving a brace to
+/// the next line, rewrapping arguments) is no change at all. Token edits are
+/// mapped back to lines for display: a line holding a deleted token is removed,
+/// one holding an inserted token is added, and a removed line directly
+/// followed by an added one becomes a modification. Tokens that merely moved
+/// to another line leave both lines unchanged.
+///
+/// Changes are emitted like `MyersDiff::for_each_change`, so they can feed the
+/// same hunk building. String literals that `strings` finds are single tokens.
+pub(crate) fn for_each_line_change(
+    old_lines: &[&str],
+    new_lines: &[&str],
+    strings: Option<&SyntaxHighlighter>,
+    mut emit: impl FnMut(ChangeType, usize, usize),
+) {
+    let old_tokens = line_tokens(old_lines, strings);
+    let new_tokens = line_tokens(new_lines, strings);
+    let old_words: Vec<&str> = old_tokens.iter().map(|t| t.text).collect();
+    let new_words: Vec<&str> = new_tokens.iter().map(|t| t.text).collect();
+    let script = MyersDiff::new(&old_words, &new_words).edit_script();
+
+    let mut old_changed = vec![false; old_lines.len()];
+    let mut new_changed = vec![false; new_lines.len()];
+    for op in &script {
+        match *op {
+            EditOp::Delete(i) => old_changed[old_tokens[i].line] = true,
+            EditOp::Insert(j) => new_changed[new_tokens[j].line] = true,
+            EditOp::Keep(..) => {}
+        }
+    }
+
+    let mut walk = LineWalk {
+        old_changed: &old_changed,
+        new_changed: &new_changed,
+        old_next: 0,
+        new_next: 0,
+        pending_removal: None,
+    };
+    for op in &script {
+        match *op {
+            EditOp::Keep(i, j) => {
+                walk.old_through(old_tokens[i].line + 1, &mut emit);
+                walk.new_through(new_tokens[j].line + 1, &mut emit);
+            }
+            EditOp::Delete(i) => walk.old_through(old_tokens[i].line + 1, &mut emit),
+            EditOp::Insert(j) => walk.new_through(new_tokens[j].line + 1, &mut emit),
+        }
+    }
+    walk.old_through(old_lines.len(), &mut emit);
+    walk.new_through(new_lines.len(), &mut emit);
+    if let Some((old_idx, new_idx)) = walk.pending_removal {
+        emit(ChangeType::Removed, old_idx, new_idx);
+    }
+}
+
+/// Non-whitespace tokens of every line, with string literals kept whole
+fn line_tokens<'a>(lines: &[&'a str], strings: Option<&SyntaxHighlighter>) -> Vec<LineToken<'a>> {
+    let mut tokens = Vec::new();
+    for (line_idx, &line) in lines.iter().enumerate() {
+        let atomic = atomic_ranges(line, strings);
+        tokens.extend(
+            tokenize(line, &atomic)
+                .into_iter()
+                .map(|(start, end)| &line[start..end])
+                .filter(|text| !text.trim().is_empty())
+                .map(|text| LineToken { text, line: line_idx }),
+        );
+    }
+    tokens
+}
+
+/// Walks both files' lines in step with the token edit script
+struct LineWalk<'a> {
+    old_changed: &'a [bool],
+    new_changed: &'a [bool],
+    old_next: usize,
+    new_next: usize,
+    /// A removed line held back in case an added line follows
+    pending_removal: Option<(usize, usize)>,
+}
+
+impl LineWalk<'_> {
+    /// Emit old lines up to `end`; unchanged ones are reported from the new side,
+    /// and a removal is only held back while no unchanged line follows it
+    fn old_through(&mut self, end: usize, emit: &mut impl FnMut(ChangeType, usize, usize)) {
+        while self.old_next < end {
+            if self.old_changed[self.old_next] {
+                if let Some((old_idx, new_idx)) = self.pending_removal.take() {
+                    emit(ChangeType::Removed, old_idx, new_idx);
+                }
+                self.pending_removal = Some((self.old_next, self.new_next));
+            } else if let Some((old_idx, new_idx)) = self.pending_removal.take() {
+                emit(ChangeType::Removed, old_idx, new_idx);
+            }
+            self.old_next += 1;
+        }
+    }
+
+    fn new_through(&mut self, end: usize, emit: &mut impl FnMut(ChangeType, usize, usize)) {
+        while self.new_next < end {
+            let pending = self.pending_removal.take();
+            match (self.new_changed[self.new_next], pending) {
+                (true, Some((old_idx, _))) => emit(ChangeType::Modified, old_idx, self.new_next),
+                (true, None) => emit(ChangeType::Added, self.old_next, self.new_next),
+                (false, pending) => {
+                    if let Some((old_idx, new_idx)) = pending {
+                        emit(ChangeType::Removed, old_idx, new_idx);
+                    }
+                    emit(ChangeType::Unchanged, self.old_next, self.new_next);
+                }
+            }
+            self.new_next += 1;
+        }
+    }
+}
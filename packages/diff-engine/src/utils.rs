@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::time::{Duration, Instant};
+use unicode_width::UnicodeWidthChar;
 use wasm_bindgen::prelude::*;
 
 /// Performance timer for measuring diff operations
@@ -50,6 +52,12 @@ impl PerformanceTimer {
     }
 }
 
+impl Default for PerformanceTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Memory usage tracker
 pub struct MemoryTracker {
     initial_usage: usize,
@@ -89,6 +97,12 @@ impl MemoryTracker {
     }
 }
 
+impl Default for MemoryTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Text processing utilities
 pub struct TextUtils;
 
@@ -128,6 +142,19 @@ impl TextUtils {
         text.replace("\r\n", "\n").replace('\r', "\n")
     }
 
+    /// Display columns a line occupies, expanding tabs to the next multiple
+    /// of `tab_width` and counting wide (CJK) characters as two columns
+    pub fn visual_width(line: &str, tab_width: usize) -> usize {
+        let tab_width = tab_width.max(1);
+        line.chars().fold(0, |column, c| {
+            if c == '\t' {
+                column + tab_width - column % tab_width
+            } else {
+                column + c.width().unwrap_or(0)
+            }
+        })
+    }
+
     /// Check if text is likely binary
     pub fn is_binary(text: &[u8]) -> bool {
         // Check for null bytes or high percentage of non-printable characters
@@ -215,6 +242,54 @@ impl TextUtils {
         let step = lines.len() / count;
         lines.iter().step_by(step).copied().collect()
     }
+
+    /// Approximate how many separate changed regions a diff of `a` and `b`
+    /// would have, in near-linear time
+    ///
+    /// Lines are compared by hash. Walking `b`, a line that doesn't continue
+    /// the current match resyncs on its next occurrence in `a`, skipping the
+    /// lines in between as removed; a line with no later occurrence counts as
+    /// added. Repeated lines (blanks, closing braces) can resync early, so
+    /// the estimate errs low for heavily rewritten text.
+    pub fn estimate_change_regions(a: &str, b: &str) -> usize {
+        let old_hashes: Vec<u64> = a.lines().map(WasmUtils::hash64).collect();
+        let mut positions: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, &hash) in old_hashes.iter().enumerate() {
+            positions.entry(hash).or_default().push(i);
+        }
+
+        let mut regions = 0;
+        let mut in_change = false;
+        let mut old_idx = 0;
+        for line in b.lines() {
+            let hash = WasmUtils::hash64(line);
+            let resync = if old_hashes.get(old_idx) == Some(&hash) {
+                Some(old_idx)
+            } else {
+                positions.get(&hash).and_then(|found| {
+                    let next = found.partition_point(|&i| i < old_idx);
+                    found.get(next).copied()
+                })
+            };
+
+            if resync != Some(old_idx) && !in_change {
+                regions += 1;
+            }
+            match resync {
+                // Lines skipped in `a` were removed, within the region just counted
+                Some(i) => {
+                    old_idx = i + 1;
+                    in_change = false;
+                }
+                None => in_change = true,
+            }
+        }
+
+        if old_idx < old_hashes.len() && !in_change {
+            regions += 1;
+        }
+        regions
+    }
 }
 
 /// Simple Levenshtein distance calculation
@@ -289,6 +364,18 @@ impl WasmUtils {
         }
     }
 
+    /// Display width of a line, accounting for tab stops and wide characters
+    #[wasm_bindgen(js_name = visualWidth)]
+    pub fn visual_width(line: &str, tab_width: usize) -> usize {
+        TextUtils::visual_width(line, tab_width)
+    }
+
+    /// Rough count of changed regions between two texts, to warn before a big diff
+    #[wasm_bindgen(js_name = estimateChangeRegions)]
+    pub fn estimate_change_regions(a: &str, b: &str) -> usize {
+        TextUtils::estimate_change_regions(a, b)
+    }
+
     /// Calculate hash of a string
     #[wasm_bindgen(js_name = hash)]
     pub fn hash(text: &str) -> u32 {
@@ -300,6 +387,19 @@ impl WasmUtils {
         }
         hash
     }
+
+    /// Calculate a 64-bit hash of a string, for cache keys where a 32-bit
+    /// collision would serve the wrong result
+    #[wasm_bindgen(js_name = hash64)]
+    pub fn hash64(text: &str) -> u64 {
+        // FNV-1a, 64-bit variant
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in text.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
 }
 
 /// Batch processor for handling operations in chunks
@@ -387,7 +487,7 @@ impl<K: std::hash::Hash + Eq + Clone, V> LruCache<K, V> {
 
     /// Evict least recently used item
     fn evict_lru(&mut self) {
-        if let Some((lru_key, _)) = self.items
+        if let Some(lru_key) = self.items
             .iter()
             .min_by_key(|(_, (_, order))| *order)
             .map(|(k, _)| k.clone())
@@ -431,6 +531,48 @@ mod tests {
         assert!(!TextUtils::is_binary(b"hello world"));
     }
 
+    #[test]
+    fn test_visual_width() {
+        assert_eq!(TextUtils::visual_width("\tfoo", 4), 7);
+        assert_eq!(TextUtils::visual_width("ab\tc", 4), 5);
+        assert_eq!(TextUtils::visual_width("\tfoo", 8), 11);
+
+        // Full-width characters take two columns
+        assert_eq!(TextUtils::visual_width("a漢b", 4), 4);
+        assert_eq!(TextUtils::visual_width("漢\tx", 4), 5);
+    }
+
+    #[test]
+    fn test_estimate_change_regions() {
+        let old_text: String = (0..500).map(|i| format!("line {}\n", i)).collect();
+
+        let few = old_text
+            .replace("line 10\n", "line ten\n")
+            .replace("line 200\n", "")
+            .replace("line 450\n", "line 450\ninserted\nlines\n");
+        assert_eq!(TextUtils::estimate_change_regions(&old_text, &few), 3);
+
+        let many: String = (0..500)
+            .map(|i| if i % 5 == 0 { format!("changed {}\n", i) } else { format!("line {}\n", i) })
+            .collect();
+        let estimate = TextUtils::estimate_change_regions(&old_text, &many);
+        assert!((90..=100).contains(&estimate), "{}", estimate);
+
+        assert_eq!(TextUtils::estimate_change_regions(&old_text, &old_text), 0);
+        assert_eq!(TextUtils::estimate_change_regions(&old_text, "line 0\n"), 1);
+    }
+
+    #[test]
+    fn test_hash64_separates_32bit_collisions() {
+        for (a, b) in [("costarring", "liquid"), ("declinate", "macallums"), ("altarage", "zinke")] {
+            assert_eq!(WasmUtils::hash(a), WasmUtils::hash(b));
+            assert_ne!(WasmUtils::hash64(a), WasmUtils::hash64(b));
+        }
+
+        assert_eq!(WasmUtils::hash64(""), 0xcbf29ce484222325);
+        assert_eq!(WasmUtils::hash64("a"), 0xaf63dc4c8601ec8c);
+    }
+
     #[test]
     fn test_batch_processor() {
         let items = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
@@ -1,5 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+use crate::diff::DiffResult;
+
+/// Frames of scrolling at the current velocity the leading buffer covers
+const VELOCITY_LOOKAHEAD_FRAMES: f64 = 8.0;
+
 /// Virtual scrolling manager for handling large diffs efficiently
 pub struct VirtualScroll {
     total_lines: usize,
@@ -31,22 +36,74 @@ pub struct VirtualItem {
 impl VirtualScroll {
     /// Create a new virtual scroll manager
     pub fn new(total_lines: usize, viewport_height: usize) -> Self {
-        Self {
+        // 20px lines, with 10 extra lines rendered either side for smooth scrolling
+        Self::with_config(total_lines, viewport_height, 20.0, 10)
+    }
+
+    /// Create a virtual scroll manager with a custom line height and buffer size
+    pub fn with_config(
+        total_lines: usize,
+        viewport_height: usize,
+        line_height: f64,
+        buffer_size: usize,
+    ) -> Self {
+        let mut scroll = Self {
             total_lines,
             viewport_height,
-            line_height: 20.0, // Default line height in pixels
-            buffer_size: 10,   // Number of extra lines to render for smooth scrolling
+            line_height,
+            buffer_size,
             visible_range: VisibleRange {
                 start_index: 0,
                 end_index: viewport_height.min(total_lines),
                 offset_y: 0.0,
-                total_height: total_lines as f64 * 20.0,
+                total_height: 0.0,
             },
-        }
+        };
+        scroll.visible_range.total_height = scroll.total_height();
+        scroll
+    }
+
+    /// Total scrollable height of all lines
+    pub fn total_height(&self) -> f64 {
+        self.total_lines as f64 * self.line_height
     }
 
     /// Update viewport position and return new visible range
     pub fn update_viewport(&mut self, scroll_top: f64, viewport_height: usize) -> VisibleRange {
+        self.update_buffered(scroll_top, viewport_height, self.buffer_size, self.buffer_size)
+    }
+
+    /// Update viewport position, growing the buffer in the scroll direction
+    /// with `velocity` (pixels per frame, negative when scrolling up)
+    ///
+    /// The leading side gets enough extra lines for the next few frames, at
+    /// most two viewports' worth; the trailing side, and both sides at rest,
+    /// keep the configured buffer size.
+    pub fn update_viewport_with_velocity(
+        &mut self,
+        scroll_top: f64,
+        viewport_height: usize,
+        velocity: f64,
+    ) -> VisibleRange {
+        let visible_lines = (viewport_height as f64 / self.line_height).ceil() as usize;
+        let lookahead = (velocity.abs() * VELOCITY_LOOKAHEAD_FRAMES / self.line_height).ceil() as usize;
+        let leading = self.buffer_size + lookahead.min(visible_lines * 2);
+
+        if velocity < 0.0 {
+            self.update_buffered(scroll_top, viewport_height, leading, self.buffer_size)
+        } else {
+            self.update_buffered(scroll_top, viewport_height, self.buffer_size, leading)
+        }
+    }
+
+    /// Set the visible range with `before` and `after` extra lines around the viewport
+    fn update_buffered(
+        &mut self,
+        scroll_top: f64,
+        viewport_height: usize,
+        before: usize,
+        after: usize,
+    ) -> VisibleRange {
         self.viewport_height = viewport_height;
         
         // Calculate visible line range
@@ -54,14 +111,14 @@ impl VirtualScroll {
         let visible_lines = (viewport_height as f64 / self.line_height).ceil() as usize;
         
         // Apply buffer for smooth scrolling
-        let buffered_start = start_line.saturating_sub(self.buffer_size);
-        let buffered_end = (start_line + visible_lines + self.buffer_size).min(self.total_lines);
+        let buffered_start = start_line.saturating_sub(before);
+        let buffered_end = (start_line + visible_lines + after).min(self.total_lines);
         
         self.visible_range = VisibleRange {
             start_index: buffered_start,
             end_index: buffered_end,
             offset_y: buffered_start as f64 * self.line_height,
-            total_height: self.total_lines as f64 * self.line_height,
+            total_height: self.total_height(),
         };
 
         self.visible_range.clone()
@@ -75,13 +132,18 @@ impl VirtualScroll {
     /// Set line height for accurate calculations
     pub fn set_line_height(&mut self, height: f64) {
         self.line_height = height;
-        self.visible_range.total_height = self.total_lines as f64 * height;
+        self.visible_range.total_height = self.total_height();
+    }
+
+    /// Set the number of extra lines rendered above and below the viewport
+    pub fn set_buffer_size(&mut self, buffer_size: usize) {
+        self.buffer_size = buffer_size;
     }
 
     /// Update total number of lines
     pub fn set_total_lines(&mut self, total_lines: usize) {
         self.total_lines = total_lines;
-        self.visible_range.total_height = total_lines as f64 * self.line_height;
+        self.visible_range.total_height = self.total_height();
         
         // Adjust end index if necessary
         if self.visible_range.end_index > total_lines {
@@ -107,7 +169,7 @@ impl VirtualScroll {
     /// Calculate scroll position for a specific line
     pub fn scroll_to_line(&self, line_index: usize) -> f64 {
         if line_index >= self.total_lines {
-            return self.visible_range.total_height;
+            return self.total_height();
         }
         
         line_index as f64 * self.line_height
@@ -153,7 +215,8 @@ impl DynamicVirtualScroll {
         }
         
         let total_height = cached_positions.last().copied().unwrap_or(0.0);
-        
+        let end_index = viewport_height.min(line_heights.len());
+
         Self {
             line_heights,
             viewport_height,
@@ -161,7 +224,7 @@ impl DynamicVirtualScroll {
             cached_positions,
             visible_range: VisibleRange {
                 start_index: 0,
-                end_index: viewport_height.min(line_heights.len()),
+                end_index,
                 offset_y: 0.0,
                 total_height,
             },
@@ -249,6 +312,64 @@ impl DynamicVirtualScroll {
     }
 }
 
+/// Maps rendered line indices to the hunks they belong to
+///
+/// Each hunk renders as one header line followed by its changes, so a
+/// virtual scroller over the whole diff can find which hunk (and whether its
+/// header) is at a given line without walking every change.
+pub struct HunkIndex {
+    /// Rendered index of each hunk's header line, in ascending order
+    hunk_starts: Vec<usize>,
+    total_lines: usize,
+}
+
+impl HunkIndex {
+    /// Build the index from a diff result
+    pub fn new(result: &DiffResult) -> Self {
+        let mut hunk_starts = Vec::with_capacity(result.hunks.len());
+        let mut line = 0;
+
+        for hunk in &result.hunks {
+            hunk_starts.push(line);
+            line += 1 + hunk.changes.len();
+        }
+
+        Self {
+            hunk_starts,
+            total_lines: line,
+        }
+    }
+
+    /// Total rendered lines, headers included
+    pub fn total_lines(&self) -> usize {
+        self.total_lines
+    }
+
+    /// Index of the hunk containing the given rendered line
+    pub fn hunk_at_line(&self, line: usize) -> Option<usize> {
+        if line >= self.total_lines {
+            return None;
+        }
+
+        match self.hunk_starts.binary_search(&line) {
+            Ok(hunk) => Some(hunk),
+            Err(next) => Some(next - 1),
+        }
+    }
+
+    /// Whether the given rendered line is a hunk header
+    pub fn is_header(&self, line: usize) -> bool {
+        self.hunk_starts.binary_search(&line).is_ok()
+    }
+
+    /// Rendered line range of a hunk, header included
+    pub fn hunk_range(&self, hunk: usize) -> Option<std::ops::Range<usize>> {
+        let start = *self.hunk_starts.get(hunk)?;
+        let end = self.hunk_starts.get(hunk + 1).copied().unwrap_or(self.total_lines);
+        Some(start..end)
+    }
+}
+
 /// Chunked virtual scroll for extremely large datasets
 pub struct ChunkedVirtualScroll {
     total_lines: usize,
@@ -348,10 +469,62 @@ mod tests {
         assert!(range.end_index <= 10);
     }
 
+    #[test]
+    fn test_virtual_scroll_with_config() {
+        let mut scroll = VirtualScroll::with_config(1000, 400, 10.0, 5);
+
+        // 400px / 10px = 40 visible lines starting at line 50, plus 5 either side
+        let range = scroll.update_viewport(500.0, 400);
+        assert_eq!(range.start_index, 45);
+        assert_eq!(range.end_index, 95);
+        assert_eq!(range.offset_y, 450.0);
+        assert_eq!(range.total_height, 10000.0);
+    }
+
+    #[test]
+    fn test_virtual_scroll_set_buffer_size() {
+        let mut scroll = VirtualScroll::new(1000, 400);
+        scroll.set_buffer_size(0);
+
+        let range = scroll.update_viewport(200.0, 400);
+        assert_eq!(range.start_index, 10);
+        assert_eq!(range.end_index, 30);
+    }
+
+    #[test]
+    fn test_velocity_grows_leading_buffer() {
+        let mut scroll = VirtualScroll::with_config(10_000, 400, 20.0, 10);
+        // 20 visible lines starting at line 500
+        let at_rest = scroll.update_viewport_with_velocity(10_000.0, 400, 0.0);
+        assert_eq!((at_rest.start_index, at_rest.end_index), (490, 530));
+
+        let slow = scroll.update_viewport_with_velocity(10_000.0, 400, 5.0);
+        let fast = scroll.update_viewport_with_velocity(10_000.0, 400, 200.0);
+        assert!(fast.end_index > slow.end_index && slow.end_index > at_rest.end_index);
+        assert_eq!(fast.start_index, 490);
+        // Capped at two viewports beyond the usual buffer
+        assert_eq!(fast.end_index, 570);
+
+        let upward = scroll.update_viewport_with_velocity(10_000.0, 400, -200.0);
+        assert_eq!((upward.start_index, upward.end_index), (450, 530));
+    }
+
+    #[test]
+    fn test_virtual_scroll_total_height() {
+        let mut scroll = VirtualScroll::new(100, 400);
+        assert_eq!(scroll.total_height(), 2000.0);
+
+        scroll.set_line_height(15.0);
+        scroll.set_total_lines(200);
+        assert_eq!(scroll.total_height(), 3000.0);
+        assert_eq!(scroll.get_visible_range().total_height, 3000.0);
+        assert_eq!(scroll.scroll_to_line(500), 3000.0);
+    }
+
     #[test]
     fn test_dynamic_virtual_scroll() {
         let heights = vec![20.0, 30.0, 25.0, 20.0, 40.0];
-        let mut scroll = DynamicVirtualScroll::new(heights, 100);
+        let scroll = DynamicVirtualScroll::new(heights, 100);
         
         let items = scroll.get_virtual_items();
         assert_eq!(items.len(), 5);
@@ -359,6 +532,40 @@ mod tests {
         assert_eq!(items[1].height, 30.0);
     }
 
+    #[test]
+    fn test_hunk_index() {
+        use crate::diff::{compute_diff, DiffOptions};
+
+        let old: Vec<String> = (0..60).map(|i| format!("line {}", i)).collect();
+        let mut new = old.clone();
+        new[5] = "line 5!".to_string();
+        new[25] = "line 25!".to_string();
+        new.insert(26, "inserted".to_string());
+        new[51] = "line 50!".to_string();
+
+        let options = DiffOptions {
+            context_lines: 0,
+            ..DiffOptions::default()
+        };
+        let result = compute_diff(&old.join("\n"), &new.join("\n"), &options).unwrap();
+        assert_eq!(result.hunks.len(), 3);
+
+        // Rendered as: header + 1 change, header + 2 changes, header + 1 change
+        let index = HunkIndex::new(&result);
+        assert_eq!(index.total_lines(), 7);
+
+        assert_eq!(index.hunk_at_line(0), Some(0));
+        assert!(index.is_header(0));
+        assert_eq!(index.hunk_at_line(1), Some(0));
+        assert_eq!(index.hunk_at_line(2), Some(1));
+        assert!(index.is_header(2));
+        assert_eq!(index.hunk_at_line(4), Some(1));
+        assert!(!index.is_header(4));
+        assert_eq!(index.hunk_at_line(6), Some(2));
+        assert_eq!(index.hunk_at_line(7), None);
+        assert_eq!(index.hunk_range(1), Some(2..5));
+    }
+
     #[test]
     fn test_chunked_virtual_scroll() {
         let scroll = ChunkedVirtualScroll::new(10000, 100, 20);
@@ -0,0 +1,313 @@
+use std::fmt;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::diff::{ChangeType, DiffError};
+use crate::myers::MyersDiff;
+use crate::syntax::SyntaxHighlighter;
+
+/// A changed span within a line, in byte offsets
+///
+/// `Removed` spans index into the old line, `Added` spans into the new one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WordSpan {
+    pub start: usize,
+    pub end: usize,
+    pub change_type: ChangeType,
+}
+
+/// What counts as one word when diffing within a line
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WordBoundary {
+    /// Runs of letters, digits and `_`; any other character stands alone,
+    /// so `a.b`, `a->b` and `a::b` split around the operator
+    #[default]
+    Code,
+    /// Like `Code`, but an apostrophe between letters stays in the word, so
+    /// `don't` is one word
+    Prose,
+    /// Every match of the pattern is one word; the text between matches
+    /// splits like `Code`
+    Custom(WordPattern),
+}
+
+impl WordBoundary {
+    /// A `Custom` boundary, failing with `DiffError::InvalidFormat` if
+    /// `pattern` isn't a valid regex
+    pub fn custom(pattern: &str) -> Result<Self, DiffError> {
+        WordPattern::new(pattern).map(WordBoundary::Custom)
+    }
+}
+
+/// A compiled word pattern for `WordBoundary::Custom`, serialized as its source
+#[derive(Clone)]
+pub struct WordPattern(Regex);
+
+impl WordPattern {
+    pub fn new(pattern: &str) -> Result<Self, DiffError> {
+        Regex::new(pattern)
+            .map(WordPattern)
+            .map_err(|e| DiffError::InvalidFormat(format!("invalid word pattern `{}`: {}", pattern, e)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl fmt::Debug for WordPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("WordPattern").field(&self.as_str()).finish()
+    }
+}
+
+impl PartialEq for WordPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Serialize for WordPattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for WordPattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        WordPattern::new(&pattern).map_err(de::Error::custom)
+    }
+}
+
+/// A word with apostrophes inside it, like `don't` or `o’clock`
+static PROSE_WORD: Lazy<Regex> = Lazy::new(|| Regex::new(r"\w+(?:['’]\w+)+").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Word,
+    Space,
+    Punct,
+}
+
+/// Compute the changed word spans between two versions of a line
+///
+/// With a `language`, string literals are treated as single tokens so an
+/// edit inside one is reported as one changed string; `split_strings` dives
+/// inside them instead. Comments are always split into words, as `boundary`
+/// defines them.
+pub fn diff_words(
+    old_line: &str,
+    new_line: &str,
+    language: Option<&str>,
+    split_strings: bool,
+    boundary: &WordBoundary,
+) -> Vec<WordSpan> {
+    let strings = if split_strings {
+        None
+    } else {
+        language.and_then(|language| SyntaxHighlighter::new(language).ok())
+    };
+    diff_words_with(old_line, new_line, strings.as_ref(), boundary)
+}
+
+/// `diff_words` keeping the string literals `strings` finds whole, for
+/// callers that diff many lines with one highlighter
+pub(crate) fn diff_words_with(
+    old_line: &str,
+    new_line: &str,
+    strings: Option<&SyntaxHighlighter>,
+    boundary: &WordBoundary,
+) -> Vec<WordSpan> {
+    let tokens_of = |line: &str| {
+        let mut atomic = atomic_ranges(line, strings);
+        atomic.extend(word_ranges(line, boundary));
+        tokenize(line, &atomic)
+    };
+    diff_tokens(old_line, new_line, &tokens_of(old_line), &tokens_of(new_line))
+}
+
+/// Compute the changed character spans between two versions of a line, for
+/// edits inside a single long token such as a hash or URL
+///
+/// Spans are still byte offsets, always on character boundaries.
+pub fn diff_chars(old_line: &str, new_line: &str) -> Vec<WordSpan> {
+    let char_ranges = |line: &str| -> Vec<(usize, usize)> {
+        line.char_indices().map(|(i, c)| (i, i + c.len_utf8())).collect()
+    };
+    diff_tokens(old_line, new_line, &char_ranges(old_line), &char_ranges(new_line))
+}
+
+/// Diff two lines split into tokens by byte range, merging adjacent changed tokens
+fn diff_tokens(
+    old_line: &str,
+    new_line: &str,
+    old_tokens: &[(usize, usize)],
+    new_tokens: &[(usize, usize)],
+) -> Vec<WordSpan> {
+    let old_words: Vec<&str> = old_tokens.iter().map(|&(s, e)| &old_line[s..e]).collect();
+    let new_words: Vec<&str> = new_tokens.iter().map(|&(s, e)| &new_line[s..e]).collect();
+
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+
+    for (change_type, old_idx, new_idx) in MyersDiff::new(&old_words, &new_words).compute_diff() {
+        match change_type {
+            ChangeType::Removed => removed.push(old_tokens[old_idx]),
+            ChangeType::Added => added.push(new_tokens[new_idx]),
+            ChangeType::Modified | ChangeType::ReformatBlock => {
+                removed.push(old_tokens[old_idx]);
+                added.push(new_tokens[new_idx]);
+            }
+            ChangeType::Unchanged | ChangeType::Moved => {}
+        }
+    }
+
+    let mut spans = merge_ranges(removed, ChangeType::Removed);
+    spans.extend(merge_ranges(added, ChangeType::Added));
+    spans
+}
+
+/// Byte ranges that must not be split: the string literals `strings` finds
+pub(crate) fn atomic_ranges(line: &str, strings: Option<&SyntaxHighlighter>) -> Vec<(usize, usize)> {
+    let Some(highlighter) = strings else {
+        return Vec::new();
+    };
+
+    highlighter
+        .highlight(line)
+        .into_iter()
+        .filter(|(token_type, _, _, _)| token_type == "string")
+        .map(|(_, _, start, end)| (start, end))
+        .collect()
+}
+
+/// Byte ranges `boundary` makes single words beyond the `Code` ones
+fn word_ranges(line: &str, boundary: &WordBoundary) -> Vec<(usize, usize)> {
+    let regex = match boundary {
+        WordBoundary::Code => return Vec::new(),
+        WordBoundary::Prose => &*PROSE_WORD,
+        WordBoundary::Custom(pattern) => &pattern.0,
+    };
+    regex
+        .find_iter(line)
+        .filter(|m| !m.is_empty())
+        .map(|m| (m.start(), m.end()))
+        .collect()
+}
+
+/// Split a line into word, whitespace and punctuation tokens
+pub(crate) fn tokenize(line: &str, atomic: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if let Some(&(_, end)) = atomic.iter().find(|(s, _)| *s == start) {
+            while chars.peek().is_some_and(|&(i, _)| i < end) {
+                chars.next();
+            }
+            tokens.push((start, end));
+            continue;
+        }
+
+        let class = char_class(c);
+        let mut end = start + c.len_utf8();
+        if class != CharClass::Punct {
+            while let Some(&(i, next)) = chars.peek() {
+                if char_class(next) != class || atomic.iter().any(|(s, _)| *s == i) {
+                    break;
+                }
+                end = i + next.len_utf8();
+                chars.next();
+            }
+        }
+        tokens.push((start, end));
+    }
+
+    tokens
+}
+
+fn char_class(c: char) -> CharClass {
+    if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else if c.is_whitespace() {
+        CharClass::Space
+    } else {
+        CharClass::Punct
+    }
+}
+
+/// Join contiguous ranges into single spans
+fn merge_ranges(ranges: Vec<(usize, usize)>, change_type: ChangeType) -> Vec<WordSpan> {
+    let mut spans: Vec<WordSpan> = Vec::new();
+
+    for (start, end) in ranges {
+        match spans.last_mut() {
+            Some(last) if last.end == start => last.end = end,
+            _ => spans.push(WordSpan { start, end, change_type }),
+        }
+    }
+
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string_literal_is_single_token() {
+        let old_line = r#"let s = "hello world";"#;
+        let new_line = r#"let s = "hello there";"#;
+
+        let spans = diff_words(old_line, new_line, Some("rust"), false, &WordBoundary::Code);
+        assert_eq!(spans.len(), 2);
+
+        let removed = &spans[0];
+        assert_eq!(removed.change_type, ChangeType::Removed);
+        assert_eq!(&old_line[removed.start..removed.end], r#""hello world""#);
+
+        let added = &spans[1];
+        assert_eq!(added.change_type, ChangeType::Added);
+        assert_eq!(&new_line[added.start..added.end], r#""hello there""#);
+    }
+
+    #[test]
+    fn test_split_strings_dives_inside() {
+        let old_line = r#"let s = "hello world";"#;
+        let new_line = r#"let s = "hello there";"#;
+
+        let spans = diff_words(old_line, new_line, Some("rust"), true, &WordBoundary::Code);
+        assert_eq!(spans.len(), 2);
+        assert_eq!(&old_line[spans[0].start..spans[0].end], "world");
+        assert_eq!(&new_line[spans[1].start..spans[1].end], "there");
+    }
+
+    #[test]
+    fn test_char_spans_on_boundaries() {
+        let spans = diff_chars("naïve", "naive");
+        assert_eq!(
+            spans,
+            vec![
+                WordSpan { start: 2, end: 4, change_type: ChangeType::Removed },
+                WordSpan { start: 2, end: 3, change_type: ChangeType::Added },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comment_words_are_diffed() {
+        let old_line = "x = 1; // the quick fox";
+        let new_line = "x = 1; // the slow fox";
+
+        let spans = diff_words(old_line, new_line, Some("javascript"), false, &WordBoundary::Code);
+        assert_eq!(spans.len(), 2);
+        assert_eq!(&old_line[spans[0].start..spans[0].end], "quick");
+        assert_eq!(&new_line[spans[1].start..spans[1].end], "slow");
+    }
+}
@@ -6,7 +6,7 @@ wasm_bindgen_test_configure!(run_in_browser);
 #[wasm_bindgen_test]
 fn test_diff_engine_creation() {
     let engine = DiffEngine::new();
-    assert!(!engine.is_undefined());
+    assert!(!wasm_bindgen::JsValue::from(engine).is_undefined());
 }
 
 #[wasm_bindgen_test]
@@ -56,14 +56,89 @@ fn test_supported_languages() {
     assert!(!lang_list.is_undefined());
 }
 
+#[wasm_bindgen_test]
+fn test_similarity_callback() {
+    let mut engine = DiffEngine::new();
+    let old_text = "a\nkeep\nb\nkeep too\nc";
+    let new_text = "x\nkeep\ny\nkeep too\nz";
+
+    let change_types = |engine: &DiffEngine| {
+        let result: diff::DiffResult =
+            serde_wasm_bindgen::from_value(engine.compute_diff(old_text, new_text).unwrap()).unwrap();
+        result
+            .hunks
+            .iter()
+            .flat_map(|h| h.changes.iter().map(|c| c.change_type))
+            .filter(|t| *t != diff::ChangeType::Unchanged)
+            .collect::<Vec<_>>()
+    };
+
+    // Single letters share nothing by edit distance
+    assert!(!change_types(&engine).contains(&diff::ChangeType::Modified));
+
+    engine.set_similarity(Some(js_sys::Function::new_with_args("a, b", "return 1.0;")));
+    let types = change_types(&engine);
+    assert_eq!(types.len(), 3);
+    assert!(types.iter().all(|t| *t == diff::ChangeType::Modified));
+}
+
+#[wasm_bindgen_test]
+fn test_engine_reuse() {
+    let mut engine = DiffEngine::new();
+    let has_changes = |engine: &DiffEngine| {
+        let result: diff::DiffResult =
+            serde_wasm_bindgen::from_value(engine.compute_diff("Hello\nWorld", "hello\nworld").unwrap())
+                .unwrap();
+        result.stats.has_changes
+    };
+
+    let options = diff::DiffOptions {
+        ignore_case: true,
+        ..diff::DiffOptions::default()
+    };
+    engine.set_options(serde_wasm_bindgen::to_value(&options).unwrap()).unwrap();
+    assert!(!has_changes(&engine));
+
+    engine.reset_options();
+    assert!(has_changes(&engine));
+}
+
 #[cfg(test)]
 mod native_tests {
     use super::*;
     use diffit_diff_engine::diff::*;
+    // The crate root's JSON `compute_diff` is called by its full path
+    use diffit_diff_engine::diff::compute_diff;
     use diffit_diff_engine::myers::*;
     use diffit_diff_engine::streaming::*;
     use diffit_diff_engine::virtual_scroll::*;
 
+    #[test]
+    fn test_similarity_callback() {
+        use diffit_diff_engine::syntax::HighlighterCache;
+
+        let old_text = "a\nkeep\nb\nkeep too\nc";
+        let new_text = "x\nkeep\ny\nkeep too\nz";
+        let options = DiffOptions::default();
+        let changed = |result: DiffResult| {
+            result
+                .hunks
+                .iter()
+                .flat_map(|h| h.changes.iter().map(|c| c.change_type))
+                .filter(|t| *t != ChangeType::Unchanged)
+                .collect::<Vec<_>>()
+        };
+
+        // Single letters share nothing by edit distance
+        let plain = changed(compute_diff(old_text, new_text, &options).unwrap());
+        assert!(!plain.contains(&ChangeType::Modified));
+
+        let always = |_: &str, _: &str| 1.0;
+        let mut highlighters = HighlighterCache::new();
+        let scored = compute_diff_with_similarity(old_text, new_text, &options, &mut highlighters, &always);
+        assert_eq!(changed(scored.unwrap()), vec![ChangeType::Modified; 3]);
+    }
+
     #[test]
     fn test_myers_algorithm() {
         let old_lines = vec!["a", "b", "c"];
@@ -86,9 +161,10 @@ mod native_tests {
             semantic_diff: true,
             syntax_highlight: false,
             language: Some("rust".to_string()),
-            word_diff: false,
+            intra_line: IntraLineMode::None,
             line_numbers: true,
             max_file_size: 1024 * 1024,
+            ..DiffOptions::default()
         };
         
         let old_text = "  Hello World  ";
@@ -125,8 +201,16 @@ mod native_tests {
         assert_eq!(range.start_index, 0);
         assert!(range.end_index > 0);
         
-        let range = scroll.update_viewport(200.0, 20);
-        assert!(range.start_index > 0);
+        // Line 20 is at the top, with the 10-line buffer above it
+        let range = scroll.update_viewport(400.0, 20);
+        assert_eq!(range.start_index, 10);
+    }
+
+    #[test]
+    fn test_scroll_manager_total_height() {
+        let manager = VirtualScrollManager::with_config(120, 400, 18.0, 5);
+        assert_eq!(manager.total_height(), 2160.0);
+        assert_eq!(VirtualScrollManager::new(100, 400).total_height(), 2000.0);
     }
 
     #[test]
@@ -148,55 +232,2489 @@ mod native_tests {
     }
 
     #[test]
-    fn test_binary_detection() {
-        let binary_data = vec![0u8, 1, 2, 3, 255, 254];
-        let text_data = b"Hello, world!";
-        
-        assert!(crate::diff::is_binary(&String::from_utf8_lossy(&binary_data)));
-        assert!(!crate::diff::is_binary(&String::from_utf8_lossy(text_data)));
+    fn test_emit_all_lines() {
+        let options = DiffOptions {
+            emit_all_lines: true,
+            ..DiffOptions::default()
+        };
+
+        let old_text = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj";
+        let new_text = "a\nb\nc\nd\ne\nadded\nf\ng\nh\ni\nj";
+
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        assert_eq!(result.hunks.len(), 1);
+
+        let changes = &result.hunks[0].changes;
+        assert_eq!(changes.len(), new_text.lines().count());
+        assert_eq!(changes.iter().filter(|c| c.change_type == ChangeType::Added).count(), 1);
+
+        let added = changes.iter().find(|c| c.change_type == ChangeType::Added).unwrap();
+        assert_eq!(added.content, "added");
+        assert_eq!(added.new_line_number, Some(6));
+        assert_eq!(changes.last().unwrap().old_line_number, Some(10));
+        assert_eq!(changes.last().unwrap().new_line_number, Some(11));
     }
 
     #[test]
-    fn test_syntax_highlighting() {
-        use diffit_diff_engine::syntax::*;
-        
-        let highlighter = SyntaxHighlighter::new("rust");
-        assert!(highlighter.is_ok());
-        
-        let highlighter = highlighter.unwrap();
-        let tokens = highlighter.highlight("fn main() { println!(\"Hello\"); }");
-        
-        assert!(!tokens.is_empty());
-        assert!(tokens.iter().any(|t| t.token_type == "keyword"));
+    fn test_line_numbers_disabled() {
+        let options = DiffOptions {
+            line_numbers: false,
+            ..DiffOptions::default()
+        };
+
+        let result = compute_diff("a\nb\nc\nd", "a\nx\nc\nd\ne", &options).unwrap();
+        assert!(!result.hunks.is_empty());
+        for change in result.hunks.iter().flat_map(|h| &h.changes) {
+            assert_eq!(change.old_line_number, None);
+            assert_eq!(change.new_line_number, None);
+        }
+
+        let options = DiffOptions {
+            emit_all_lines: true,
+            ..options
+        };
+        let result = compute_diff("a\nb", "a\nc", &options).unwrap();
+        assert!(result.hunks[0]
+            .changes
+            .iter()
+            .all(|c| c.old_line_number.is_none() && c.new_line_number.is_none()));
     }
 
     #[test]
-    fn test_semantic_analysis() {
-        use diffit_diff_engine::semantic::*;
-        
-        let analyzer = SemanticAnalyzer::new(Some("rust"));
-        let info = analyzer.extract_semantic_info("pub fn test() {", &[]);
-        
-        assert!(info.is_some());
-        let info = info.unwrap();
-        assert_eq!(info.entity_type, "function");
-        assert_eq!(info.entity_name, Some("test".to_string()));
+    fn test_hunk_blocks() {
+        let old_text = "keep\nfn old() {\n    one();\n    two();\n}\nkeep\nkeep\nkeep";
+        let new_text = "keep\nreplacement\nbody\nkeep\nkeep\nkeep\ntail";
+
+        let options = DiffOptions {
+            emit_all_lines: true,
+            ..DiffOptions::default()
+        };
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        let blocks: Vec<ChangeBlock> = result.hunks.iter().flat_map(|h| h.blocks()).collect();
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].kind, BlockKind::Replaced);
+        assert_eq!(blocks[0].old_lines, 4);
+        assert_eq!(blocks[0].new_lines, 2);
+        assert_eq!(blocks[1].kind, BlockKind::Added);
+        assert_eq!(blocks[1].new_lines, 1);
     }
 
     #[test]
-    fn test_performance() {
-        use std::time::Instant;
-        
-        let old_text = "a\n".repeat(1000);
-        let new_text = "b\n".repeat(1000);
-        
-        let start = Instant::now();
+    fn test_git_patch() {
+        let patch = |old_text: &str, new_text: &str, path: &str| {
+            let result = compute_diff(old_text, new_text, &DiffOptions::default()).unwrap();
+            to_git_patch(&result, old_text, new_text, path, path, 3)
+        };
+
+        let old_text = "a\nb\nc\nd\ne\nf\ng\nh";
+        let new_text = "a\nb\nc\nD\ne\nf\ng\nh\n";
+        assert_eq!(
+            patch(old_text, new_text, "src/x.txt"),
+            "diff --git a/src/x.txt b/src/x.txt\n--- a/src/x.txt\n+++ b/src/x.txt\n\
+             @@ -1,8 +1,8 @@\n a\n b\n c\n-d\n+D\n e\n f\n g\n-h\n\\ No newline at end of file\n+h\n"
+        );
+
+        let old_text: String = (0..50).map(|i| format!("line {}\n", i)).collect();
+        let new_text = old_text
+            .replacen("line 3\n", "", 1)
+            .replacen("line 25\n", "line 25\nextra\n", 1)
+            .replacen("line 48\n", "changed\n", 1);
+        assert_eq!(
+            patch(&old_text, &new_text, "f"),
+            "diff --git a/f b/f\n--- a/f\n+++ b/f\n\
+             @@ -1,7 +1,6 @@\n line 0\n line 1\n line 2\n-line 3\n line 4\n line 5\n line 6\n\
+             @@ -24,6 +23,7 @@\n line 23\n line 24\n line 25\n+extra\n line 26\n line 27\n line 28\n\
+             @@ -46,5 +46,5 @@\n line 45\n line 46\n line 47\n-line 48\n+changed\n line 49\n"
+        );
+
+        // Created and deleted files
+        assert_eq!(
+            patch("", "new\n", "n"),
+            "diff --git a/n b/n\nnew file mode 100644\n--- /dev/null\n+++ b/n\n@@ -0,0 +1,1 @@\n+new\n"
+        );
+        assert_eq!(
+            patch("old\n", "", "o"),
+            "diff --git a/o b/o\ndeleted file mode 100644\n--- a/o\n+++ /dev/null\n@@ -1,1 +0,0 @@\n-old\n"
+        );
+
+        assert!(patch("same", "same", "s").is_empty());
+
+        // Through the engine, with its context setting
+        let engine = DiffEngine::new();
+        assert_eq!(
+            engine.compute_git_patch("one\ntwo\n", "one\n2\n", "f", "f").unwrap(),
+            "diff --git a/f b/f\n--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n one\n-two\n+2\n"
+        );
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old_text: String = (1..=20).map(|i| format!("line {}\n", i)).collect();
+        let new_text = old_text.replacen("line 10\n", "line ten\n", 1).replacen("line 15\n", "", 1);
+        let result = compute_diff(&old_text, &new_text, &DiffOptions::default()).unwrap();
+
+        let diff = to_unified_diff(&result, &old_text, &new_text, "a.txt", "b.txt", 1);
+        assert_eq!(
+            diff,
+            "--- a.txt\n+++ b.txt\n\
+             @@ -9,3 +9,3 @@\n line 9\n-line 10\n+line ten\n line 11\n\
+             @@ -14,3 +14,2 @@\n line 14\n-line 15\n line 16\n"
+        );
+
+        // Wider context joins both changes into one hunk
+        let joined = to_unified_diff(&result, &old_text, &new_text, "a.txt", "b.txt", 2);
+        assert_eq!(joined.matches("@@ -").count(), 1);
+        assert!(joined.contains("@@ -8,10 +8,9 @@\n"));
+
+        let same = compute_diff(&old_text, &old_text, &DiffOptions::default()).unwrap();
+        assert!(to_unified_diff(&same, &old_text, &old_text, "a.txt", "b.txt", 3).is_empty());
+
+        // Lines matched under normalization are still written as they changed
+        let options = DiffOptions {
+            ignore_case: true,
+            ..DiffOptions::default()
+        };
+        let result = compute_diff("a\nb\nc\n", "a\nB\nc", &options).unwrap();
+        assert!(result.hunks.is_empty());
+        assert_eq!(
+            to_unified_diff(&result, "a\nb\nc\n", "a\nB\nc", "a", "b", 0),
+            "--- a\n+++ b\n@@ -2,2 +2,2 @@\n-b\n-c\n+B\n+c\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn test_word_diff_spans() {
+        use diffit_diff_engine::word_diff::WordSpan;
+
+        let options = DiffOptions {
+            intra_line: IntraLineMode::Word,
+            ..DiffOptions::default()
+        };
+        let spans_of = |old_line: &str, new_line: &str| -> Vec<WordSpan> {
+            let result = compute_diff(old_line, new_line, &options).unwrap();
+            let change = &result.hunks[0].changes[0];
+            assert_eq!(change.change_type, ChangeType::Modified);
+            change.intra_line_spans.clone().unwrap()
+        };
+
+        // One word changed in the middle
+        let spans = spans_of("the quick brown fox", "the quick red fox");
+        assert_eq!(
+            spans,
+            vec![
+                WordSpan { start: 10, end: 15, change_type: ChangeType::Removed },
+                WordSpan { start: 10, end: 13, change_type: ChangeType::Added },
+            ]
+        );
+
+        // Reordered words show as removed from one place and added at the
+        // other, leaving the operator between them unchanged
+        let old_line = "let total = price + tax;";
+        let new_line = "let total = tax + price;";
+        let spans = spans_of(old_line, new_line);
+        let texts = |change_type: ChangeType, line: &str| -> Vec<String> {
+            spans
+                .iter()
+                .filter(|span| span.change_type == change_type)
+                .map(|span| line[span.start..span.end].to_string())
+                .collect()
+        };
+        assert_eq!(texts(ChangeType::Removed, old_line), vec!["price", "tax"]);
+        assert_eq!(texts(ChangeType::Added, new_line), vec!["tax", "price"]);
+
+        let result = compute_diff("the quick brown fox", "the quick red fox", &DiffOptions::default()).unwrap();
+        assert!(result.hunks[0].changes[0].intra_line_spans.is_none());
+    }
+
+    #[test]
+    fn test_word_diff_split_strings() {
+        let old_line = r#"let s = "hello world";"#;
+        let new_line = r#"let s = "hello there";"#;
+        let changed = |word_diff_split_strings: bool| -> Vec<String> {
+            let options = DiffOptions {
+                intra_line: IntraLineMode::Word,
+                language: Some("rust".to_string()),
+                word_diff_split_strings,
+                ..DiffOptions::default()
+            };
+            let result = compute_diff(old_line, new_line, &options).unwrap();
+            let change = &result.hunks[0].changes[0];
+            assert_eq!(change.change_type, ChangeType::Modified);
+            change
+                .intra_line_spans
+                .as_ref()
+                .unwrap()
+                .iter()
+                .filter(|span| span.change_type == ChangeType::Removed)
+                .map(|span| old_line[span.start..span.end].to_string())
+                .collect()
+        };
+
+        // The edited string is one change by default, one word when split
+        assert_eq!(changed(false), vec![r#""hello world""#]);
+        assert_eq!(changed(true), vec!["world"]);
+    }
+
+    #[test]
+    fn test_word_boundaries() {
+        use diffit_diff_engine::word_diff::WordBoundary;
+
+        let changed_words = |old_line: &str, new_line: &str, word_boundary: WordBoundary| -> Vec<String> {
+            let options = DiffOptions { intra_line: IntraLineMode::Word, word_boundary, ..DiffOptions::default() };
+            let result = compute_diff(old_line, new_line, &options).unwrap();
+            let change = &result.hunks[0].changes[0];
+            assert_eq!(change.change_type, ChangeType::Modified);
+            change
+                .intra_line_spans
+                .as_ref()
+                .unwrap()
+                .iter()
+                .filter(|span| span.change_type == ChangeType::Removed)
+                .map(|span| old_line[span.start..span.end].to_string())
+                .collect()
+        };
+
+        // Prose keeps contractions whole, code splits at the apostrophe
+        let old_line = "I really don't think we should ship it today";
+        let new_line = "I really won't think we should ship it today";
+        assert_eq!(changed_words(old_line, new_line, WordBoundary::Prose), ["don't"]);
+        assert_eq!(changed_words(old_line, new_line, WordBoundary::Code), ["don"]);
+
+        let old_line = "let port = config.server.port;";
+        let new_line = "let port = config.client.port;";
+        assert_eq!(changed_words(old_line, new_line, WordBoundary::Code), ["server"]);
+        let dotted = WordBoundary::custom(r"[\w.]+").unwrap();
+        assert_eq!(changed_words(old_line, new_line, dotted), ["config.server.port"]);
+
+        assert!(matches!(WordBoundary::custom("(unclosed"), Err(DiffError::InvalidFormat(_))));
+        assert!(serde_json::from_str::<DiffOptions>(r#"{ "wordBoundary": { "custom": "(unclosed" } }"#).is_err());
+        let options: DiffOptions = serde_json::from_str(r#"{ "wordBoundary": { "custom": "[\\w.]+" } }"#).unwrap();
+        assert_eq!(options.word_boundary, WordBoundary::custom(r"[\w.]+").unwrap());
+    }
+
+    #[test]
+    fn test_char_intra_line_spans() {
+        let options = DiffOptions {
+            intra_line: IntraLineMode::Char,
+            ..DiffOptions::default()
+        };
+        let spans_of = |old_line: &str, new_line: &str| {
+            let result = compute_diff(old_line, new_line, &options).unwrap();
+            let change = &result.hunks[0].changes[0];
+            assert_eq!(change.change_type, ChangeType::Modified);
+            change.intra_line_spans.clone().unwrap()
+        };
+
+        let old_line = "commit 3f9a2c7e81b4d05f";
+        let new_line = "commit 3f9a2c7e91b4d05f";
+        let spans = spans_of(old_line, new_line);
+        assert_eq!(spans.len(), 2);
+        assert_eq!((spans[0].change_type, &old_line[spans[0].start..spans[0].end]), (ChangeType::Removed, "8"));
+        assert_eq!((spans[1].change_type, &new_line[spans[1].start..spans[1].end]), (ChangeType::Added, "9"));
+
+        // Offsets are bytes, on character boundaries
+        let old_line = "le café crème";
+        let new_line = "le cafè crème";
+        let spans = spans_of(old_line, new_line);
+        assert_eq!((spans[0].start, spans[0].end), (6, 8));
+        assert_eq!(&old_line[spans[0].start..spans[0].end], "é");
+        assert_eq!(&new_line[spans[1].start..spans[1].end], "è");
+
+        // Old JSON options keep working
+        let legacy: DiffOptions = serde_json::from_str(r#"{ "wordDiff": true }"#).unwrap();
+        assert_eq!(legacy.intra_line, IntraLineMode::Word);
+        let explicit: DiffOptions =
+            serde_json::from_str(r#"{ "wordDiff": true, "intraLine": "char" }"#).unwrap();
+        assert_eq!(explicit.intra_line, IntraLineMode::Char);
+    }
+
+    #[test]
+    fn test_semantic_insights() {
+        let left = "use std::fmt;\n\nfn bar() -> u32 {\n    1\n}\n\nfn keep(x: u32) {}\n";
+        let right = "use std::io;\n\nfn foo() -> u32 {\n    1\n}\n\nfn keep(x: u64) {}\n";
+        let request = serde_json::json!({
+            "left": left,
+            "right": right,
+            "options": { "language": "rust" },
+        });
+
+        let response: serde_json::Value =
+            serde_json::from_str(&diffit_diff_engine::compute_diff(&request.to_string())).unwrap();
+        let semantic = &response["insights"]["semantic"];
+        assert_eq!(semantic["functionsAdded"], serde_json::json!(["foo"]));
+        assert_eq!(semantic["functionsRemoved"], serde_json::json!(["bar"]));
+        assert_eq!(semantic["importsChanged"], 1);
+
+        let request = serde_json::json!({ "left": left, "right": right });
+        let response: serde_json::Value =
+            serde_json::from_str(&diffit_diff_engine::compute_diff(&request.to_string())).unwrap();
+        assert!(response["insights"]["semantic"].is_null());
+    }
+
+    #[test]
+    fn test_apply_patch_request() {
+        let apply = |request: serde_json::Value| -> serde_json::Value {
+            serde_json::from_str(&diffit_diff_engine::apply_patch(&request.to_string())).unwrap()
+        };
+        let patch = "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n";
+
+        let response = apply(serde_json::json!({ "original": "a\nb\nc\n", "patch": patch }));
+        assert_eq!(response["text"], "a\nB\nc\n");
+        assert!(response["error"].is_null());
+
+        let response = apply(serde_json::json!({ "original": "a\nx\nc\n", "patch": patch }));
+        assert!(response["text"].is_null());
+        assert!(response["error"].as_str().unwrap().starts_with("Patch application failed"));
+
+        let response: serde_json::Value =
+            serde_json::from_str(&diffit_diff_engine::apply_patch("{")).unwrap();
+        assert!(response["error"].as_str().unwrap().starts_with("Failed to parse request"));
+    }
+
+    #[test]
+    fn test_line_ranges() {
+        let old_text: String = (1..=50).map(|i| format!("line {}\n", i)).collect();
+        let new_text = old_text
+            .replace("line 5\n", "line 5;\n")
+            .replace("line 30\n", "line 30;\n");
+        let options = DiffOptions {
+            old_line_range: Some((20, 41)),
+            new_line_range: Some((20, 41)),
+            include_byte_offsets: true,
+            ..DiffOptions::default()
+        };
+
+        let result = compute_diff(&old_text, &new_text, &options).unwrap();
+        assert_eq!(result.hunks.len(), 1);
+        assert_eq!(result.stats.total_lines, 21);
+
+        let hunk = &result.hunks[0];
+        assert!(hunk.header.starts_with(&format!("@@ -{},", hunk.old_start)));
+        let change = &hunk.changes[hunk.leading_context().len()];
+        assert_eq!(change.content, "line 30;");
+        assert_eq!((change.old_line_number, change.new_line_number), (Some(30), Some(30)));
+        let range = change.new_byte_range.clone().unwrap();
+        assert_eq!(&new_text[range], "line 30;\n");
+
+        // Lines before the range don't affect alignment within it
+        let shifted = format!("extra\n{}", new_text);
+        let options = DiffOptions {
+            new_line_range: Some((21, 42)),
+            ..options
+        };
+        let result = compute_diff(&old_text, &shifted, &options).unwrap();
+        let hunk = &result.hunks[0];
+        let change = &hunk.changes[hunk.leading_context().len()];
+        assert_eq!((change.old_line_number, change.new_line_number), (Some(30), Some(31)));
+    }
+
+    #[test]
+    fn test_auto_collapse_hints() {
+        let block = |indent: &str| -> String {
+            (0..30).map(|i| format!("{}value_{} = {}\n", indent, i, i)).collect()
+        };
+        let spacer: String = (0..10).map(|i| format!("spacer {}\n", i)).collect();
+        let old_text = format!("{}{}let x = 1;\n", block("  "), spacer);
+        let new_text = format!("{}{}let x = 2;\n", block("    "), spacer);
+        let options = DiffOptions {
+            auto_collapse_threshold: Some(20),
+            syntax_highlight: false,
+            ..DiffOptions::default()
+        };
+
+        let result = compute_diff(&old_text, &new_text, &options).unwrap();
+        let hints: Vec<bool> = result.hunks.iter().map(|h| h.collapsed_by_default).collect();
+        assert_eq!(hints, vec![true, false]);
+
+        // A large hunk that declares a function stays open
+        let added: String = (0..30).map(|i| format!("    step_{}();\n", i)).collect();
+        let rust_options = DiffOptions {
+            language: Some("rust".to_string()),
+            ..options.clone()
+        };
+        let result = compute_diff("", &format!("fn added() {{\n{}}}\n", added), &rust_options).unwrap();
+        assert!(!result.hunks[0].collapsed_by_default);
+
+        let result = compute_diff(&old_text, &new_text, &DiffOptions::default()).unwrap();
+        assert!(result.hunks.iter().all(|h| !h.collapsed_by_default));
+    }
+
+    #[test]
+    fn test_options_preset_with_overrides() {
+        let options: DiffOptions = serde_json::from_str(
+            r#"{ "preset": "code_review", "ignoreWhitespace": true, "wordDiff": false }"#,
+        )
+        .unwrap();
+
+        assert_eq!(options.preset.as_deref(), Some("code_review"));
+        assert!(options.ignore_whitespace);
+        assert_eq!(options.intra_line, IntraLineMode::None);
+        // Untouched fields come from the preset, not the defaults
+        assert!(options.include_enclosing_signature);
+        assert!(options.mark_whitespace);
+        assert_eq!(options.context_lines, 3);
+
+        let plain: DiffOptions = serde_json::from_str(r#"{ "contextLines": 1 }"#).unwrap();
+        assert_eq!(plain.context_lines, 1);
+        assert!(!plain.include_enclosing_signature);
+
+        let round_trip: DiffOptions =
+            serde_json::from_str(&serde_json::to_string(&options).unwrap()).unwrap();
+        assert!(round_trip.ignore_whitespace && round_trip.intra_line == IntraLineMode::None);
+
+        assert!(serde_json::from_str::<DiffOptions>(r#"{ "preset": "nope" }"#).is_err());
+    }
+
+    #[test]
+    fn test_stats_modified_as() {
+        let old_text = "one\ntwo\nthree\nfour";
+        let new_text = "one\ntwo!\nthree\nfour";
+
+        let separate = compute_diff(old_text, new_text, &DiffOptions::default()).unwrap();
+        let options = DiffOptions {
+            stats_modified_as: ModifiedCounting::AddRemove,
+            ..Default::default()
+        };
+        let add_remove = compute_diff(old_text, new_text, &options).unwrap();
+
+        let counts = |stats: &DiffStats| {
+            (stats.added_lines, stats.removed_lines, stats.modified_lines, stats.unchanged_lines)
+        };
+        assert_eq!(counts(&separate.stats), (0, 0, 1, 3));
+        assert_eq!(counts(&add_remove.stats), (1, 1, 0, 3));
+        assert_eq!(separate.stats.similarity, add_remove.stats.similarity);
+        let hunk = &add_remove.hunks[0];
+        assert_eq!(hunk.changes[hunk.leading_context().len()].change_type, ChangeType::Modified);
+    }
+
+    #[test]
+    fn test_longest_unchanged_run() {
+        let old_text: String = (0..100).map(|i| format!("line {}\n", i)).collect();
+        let new_text = old_text.replace("line 40\n", "line 40;\n");
+
+        let result = compute_diff(&old_text, &new_text, &DiffOptions::default()).unwrap();
+        assert_eq!(result.stats.modified_lines, 1);
+        assert_eq!(result.stats.longest_unchanged_run, 59);
+
+        let all_lines = DiffOptions {
+            emit_all_lines: true,
+            ..DiffOptions::default()
+        };
+        let result = compute_diff(&old_text, &new_text, &all_lines).unwrap();
+        assert_eq!(result.stats.longest_unchanged_run, 59);
+
+        let identical = compute_diff(&old_text, &old_text, &DiffOptions::default()).unwrap();
+        assert_eq!(identical.stats.longest_unchanged_run, 100);
+    }
+
+    #[test]
+    fn test_duplicate_blocks() {
+        let block = "fn total(items: &[u32]) -> u32 {\n    let mut sum = 0;\n    for item in items {\n        sum += item;\n    }\n";
+        let new_text = format!("use std::fmt;\n\n{}}}\n\nfn other() {{}}\n\n{}}}\n", block, block);
+
+        let blocks = find_duplicate_blocks(&new_text, 5);
+        assert_eq!(
+            blocks,
+            vec![DuplicateBlock { first_line: 3, duplicate_line: 12, line_count: 6 }]
+        );
+
+        assert!(find_duplicate_blocks(&new_text, 7).is_empty());
+        assert!(find_duplicate_blocks("}\n}\n}\n}\n}\n}\n}\n}\n}\n}\n", 2).is_empty());
+    }
+
+    #[test]
+    fn test_result_delta() {
+        let old_text: String = (0..40).map(|i| format!("line {}\n", i)).collect();
+        let first = old_text.replacen("line 5\n", "line 5!\n", 1).replacen("line 30\n", "line 30!\n", 1);
+        let second = first.replacen("line 30!\n", "line 30!!\n", 1);
+
         let options = DiffOptions::default();
-        let result = compute_diff(&old_text, &new_text, &options);
-        let duration = start.elapsed();
-        
-        assert!(result.is_ok());
-        assert!(duration.as_millis() < 1000); // Should complete in under 1 second
+        let before = compute_diff(&old_text, &first, &options).unwrap();
+        let after = compute_diff(&old_text, &second, &options).unwrap();
+        assert_eq!(before.hunks.len(), 2);
+
+        let delta = before.delta(&after);
+        assert_eq!(delta.changed_hunks, vec![(1, 1)]);
+        assert!(delta.added_hunks.is_empty());
+        assert!(delta.removed_hunks.is_empty());
+
+        // A new hunk between the others leaves them as they were
+        let third = second.replacen("line 18\n", "line 18 and more\nline 19\n", 1);
+        let delta = after.delta(&compute_diff(&old_text, &third, &options).unwrap());
+        assert_eq!(delta.added_hunks, vec![1]);
+        assert!(delta.changed_hunks.is_empty());
+        assert!(delta.removed_hunks.is_empty());
+
+        assert!(after.delta(&after).is_empty());
+    }
+
+    #[test]
+    fn test_msgpack_round_trip() {
+        let old_text = "fn main() {\n    let x = 1;\n}\n";
+        let new_text = "fn main() {\n    let x = 2;\n    println!(\"{}\", x);\n}\n";
+        let options = DiffOptions {
+            language: Some("rust".to_string()),
+            include_byte_offsets: true,
+            ..DiffOptions::default()
+        };
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+
+        let bytes = result.to_msgpack().unwrap();
+        let json = serde_json::to_vec(&result).unwrap();
+        assert!(bytes.len() < json.len());
+
+        let decoded = DiffResult::from_msgpack(&bytes).unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), serde_json::to_value(&result).unwrap());
+
+        assert!(DiffResult::from_msgpack(&bytes[..bytes.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn test_engine_binary_diff_decodes() {
+        let old_text = "alpha\nbeta\ngamma\n";
+        let new_text = "alpha\nBETA\ngamma\ndelta\n";
+        let engine = DiffEngine::new();
+
+        let bytes = engine.compute_diff_binary(old_text, new_text).unwrap();
+        let decoded = DiffResult::from_msgpack(&bytes).unwrap();
+        let expected = compute_diff(old_text, new_text, &DiffOptions::default()).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&decoded.hunks).unwrap(),
+            serde_json::to_value(&expected.hunks).unwrap()
+        );
+        assert_eq!(decoded.stats.added_lines, expected.stats.added_lines);
+        assert_eq!(decoded.stats.modified_lines, expected.stats.modified_lines);
+    }
+
+    #[test]
+    fn test_hunk_context() {
+        let old_text = "keep 1\nkeep 2\nold\nkeep 3";
+        let new_text = "keep 1\nkeep 2\nnew\nextra\nkeep 3";
+
+        let options = DiffOptions {
+            emit_all_lines: true,
+            ..DiffOptions::default()
+        };
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        let hunk = &result.hunks[0];
+
+        let leading = hunk.leading_context();
+        assert_eq!(leading.len(), 2);
+        assert_eq!(leading[1].content, "keep 2");
+
+        let trailing = hunk.trailing_context();
+        assert_eq!(trailing.len(), 1);
+        assert_eq!(trailing[0].content, "keep 3");
+
+        let changed = hunk.changes.len() - leading.len() - trailing.len();
+        assert_eq!(changed, 3);
+        assert!(leading.iter().chain(trailing).all(|c| c.change_type == ChangeType::Unchanged));
+    }
+
+    #[test]
+    fn test_large_file_threshold() {
+        let options = DiffOptions {
+            large_file_threshold: 16,
+            ..DiffOptions::default()
+        };
+
+        let at_limit = "a".repeat(16);
+        let over_limit = "a".repeat(17);
+
+        let result = compute_diff(&at_limit, &at_limit, &options).unwrap();
+        assert!(!result.is_large_file);
+
+        let result = compute_diff(&at_limit, &over_limit, &options).unwrap();
+        assert!(result.is_large_file);
+    }
+
+    #[test]
+    fn test_error_on_empty() {
+        // Empty inputs are a valid, change-free diff by default
+        let result = compute_diff("", "", &DiffOptions::default()).unwrap();
+        assert!(result.hunks.is_empty());
+        assert!(!result.stats.has_changes);
+
+        let options = DiffOptions {
+            error_on_empty: true,
+            ..DiffOptions::default()
+        };
+        assert!(matches!(compute_diff("", "", &options), Err(DiffError::EmptyInput)));
+
+        // Only both sides being empty is an error
+        let result = compute_diff("", "line\n", &options).unwrap();
+        assert_eq!(result.stats.added_lines, 1);
+    }
+
+    #[test]
+    fn test_identical_inputs_fast_path() {
+        let text: String = (0..10_000).map(|i| format!("fn line_{}() {{ let x = {}; }}\n", i, i)).collect();
+
+        // A search budget too small for two separate edits, which come back
+        // flagged as a partial alignment; identical inputs stay within it
+        let budget = DiffOptions {
+            max_edit_distance: Some(1),
+            ..DiffOptions::default()
+        };
+        let edited = text
+            .replacen("let x = 100;", "let x = -100;", 1)
+            .replacen("let x = 9000;", "let x = -9000;", 1);
+        let partial = compute_diff(&text, &edited, &budget).unwrap();
+        assert_eq!(partial.alignment_fallback, Some(AlignmentFallback::Unaligned));
+        let changed: Vec<_> = partial
+            .hunks
+            .iter()
+            .flat_map(|h| &h.changes)
+            .filter(|c| c.change_type != ChangeType::Unchanged)
+            .collect();
+        assert_eq!(changed.first().unwrap().old_line_number, Some(101));
+        assert_eq!(changed.last().unwrap().new_line_number, Some(9001));
+
+        let result = compute_diff(&text, &text.clone(), &budget).unwrap();
+        assert_eq!(result.alignment_fallback, None);
+        assert!(result.hunks.is_empty());
+        assert_eq!(result.stats.total_lines, 10_000);
+        assert_eq!(result.stats.unchanged_lines, 10_000);
+        assert_eq!(result.stats.similarity, 1.0);
+        assert_eq!(result.file_language, Some("rust".to_string()));
+        assert!(!result.is_binary);
+
+        // Equal after normalization also takes the fast path
+        let options = DiffOptions {
+            ignore_case: true,
+            ..DiffOptions::default()
+        };
+        let result = compute_diff("Hello\nWorld", "hello\nworld", &options).unwrap();
+        assert!(result.hunks.is_empty());
+        assert_eq!(result.stats.similarity, 1.0);
+    }
+
+    #[test]
+    fn test_max_edit_distance_fallback() {
+        let old_text = "a\nb\nc\nd\ne\nf";
+        let new_text = "a\nB\nc\nd\ne\nF\ng";
+        let full = compute_diff(old_text, new_text, &DiffOptions::default()).unwrap();
+        assert_eq!(full.alignment_fallback, None);
+
+        let options = DiffOptions {
+            max_edit_distance: Some(1),
+            ..DiffOptions::default()
+        };
+        let limited = compute_diff(old_text, new_text, &options).unwrap();
+        assert_eq!(limited.alignment_fallback, Some(AlignmentFallback::Lcs));
+        assert_eq!(
+            serde_json::to_value(&limited.hunks).unwrap(),
+            serde_json::to_value(&full.hunks).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_similarity_significance() {
+        let options = DiffOptions {
+            semantic_diff: false,
+            syntax_highlight: false,
+            ..DiffOptions::default()
+        };
+
+        let old: String = (0..100_000).map(|i| format!("line {}\n", i)).collect();
+        let result = compute_diff(&old, &old, &options).unwrap();
+        assert_eq!(result.stats.similarity, 1.0);
+        assert!(!result.stats.has_changes);
+        assert!(!result.stats.is_trivial_change);
+
+        let new = old.replacen("line 500\n", "line 500!\n", 1);
+        let result = compute_diff(&old, &new, &options).unwrap();
+        assert!(result.stats.has_changes);
+        assert!(result.stats.is_trivial_change);
+        assert!(result.stats.similarity > 0.99);
+        assert!(result.stats.similarity <= options.significant_change_threshold);
+
+        let result = compute_diff("a\nb\nc", "x\ny\nz", &options).unwrap();
+        assert!(result.stats.has_changes);
+        assert!(!result.stats.is_trivial_change);
+        assert!(result.stats.similarity < 0.5);
+    }
+
+    #[test]
+    fn test_result_schema() {
+        let schema = diff::result_schema();
+        let properties = &schema["properties"];
+
+        assert_eq!(properties["hunks"]["type"], "array");
+        assert_eq!(properties["hunks"]["items"]["$ref"], "#/definitions/DiffHunk");
+        assert_eq!(properties["stats"]["$ref"], "#/definitions/DiffStats");
+        assert_eq!(properties["isBinary"]["type"], "boolean");
+
+        let stats = &schema["definitions"]["DiffStats"]["properties"];
+        assert_eq!(stats["similarity"]["type"], "number");
+        assert_eq!(stats["totalLines"]["type"], "integer");
+    }
+
+    #[test]
+    fn test_hunk_header_context() {
+        let old_text = "use std::io;\n\npub fn process_data(input: &str) -> usize {\n    let a = 1;\n    let b = 2;\n    let c = 3;\n    let d = 4;\n    a + b + c + d\n}\n";
+        let new_text = old_text.replace("let d = 4;", "let d = 5;");
+
+        let options = DiffOptions {
+            language: Some("rust".to_string()),
+            syntax_highlight: false,
+            ..DiffOptions::default()
+        };
+
+        let result = compute_diff(old_text, &new_text, &options).unwrap();
+        assert_eq!(result.hunks.len(), 1);
+        assert!(result.hunks[0].header.starts_with("@@ "));
+        assert!(result.hunks[0].header.ends_with("@@ pub fn process_data"));
+
+        // An insertion right after the signature is inside the function
+        let no_context = DiffOptions {
+            context_lines: 0,
+            ..options.clone()
+        };
+        let inserted = old_text.replace("{\n", "{\n    let z = 0;\n");
+        let result = compute_diff(old_text, &inserted, &no_context).unwrap();
+        assert_eq!(result.hunks[0].old_lines, 0);
+        assert!(result.hunks[0].header.ends_with("@@ pub fn process_data"));
+
+        // Inserting into an empty file has no lines before it to search
+        let result = compute_diff("", old_text, &no_context).unwrap();
+        assert_eq!(result.hunks[0].old_start, 0);
+        assert!(result.hunks[0].header.ends_with("@@"));
+    }
+
+    #[test]
+    fn test_enclosing_signature() {
+        let body: String = (0..20).map(|i| format!("    let v{} = {};\n", i, i)).collect();
+        let old_text = format!("use std::io;\n\npub fn process_data(input: &str) -> usize {{\n{}    0\n}}\n", body);
+        let new_text = old_text
+            .replacen("use std::io;\n", "use std::io;\nuse std::fs;\n", 1)
+            .replace("let v15 = 15;", "let v15 = 16;");
+
+        let options = DiffOptions {
+            language: Some("rust".to_string()),
+            syntax_highlight: false,
+            include_enclosing_signature: true,
+            ..DiffOptions::default()
+        };
+        let result = compute_diff(&old_text, &new_text, &options).unwrap();
+        assert_eq!(result.hunks.len(), 2);
+
+        // The import hunk isn't inside anything
+        assert_eq!(result.hunks[0].changes[0].content, "use std::io;");
+        assert!(result.hunks[0].changes[0].semantic_info.is_none());
+
+        let signature = &result.hunks[1].changes[0];
+        assert_eq!(signature.change_type, ChangeType::Unchanged);
+        assert_eq!(signature.content, "pub fn process_data(input: &str) -> usize {");
+        assert_eq!((signature.old_line_number, signature.new_line_number), (Some(3), Some(4)));
+        assert_eq!(signature.semantic_info.as_ref().unwrap().entity_type, "enclosing_signature");
+        assert_eq!(result.hunks[1].changes[1].content, "    let v12 = 12;");
+        assert_eq!(result.hunks[1].changes[4].change_type, ChangeType::Modified);
+        assert_eq!(result.stats.modified_lines, 1);
+        // The signature lies outside the hunk's range
+        assert_eq!((result.hunks[1].new_start, result.hunks[1].new_lines), (17, 7));
+
+        let options = DiffOptions {
+            include_enclosing_signature: false,
+            ..options
+        };
+        let result = compute_diff(&old_text, &new_text, &options).unwrap();
+        assert_eq!(result.hunks[1].changes[0].content, "    let v12 = 12;");
+        assert_eq!(result.hunks[1].changes[3].change_type, ChangeType::Modified);
+    }
+
+    #[test]
+    fn test_token_diff() {
+        let old_text = "function total(items) {\n  let sum = 0;\n  return sum;\n}\n";
+        let new_text = "function total(items)\n{\n  let sum = 0;\n  return sum;\n}\n";
+
+        let options = DiffOptions {
+            language: Some("javascript".to_string()),
+            token_diff: true,
+            ..DiffOptions::default()
+        };
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        assert!(result.hunks.is_empty());
+        assert!(!result.stats.has_changes);
+
+        // Line alignment sees the moved brace
+        let line_options = DiffOptions {
+            token_diff: false,
+            ..options.clone()
+        };
+        assert!(!compute_diff(old_text, new_text, &line_options).unwrap().hunks.is_empty());
+
+        // A real edit is still reported, on the line it happened
+        let edited = new_text.replace("let sum = 0;", "let sum = 1;");
+        let result = compute_diff(old_text, &edited, &options).unwrap();
+        assert_eq!(result.hunks.len(), 1);
+        let hunk = &result.hunks[0];
+        let change = &hunk.changes[hunk.leading_context().len()];
+        assert_eq!(change.change_type, ChangeType::Modified);
+        assert_eq!((change.old_line_number, change.new_line_number), (Some(2), Some(3)));
+        assert_eq!(change.content, "  let sum = 1;");
+    }
+
+    #[test]
+    fn test_similarity_basis() {
+        let old_text = "a\nb\nc\nd";
+        let new_text = "a\nb\nnew 1\nnew 2\nc\nd";
+
+        let options = DiffOptions {
+            similarity_basis: SimilarityBasis::DeletionsOnly,
+            ..DiffOptions::default()
+        };
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        assert_eq!(result.stats.added_lines, 2);
+        assert_eq!(result.stats.similarity, 1.0);
+        assert!(result.stats.has_changes);
+
+        let result = compute_diff(old_text, new_text, &DiffOptions::default()).unwrap();
+        assert!(result.stats.similarity < 1.0);
+    }
+
+    #[test]
+    fn test_max_highlight_line_length() {
+        let long_line = format!("let x = [{}];", "1, ".repeat(500));
+        let old_text = "let a = 1;\nlet b = 2;";
+        let new_text = format!("let a = 10;\n{}", long_line);
+
+        let options = DiffOptions {
+            language: Some("javascript".to_string()),
+            max_highlight_line_length: 100,
+            emit_all_lines: true,
+            ..DiffOptions::default()
+        };
+
+        let result = compute_diff(old_text, &new_text, &options).unwrap();
+        let changes = &result.hunks[0].changes;
+
+        let short = changes.iter().find(|c| c.content == "let a = 10;").unwrap();
+        assert!(short.tokens.is_some());
+
+        let long = changes.iter().find(|c| c.content == long_line).unwrap();
+        assert!(long.tokens.is_none());
+    }
+
+    #[test]
+    fn test_extend_with_appended() {
+        let options = DiffOptions::default();
+        let old_text: String = (0..200).map(|i| format!("entry {}\n", i)).collect();
+        let tail: String = (200..300).map(|i| format!("entry {}\n", i)).collect();
+
+        // One change far from the end (new hunk) and one near it (shared hunk);
+        // a new hunk would need leading context the tail doesn't have
+        let no_context = DiffOptions {
+            context_lines: 0,
+            ..DiffOptions::default()
+        };
+        for (changed, options) in [("entry 50\n", &no_context), ("entry 198\n", &options)] {
+            let new_text = old_text.replacen(changed, "changed\n", 1);
+            let mut result = compute_diff(&old_text, &new_text, options).unwrap();
+
+            assert!(result.extend_with_appended(&tail, 200, options).unwrap());
+
+            let full = compute_diff(&old_text, &format!("{}{}", new_text, tail), options).unwrap();
+            assert_eq!(
+                serde_json::to_value(&result).unwrap(),
+                serde_json::to_value(&full).unwrap()
+            );
+        }
+
+        let new_text = old_text.replacen("entry 50\n", "changed\n", 1);
+        let mut result = compute_diff(&old_text, &new_text, &options).unwrap();
+        assert!(!result.extend_with_appended(&tail, 200, &options).unwrap());
+
+        // Removed trailing lines could pair with the tail, so refuse to extend
+        let new_text: String = (0..199).map(|i| format!("entry {}\n", i)).collect();
+        let mut result = compute_diff(&old_text, &new_text, &options).unwrap();
+        assert!(!result.extend_with_appended(&tail, 199, &options).unwrap());
+
+        // Inputs without line breaks take the tail's line ending as their own
+        let crlf_tail = "entry 1\r\nentry 2\r\n";
+        let mut result = compute_diff("", "", &options).unwrap();
+        assert!(!result.line_ending_detected);
+        assert!(result.extend_with_appended(crlf_tail, 0, &options).unwrap());
+        assert_eq!(result.line_ending, LineEnding::Crlf);
+        let full = compute_diff("", crlf_tail, &options).unwrap();
+        assert_eq!(serde_json::to_value(&result).unwrap(), serde_json::to_value(&full).unwrap());
+
+        let mut result = compute_diff(&old_text, &old_text, &no_context).unwrap();
+        assert!(result.extend_with_appended(crlf_tail, 200, &no_context).unwrap());
+        assert_eq!(result.line_ending, LineEnding::Mixed);
+    }
+
+    #[test]
+    fn test_highlighter_reuse() {
+        use diffit_diff_engine::syntax::HighlighterCache;
+
+        let options = DiffOptions {
+            language: Some("rust".to_string()),
+            ..DiffOptions::default()
+        };
+        let mut highlighters = HighlighterCache::new();
+
+        for i in 0..3 {
+            let new_text = format!("fn main() {{ let x = {}; }}", i + 1);
+            let result =
+                compute_diff_cached("fn main() { let x = 0; }", &new_text, &options, &mut highlighters).unwrap();
+            assert!(result.hunks[0].changes[0].tokens.is_some());
+        }
+        assert_eq!(highlighters.created(), 1);
+
+        let python = DiffOptions {
+            language: Some("python".to_string()),
+            ..DiffOptions::default()
+        };
+        compute_diff_cached("x = 1", "x = 2", &python, &mut highlighters).unwrap();
+        assert_eq!(highlighters.created(), 2);
+
+        // A different class name map gets its own highlighter
+        let mut class_names = std::collections::HashMap::new();
+        class_names.insert("keyword".to_string(), "tok-kw".to_string());
+        let mapped = DiffOptions {
+            class_name_map: Some(class_names),
+            ..options.clone()
+        };
+        let result = compute_diff_cached("fn a() {}", "fn b() {}", &mapped, &mut highlighters).unwrap();
+        assert_eq!(highlighters.created(), 3);
+        let keyword = |result: &DiffResult| {
+            result.hunks[0].changes[0]
+                .tokens
+                .iter()
+                .flatten()
+                .find(|t| t.token_type == "keyword")
+                .map(|t| t.class_name.clone())
+        };
+        assert_eq!(keyword(&result).as_deref(), Some("tok-kw"));
+
+        let result = compute_diff_cached("fn a() {}", "fn b() {}", &options, &mut highlighters).unwrap();
+        assert_eq!(highlighters.created(), 3);
+        assert_eq!(keyword(&result).as_deref(), Some("keyword"));
+    }
+
+    #[test]
+    fn test_class_name_map() {
+        let mut class_names = std::collections::HashMap::new();
+        class_names.insert("keyword".to_string(), "tok-kw".to_string());
+
+        let options = DiffOptions {
+            language: Some("rust".to_string()),
+            class_name_map: Some(class_names),
+            ..DiffOptions::default()
+        };
+
+        let result = compute_diff("fn a() {}", "fn b() { let x = 1; }", &options).unwrap();
+        let tokens: Vec<_> = result
+            .hunks
+            .iter()
+            .flat_map(|h| &h.changes)
+            .flat_map(|c| c.tokens.iter().flatten())
+            .collect();
+
+        let keywords: Vec<_> = tokens.iter().filter(|t| t.token_type == "keyword").collect();
+        assert!(!keywords.is_empty());
+        assert!(keywords.iter().all(|t| t.class_name == "tok-kw"));
+        assert!(tokens
+            .iter()
+            .filter(|t| t.token_type == "number")
+            .all(|t| t.class_name == "number"));
+    }
+
+    #[test]
+    fn test_word_spans_share_one_highlighter() {
+        use diffit_diff_engine::syntax::HighlighterCache;
+
+        let old_text = "let a = \"one two\";\n}\nlet b = \"three\";\n}\nlet c = 1;\n";
+        let new_text = "let a = \"one too\";\n}\nlet b = \"four\";\n}\nlet c = 2;\n";
+        let options = DiffOptions {
+            language: Some("rust".to_string()),
+            syntax_highlight: false,
+            intra_line: IntraLineMode::Word,
+            ..DiffOptions::default()
+        };
+        let mut highlighters = HighlighterCache::new();
+        let result = compute_diff_cached(old_text, new_text, &options, &mut highlighters).unwrap();
+        let modified = result.hunks.iter().flat_map(|h| &h.changes).filter(|c| c.intra_line_spans.is_some());
+        assert_eq!(modified.count(), 3);
+        assert_eq!(highlighters.created(), 1);
+
+        // Splitting strings needs no highlighter at all
+        let split = DiffOptions {
+            word_diff_split_strings: true,
+            ..options
+        };
+        let mut highlighters = HighlighterCache::new();
+        compute_diff_cached(old_text, new_text, &split, &mut highlighters).unwrap();
+        assert_eq!(highlighters.created(), 0);
+    }
+
+    #[test]
+    fn test_highlighting_errors() {
+        use diffit_diff_engine::syntax::{HighlighterCache, SyntaxHighlighter, SyntaxRule};
+
+        // An unknown language diffs normally, without tokens
+        let options = DiffOptions {
+            language: Some("klingon".to_string()),
+            ..DiffOptions::default()
+        };
+        let result = compute_diff("a = 1", "a = 2", &options).unwrap();
+        assert!(result.highlighting_skipped);
+        assert!(result.hunks[0].changes.iter().all(|c| c.tokens.is_none()));
+
+        let rust = DiffOptions {
+            language: Some("rust".to_string()),
+            ..DiffOptions::default()
+        };
+        assert!(!compute_diff("a = 1", "a = 2", &rust).unwrap().highlighting_skipped);
+
+        // A broken highlighter fails the diff instead of looking unsupported
+        let mut highlighters = HighlighterCache::new();
+        highlighters.insert(
+            "klingon",
+            SyntaxHighlighter::from_rules(vec![SyntaxRule {
+                regex: regex::Regex::new(r"\d*").unwrap(),
+                token_type: "number".to_string(),
+                class_name: "number".to_string(),
+                priority: 60,
+            }]),
+        );
+        let err = compute_diff_cached("a = 1", "a = 2", &options, &mut highlighters).unwrap_err();
+        assert!(matches!(err, DiffError::SyntaxError(_)));
+    }
+
+    #[test]
+    fn test_highlight_rule_priority() {
+        use diffit_diff_engine::syntax::*;
+
+        let rule = |pattern: &str, token_type: &str, priority: u8| SyntaxRule {
+            regex: regex::Regex::new(pattern).unwrap(),
+            token_type: token_type.to_string(),
+            class_name: token_type.to_string(),
+            priority,
+        };
+        // Listed lowest priority first, so declaration order alone would
+        // read the string prefix as an identifier
+        let highlighter = SyntaxHighlighter::from_rules(vec![
+            rule(r"\w+", "identifier", 10),
+            rule(r"\b(?:if|return)\b", "keyword", 70),
+            rule(r#"f?"[^"]*""#, "string", 80),
+        ]);
+        let line = r#"return f"if {x}""#;
+        let tokens = highlighter.highlight(line);
+        assert_eq!(tokens.len(), 2);
+        assert_eq!((tokens[0].0.as_str(), &line[tokens[0].2..tokens[0].3]), ("keyword", "return"));
+        assert_eq!((tokens[1].0.as_str(), &line[tokens[1].2..tokens[1].3]), ("string", r#"f"if {x}""#));
+
+        let line = r#"const s = "return 1";"#;
+        let tokens = SyntaxHighlighter::new("javascript").unwrap().highlight(line);
+        let inside: Vec<_> = tokens.iter().filter(|t| t.2 >= 10).collect();
+        assert_eq!(inside.len(), 1);
+        assert_eq!(inside[0].0, "string");
+        assert_eq!(&line[inside[0].2..inside[0].3], r#""return 1""#);
+    }
+
+    #[test]
+    fn test_hunk_grouping_boundaries() {
+        let options = DiffOptions {
+            semantic_diff: false,
+            syntax_highlight: false,
+            ..DiffOptions::default()
+        };
+        let old: Vec<String> = (0..40).map(|i| format!("line {}", i)).collect();
+
+        // Changes separated by exactly 2 * context_lines unchanged lines share a hunk
+        let mut new = old.clone();
+        new[10] = "line 10!".to_string();
+        new[17] = "line 17!".to_string();
+        let result = compute_diff(&old.join("\n"), &new.join("\n"), &options).unwrap();
+        assert_eq!(result.hunks.len(), 1);
+        assert_eq!(result.hunks[0].changes.len(), 14);
+        assert_eq!(result.stats.modified_lines, 2);
+
+        // One more unchanged line splits them
+        let mut new = old.clone();
+        new[10] = "line 10!".to_string();
+        new[18] = "line 18!".to_string();
+        let result = compute_diff(&old.join("\n"), &new.join("\n"), &options).unwrap();
+        assert_eq!(result.hunks.len(), 2);
+        assert_eq!(result.hunks[1].changes[0].old_line_number, Some(16));
+        assert_eq!(result.hunks[1].changes[3].old_line_number, Some(19));
+        assert_eq!(result.hunks[1].old_start, 16);
+    }
+
+    #[test]
+    fn test_default_context_lines() {
+        let old_text: String = (1..=20).map(|i| format!("line {}\n", i)).collect();
+        let new_text = old_text
+            .replace("line 8\n", "")
+            .replace("line 10\n", "line 10\nnew\n");
+        let result = compute_diff(&old_text, &new_text, &DiffOptions::default()).unwrap();
+
+        // Both changes share a hunk, with three unchanged lines on either side
+        assert_eq!(result.hunks.len(), 1);
+        let hunk = &result.hunks[0];
+        assert_eq!(hunk.header, "@@ -5,9 +5,9 @@");
+        assert_eq!((hunk.old_start, hunk.old_lines, hunk.new_start, hunk.new_lines), (5, 9, 5, 9));
+        let leading: Vec<&str> = hunk.leading_context().iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(leading, ["line 5", "line 6", "line 7"]);
+        let trailing: Vec<&str> = hunk.trailing_context().iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(trailing, ["line 11", "line 12", "line 13"]);
+
+        // Context stops at the ends of the file
+        let new_text = old_text.replacen("line 2\n", "", 1);
+        let result = compute_diff(&old_text, &new_text, &DiffOptions::default()).unwrap();
+        assert_eq!(result.hunks[0].header, "@@ -1,5 +1,4 @@");
+        assert_eq!(result.hunks[0].leading_context().len(), 1);
+
+        let new_text = format!("{}line 21\n", old_text);
+        let result = compute_diff(&old_text, &new_text, &DiffOptions::default()).unwrap();
+        assert_eq!(result.hunks[0].header, "@@ -18,3 +18,4 @@");
+    }
+
+    #[test]
+    fn test_hunk_header_counts() {
+        let keep = |range: std::ops::Range<usize>| -> String { range.map(|i| format!("keep {}\n", i)).collect() };
+        let old_text = format!("{}apple\nbanana\n{}", keep(1..11), keep(11..21));
+        let new_text = format!("{}cherry\ndamson\nelder\n{}", keep(1..11), keep(11..21));
+        let result = compute_diff(&old_text, &new_text, &DiffOptions::default()).unwrap();
+
+        // 3 context + 2 removed + 3 context old, 3 context + 3 added + 3 context new
+        assert_eq!(result.hunks.len(), 1);
+        let hunk = &result.hunks[0];
+        assert_eq!((result.stats.removed_lines, result.stats.added_lines), (2, 3));
+        assert_eq!(hunk.header, "@@ -8,8 +8,9 @@");
+        assert_eq!((hunk.old_lines, hunk.new_lines), (8, 9));
+    }
+
+    #[test]
+    fn test_debug_anchors() {
+        let old_text = "a\nb\nc\nd\ne\nf\n";
+        let new_text = "a\nc\nd\nx\ne\nb\nf\n";
+        let options = DiffOptions {
+            debug: true,
+            ..DiffOptions::default()
+        };
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+
+        let anchors = result.debug_anchors.unwrap();
+        let old_lines: Vec<&str> = old_text.lines().collect();
+        let new_lines: Vec<&str> = new_text.lines().collect();
+        assert_eq!(anchors.len(), old_lines.len() - result.stats.removed_lines - result.stats.modified_lines);
+        assert!(anchors.iter().all(|&(o, n)| old_lines[o - 1] == new_lines[n - 1]));
+        assert!(anchors.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+
+        // Anchors refer to the inputs when only part of them is diffed
+        let options = DiffOptions {
+            old_line_range: Some((2, 7)),
+            new_line_range: Some((2, 8)),
+            ..options
+        };
+        let anchors = compute_diff(old_text, new_text, &options).unwrap().debug_anchors.unwrap();
+        assert!(!anchors.is_empty());
+        assert!(anchors.iter().all(|&(o, n)| old_lines[o - 1] == new_lines[n - 1]));
+
+        // Off by default
+        assert!(compute_diff(old_text, new_text, &DiffOptions::default()).unwrap().debug_anchors.is_none());
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let lf = "one\ntwo\nthree\n";
+        let crlf = "one\r\ntwo\r\nthree\r\n";
+        let options = DiffOptions::default();
+
+        // The same lines with different endings are identical, but reported
+        let result = compute_diff(crlf, lf, &options).unwrap();
+        assert!(!result.stats.has_changes);
+        assert!(result.hunks.is_empty());
+        assert_eq!(result.line_ending, LineEnding::Mixed);
+
+        let result = compute_diff(crlf, &crlf.replace("two", "two!"), &options).unwrap();
+        assert_eq!(result.line_ending, LineEnding::Crlf);
+        assert_eq!(result.stats.modified_lines, 1);
+        assert!(result.hunks[0].changes.iter().all(|c| !c.content.ends_with('\r')));
+        assert_eq!(compute_diff(lf, "one", &options).unwrap().line_ending, LineEnding::Lf);
+
+        // Mixed within one input
+        assert_eq!(LineEnding::detect("one\r\ntwo\nthree"), Some(LineEnding::Mixed));
+        assert_eq!(LineEnding::detect("one"), None);
+        let result = compute_diff("one\r\ntwo\n", "one\r\ntwo\n", &options).unwrap();
+        assert_eq!(result.line_ending, LineEnding::Mixed);
+
+        // Strict comparison sees every line change
+        let strict = DiffOptions {
+            strict_line_endings: true,
+            ..options
+        };
+        let result = compute_diff(crlf, lf, &strict).unwrap();
+        assert!(result.stats.has_changes);
+        assert_eq!(result.stats.removed_lines + result.stats.modified_lines, 3);
+    }
+
+    #[test]
+    fn test_conflict_markers() {
+        let old_text = "fn main() {\n    run();\n}\n";
+        let new_text = "fn main() {\n<<<<<<< HEAD\n    run();\n=======\n    run_fast();\n>>>>>>> feature\n}\n";
+        let options = DiffOptions {
+            context_lines: 1,
+            ..DiffOptions::default()
+        };
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        assert!(result.has_conflict_markers);
+
+        let changes = &result.hunks[0].changes;
+        let tagged: Vec<(&str, bool)> = changes.iter().map(|c| (c.content.as_str(), c.conflict_region)).collect();
+        assert_eq!(
+            tagged,
+            [
+                ("fn main() {", false),
+                ("<<<<<<< HEAD", true),
+                ("    run();", true),
+                ("=======", true),
+                ("    run_fast();", true),
+                (">>>>>>> feature", true),
+                ("}", false),
+            ]
+        );
+
+        // Unchanged conflicts still set the flag, and a lone marker isn't one
+        assert!(compute_diff(new_text, new_text, &options).unwrap().has_conflict_markers);
+        let result = compute_diff(old_text, "<<<<<<< HEAD\n", &options).unwrap();
+        assert!(!result.has_conflict_markers);
+        assert!(result.hunks[0].changes.iter().all(|c| !c.conflict_region));
+    }
+
+    #[test]
+    fn test_offset_line_numbers() {
+        let old_text: String = (1..=20).map(|i| format!("line {}\n", i)).collect();
+        let new_text = old_text.replace("line 8\n", "line 8!\nextra\n").replace("line 15\n", "");
+        let options = DiffOptions {
+            debug: true,
+            ..DiffOptions::default()
+        };
+        let result = compute_diff(&old_text, &new_text, &options).unwrap();
+        let shifted = result.clone().offset_line_numbers(500, 600);
+
+        for (hunk, moved) in result.hunks.iter().zip(&shifted.hunks) {
+            assert_eq!((moved.old_start, moved.new_start), (hunk.old_start + 500, hunk.new_start + 600));
+            assert!(moved.header.starts_with(&format!("@@ -{},{} +{},", moved.old_start, moved.old_lines, moved.new_start)));
+            for (change, moved) in hunk.changes.iter().zip(&moved.changes) {
+                assert_eq!(moved.old_line_number, change.old_line_number.map(|n| n + 500));
+                assert_eq!(moved.new_line_number, change.new_line_number.map(|n| n + 600));
+            }
+        }
+        let anchors = result.debug_anchors.unwrap();
+        assert_eq!(shifted.debug_anchors.unwrap()[0], (anchors[0].0 + 500, anchors[0].1 + 600));
+
+        // The same as diffing the snippets padded out to their place
+        let pad = |n: usize| "pad\n".repeat(n);
+        let padded = compute_diff(
+            &(pad(500) + &old_text),
+            &(pad(600) + &new_text),
+            &DiffOptions {
+                old_line_range: Some((501, 521)),
+                new_line_range: Some((601, 621)),
+                ..DiffOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&padded.hunks).unwrap(),
+            serde_json::to_value(&shifted.hunks).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_byte_offsets() {
+        let old_text = "héllo\r\nbeta\ngamma";
+        let new_text = "héllo!\r\nbeta\ngamma\ndelta\n";
+        let options = DiffOptions {
+            include_byte_offsets: true,
+            context_lines: 0,
+            ..DiffOptions::default()
+        };
+
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        let changes: Vec<_> = result.hunks.iter().flat_map(|h| &h.changes).collect();
+
+        let modified = changes.iter().find(|c| c.change_type == ChangeType::Modified).unwrap();
+        assert_eq!(&old_text[modified.old_byte_range.clone().unwrap()], "héllo\r\n");
+        assert_eq!(&new_text[modified.new_byte_range.clone().unwrap()], "héllo!\r\n");
+
+        let added: Vec<_> = changes.iter().filter(|c| c.change_type == ChangeType::Added).collect();
+        let added_text: String = added
+            .iter()
+            .map(|c| &new_text[c.new_byte_range.clone().unwrap()])
+            .collect();
+        assert!(added.iter().all(|c| c.old_byte_range.is_none()));
+        assert!(added_text.ends_with("delta\n"));
+
+        // Off by default
+        let result = compute_diff(old_text, new_text, &DiffOptions::default()).unwrap();
+        assert!(result.hunks[0].changes.iter().all(|c| c.new_byte_range.is_none()));
+    }
+
+    #[test]
+    fn test_content_encoding() {
+        let old_text = "let a = 1;\n";
+        let new_text = "let a = 1;\n<script>alert(\"x\" & 'y')</script>\n";
+
+        let added_content = |options: &DiffOptions| {
+            let result = compute_diff(old_text, new_text, options).unwrap();
+            result.hunks[0]
+                .changes
+                .iter()
+                .find(|c| c.change_type == ChangeType::Added)
+                .cloned()
+                .unwrap()
+        };
+
+        let raw = added_content(&DiffOptions::default());
+        assert_eq!(raw.content, "<script>alert(\"x\" & 'y')</script>");
+
+        let options = DiffOptions {
+            language: Some("javascript".to_string()),
+            content_encoding: ContentEncoding::HtmlEscaped,
+            ..DiffOptions::default()
+        };
+        let escaped = added_content(&options);
+        assert_eq!(
+            escaped.content,
+            "&lt;script&gt;alert(&quot;x&quot; &amp; &#39;y&#39;)&lt;/script&gt;"
+        );
+
+        // Token offsets follow the escaped text
+        let string_token = escaped
+            .tokens
+            .as_ref()
+            .unwrap()
+            .iter()
+            .find(|t| t.token_type == "string")
+            .unwrap();
+        assert_eq!(&escaped.content[string_token.start..string_token.end], "&quot;x&quot;");
+    }
+
+    #[test]
+    fn test_whitespace_markers() {
+        let options = DiffOptions {
+            mark_whitespace: true,
+            ..DiffOptions::default()
+        };
+
+        let old_text = "fn main() {\n\tlet x = 1;\n}";
+        let new_text = "fn main() {\n    let x = 1;\n}";
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+
+        let hunk = &result.hunks[0];
+        let change = &hunk.changes[hunk.leading_context().len()];
+        assert_eq!(change.change_type, ChangeType::Modified);
+        assert_eq!(change.content, "    let x = 1;");
+        assert_eq!(
+            change.whitespace_markers,
+            Some(vec![
+                (0, WsKind::Space),
+                (1, WsKind::Space),
+                (2, WsKind::Space),
+                (3, WsKind::Space),
+            ])
+        );
+
+        // Off by default
+        let result = compute_diff(old_text, new_text, &DiffOptions::default()).unwrap();
+        assert!(result.hunks[0].changes.iter().all(|c| c.whitespace_markers.is_none()));
+    }
+
+    #[test]
+    fn test_flag_whitespace_only() {
+        let options = DiffOptions {
+            flag_whitespace_only: true,
+            ..DiffOptions::default()
+        };
+
+        let old_text = "a\nx\nb\ny = 1\nc";
+        let new_text = "a\nx  \nb\ny = 2\nc";
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+
+        let changes: Vec<_> = result.hunks[0]
+            .changes
+            .iter()
+            .filter(|c| c.change_type != ChangeType::Unchanged)
+            .collect();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].change_type, ChangeType::Modified);
+        assert_eq!(changes[0].content, "x  ");
+        assert!(changes[0].whitespace_only);
+        assert_eq!(changes[1].change_type, ChangeType::Modified);
+        assert!(!changes[1].whitespace_only);
+        assert_eq!(result.stats.modified_lines, 2);
+        assert_eq!(result.stats.whitespace_changed_lines, 1);
+
+        // Off by default
+        let result = compute_diff(old_text, new_text, &DiffOptions::default()).unwrap();
+        assert!(result.hunks[0].changes.iter().all(|c| !c.whitespace_only));
+        assert_eq!(result.stats.whitespace_changed_lines, 0);
+
+        // and so is pairing short lines by their trimmed text
+        assert_eq!(result.stats.modified_lines, 1);
+        assert_eq!((result.stats.removed_lines, result.stats.added_lines), (1, 1));
+    }
+
+    #[test]
+    fn test_line_map() {
+        let old_text = "a\nb\nremoved\nc\nd\ne";
+        let new_text = "a\nb\nc\nadded\nd\ne";
+        let result = compute_diff(old_text, new_text, &DiffOptions::default()).unwrap();
+        let map = result.line_map(6, 6);
+
+        assert_eq!(map.old_to_new.len(), 6);
+        assert_eq!(map.new_to_old.len(), 6);
+
+        // Unchanged lines map both ways, including after the changes
+        assert_eq!(map.old_to_new[0], Some(0));
+        assert_eq!(map.old_to_new[3], Some(2));
+        assert_eq!(map.new_to_old[2], Some(3));
+        assert_eq!(map.old_to_new[5], Some(5));
+
+        // Removed and added lines have no counterpart
+        assert_eq!(map.old_to_new[2], None);
+        assert_eq!(map.new_to_old[3], None);
+
+        // Identical inputs pair every line
+        let result = compute_diff(old_text, old_text, &DiffOptions::default()).unwrap();
+        let map = result.line_map(6, 6);
+        assert_eq!(map.old_to_new, (0..6).map(Some).collect::<Vec<_>>());
+
+        // Gaps between separate hunks and the tail after the last one
+        let old: Vec<String> = (0..40).map(|i| format!("line {}", i)).collect();
+        let mut new = old.clone();
+        new.remove(5);
+        new.insert(30, "inserted".to_string());
+        let (old, new) = (old.join("\n"), new.join("\n"));
+        let options = DiffOptions::default();
+        let result = compute_diff(&old, &new, &options).unwrap();
+        assert_eq!(result.hunks.len(), 2);
+        let map = result.line_map(line_count(&old, &options), line_count(&new, &options));
+        assert_eq!(map.old_to_new[4], Some(4));
+        assert_eq!(map.old_to_new[5], None);
+        assert_eq!(map.old_to_new[20], Some(19));
+        assert_eq!(map.new_to_old[30], None);
+        assert_eq!(map.old_to_new[39], Some(39));
+
+        // Insertion into an empty file
+        let result = compute_diff("", "a\nb", &options).unwrap();
+        let map = result.line_map(0, 2);
+        assert_eq!(map.new_to_old, vec![None, None]);
+    }
+
+    #[test]
+    fn test_ignore_formatting() {
+        let options = DiffOptions {
+            language: Some("javascript".to_string()),
+            ignore_formatting: true,
+            ..DiffOptions::default()
+        };
+
+        let old_text = "const greeting = 'hello';\nif(ready){\n  start( greeting )\n}";
+        let formatted = "const greeting = \"hello\";\nif (ready) {\n  start(greeting);\n}";
+        let result = compute_diff(old_text, formatted, &options).unwrap();
+        assert!(result.hunks.is_empty());
+        assert!(!result.stats.has_changes);
+
+        let edited = "const greeting = \"goodbye\";\nif (ready) {\n  start(greeting);\n}";
+        let result = compute_diff(old_text, edited, &options).unwrap();
+        assert_eq!(result.stats.modified_lines + result.stats.removed_lines, 1);
+
+        // Strings are compared verbatim, so spacing inside them still counts
+        let spaced = "const greeting = 'hel lo';\nif(ready){\n  start( greeting )\n}";
+        let result = compute_diff(old_text, spaced, &options).unwrap();
+        assert!(result.stats.has_changes);
+
+        // Off by default
+        let result = compute_diff(old_text, formatted, &DiffOptions::default()).unwrap();
+        assert!(result.stats.has_changes);
+    }
+
+    #[test]
+    fn test_ignore_number_format() {
+        let options = DiffOptions {
+            ignore_number_format: true,
+            ..DiffOptions::default()
+        };
+
+        let old_text = "scale = 1.0\nmask = 0x10\nlimit = 1_000";
+        let new_text = "scale = 1\nmask = 16\nlimit = 1000";
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        assert!(result.hunks.is_empty());
+        assert!(!result.stats.has_changes);
+
+        // Values still count
+        let result = compute_diff("scale = 1", "scale = 2", &options).unwrap();
+        assert!(result.stats.has_changes);
+
+        // Identifiers ending in digits aren't numbers
+        let result = compute_diff("v1 = 1", "v01 = 1", &options).unwrap();
+        assert!(result.stats.has_changes);
+
+        // Integers past f64 precision still compare exactly
+        let result = compute_diff("id = 9007199254740993", "id = 9007199254740992", &options).unwrap();
+        assert!(result.stats.has_changes);
+        let result = compute_diff("id = 0xFFFFFFFFFFFFFFFF", "id = 18446744073709551615", &options).unwrap();
+        assert!(!result.stats.has_changes);
+
+        // Decimals compare by value, not by spelling
+        let result = compute_diff("x = 0.10", "x = 1e-1", &options).unwrap();
+        assert!(!result.stats.has_changes);
+        let result = compute_diff("x = 1.5e3", "x = 1500", &options).unwrap();
+        assert!(!result.stats.has_changes);
+        let result = compute_diff("x = 0.1000000000000000001", "x = 0.1", &options).unwrap();
+        assert!(result.stats.has_changes);
+
+        let result = compute_diff(old_text, new_text, &DiffOptions::default()).unwrap();
+        assert!(result.stats.has_changes);
+    }
+
+    #[test]
+    fn test_preprocessed_changes_show_original_lines() {
+        let old_text = "Header\n\nSELECT Name FROM Users;\n  WHERE id = 1\nFooter\n";
+        let new_text = "HEADER\n\nselect name from users;\n  WHERE id = 2\nFooter\n";
+
+        let options = DiffOptions { ignore_case: true, ..DiffOptions::default() };
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        assert_eq!(result.stats.modified_lines, 1);
+        let hunk = &result.hunks[0];
+        let change = &hunk.changes[hunk.leading_context().len()];
+        assert_eq!(change.change_type, ChangeType::Modified);
+        assert_eq!(change.content, "  WHERE id = 2");
+        assert_eq!(change.new_line_number, Some(4));
+        let contents: Vec<&str> = hunk.changes.iter().map(|c| c.content.as_str()).collect();
+        assert!(contents.contains(&"select name from users;"));
+        assert!(contents.contains(&"HEADER"));
+
+        // Trimmed and blank-line-free comparison still shows and numbers the lines as written
+        let options = DiffOptions { ignore_whitespace: true, ignore_blank_lines: true, ..DiffOptions::default() };
+        let old_text = "fn main() {\n    let total = compute(1);\n}\n";
+        let new_text = "fn main() {\n\n\tlet total = compute(2);   \n}\n";
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        let hunk = &result.hunks[0];
+        let change = &hunk.changes[hunk.leading_context().len()];
+        assert_eq!(change.content, "\tlet total = compute(2);   ");
+        assert_eq!((change.old_line_number, change.new_line_number), (Some(2), Some(3)));
+    }
+
+    #[test]
+    fn test_case_insensitive_tokens() {
+        let options = DiffOptions {
+            language: Some("sql".to_string()),
+            case_insensitive_tokens: vec!["keyword".to_string()],
+            ..DiffOptions::default()
+        };
+
+        let old_text = "SELECT name FROM users\nWHERE role = 'Admin'";
+        let new_text = "select name from users\nwhere role = 'Admin'";
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        assert!(!result.stats.has_changes);
+
+        // String literals and identifiers keep their case
+        let new_text = "select name from users\nwhere role = 'admin'";
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        assert_eq!(result.stats.modified_lines, 1);
+        let hunk = &result.hunks[0];
+        assert_eq!(hunk.changes[hunk.leading_context().len()].old_line_number, Some(2));
+
+        let result = compute_diff(old_text, "select NAME from users\nWHERE role = 'Admin'", &options).unwrap();
+        assert_eq!(result.stats.modified_lines, 1);
+
+        let result = compute_diff(old_text, new_text, &DiffOptions::default()).unwrap();
+        assert!(result.stats.modified_lines + result.stats.removed_lines >= 2);
+    }
+
+    #[test]
+    fn test_reformat_blocks() {
+        let body: Vec<String> = (0..10).map(|i| format!("step_{}();", i)).collect();
+        let old_text = format!("setup();\n{}\ndone();", body.join("\n"));
+        let wrapped: Vec<String> = body.iter().map(|line| format!("    {}", line)).collect();
+        let new_text = format!("setup();\ntry {{\n{}\n}} catch (e) {{}}\ndone();", wrapped.join("\n"));
+
+        let options = DiffOptions {
+            reformat_blocks: true,
+            ..DiffOptions::default()
+        };
+        let result = compute_diff(&old_text, &new_text, &options).unwrap();
+        let changes: Vec<_> = result.hunks.iter().flat_map(|h| &h.changes).collect();
+
+        let blocks: Vec<_> = changes.iter().filter(|c| c.change_type == ChangeType::ReformatBlock).collect();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].block_lines, 10);
+        assert_eq!((blocks[0].old_line_number, blocks[0].new_line_number), (Some(2), Some(3)));
+        assert!(blocks[0].content.starts_with("    step_0();\n"));
+
+        // Only the new braces remain as ordinary changes
+        let added: Vec<_> = changes.iter().filter(|c| c.change_type == ChangeType::Added).collect();
+        assert_eq!(added.len(), 2);
+        assert_eq!(result.stats.modified_lines, 10);
+
+        let result = compute_diff(&old_text, &new_text, &DiffOptions::default()).unwrap();
+        assert!(result.hunks.iter().flat_map(|h| &h.changes).all(|c| c.change_type != ChangeType::ReformatBlock));
+    }
+
+    #[test]
+    fn test_semantic_info_on_changes() {
+        let options = DiffOptions {
+            language: Some("python".to_string()),
+            ..DiffOptions::default()
+        };
+        let old_text = "import os\n\nclass Loader:\n    def load(self):\n        pass\n";
+        let new_text = "import os\n\nclass Loader:\n    def load(self):\n        pass\n\n    def foo(self):\n        return 1\n";
+
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        let changes: Vec<&DiffChange> = result.hunks.iter().flat_map(|hunk| &hunk.changes).collect();
+        let foo = changes.iter().find(|change| change.content.contains("def foo")).unwrap();
+        let info = foo.semantic_info.as_ref().unwrap();
+        assert_eq!(info.entity_name.as_deref(), Some("foo"));
+        assert_eq!(info.scope.as_deref(), Some("Loader"));
+
+        // Only changed lines that declare something are annotated
+        let body = changes.iter().find(|change| change.content.contains("return 1")).unwrap();
+        assert!(body.semantic_info.is_none());
+        assert!(changes
+            .iter()
+            .filter(|change| change.change_type == ChangeType::Unchanged)
+            .all(|change| change.semantic_info.is_none()));
+    }
+
+    #[test]
+    fn test_detect_moves() {
+        let options = DiffOptions {
+            detect_moves: true,
+            ..DiffOptions::default()
+        };
+        // Five lines moved past ten, so the alignment keeps the ten in
+        // place and it is the five that move
+        let mut lines: Vec<String> = (1..=20).map(|i| format!("statement_{}();", i)).collect();
+        let old_text = lines.join("\n");
+        let block: Vec<String> = lines.drain(2..7).collect();
+        for (i, line) in block.into_iter().enumerate() {
+            lines.insert(12 + i, line);
+        }
+        let new_text = lines.join("\n");
+
+        let result = compute_diff(&old_text, &new_text, &options).unwrap();
+        let changes: Vec<&DiffChange> = result
+            .hunks
+            .iter()
+            .flat_map(|hunk| &hunk.changes)
+            .filter(|change| change.change_type != ChangeType::Unchanged)
+            .collect();
+        assert_eq!(changes.len(), 10);
+        assert!(changes.iter().all(|change| change.change_type == ChangeType::Moved));
+        assert!(changes.iter().all(|change| change.content != "statement_8();"));
+
+        // Old lines 3..=7 became new lines 13..=17
+        let sources: Vec<_> = changes.iter().filter_map(|c| Some((c.old_line_number?, c.moved_to?))).collect();
+        assert_eq!(sources, [(3, 13), (4, 14), (5, 15), (6, 16), (7, 17)]);
+        let destination = changes.iter().find(|c| c.new_line_number == Some(17)).unwrap();
+        assert_eq!(destination.moved_from, Some(7));
+        assert_eq!((result.stats.removed_lines, result.stats.added_lines), (5, 5));
+
+        let result = compute_diff(&old_text, &new_text, &DiffOptions::default()).unwrap();
+        assert!(result
+            .hunks
+            .iter()
+            .flat_map(|hunk| &hunk.changes)
+            .all(|change| change.change_type != ChangeType::Moved));
+    }
+
+    #[test]
+    fn test_detect_moves_with_edit() {
+        let options = DiffOptions {
+            detect_moves: true,
+            ..DiffOptions::default()
+        };
+        let mut lines: Vec<String> = (1..=20).map(|i| format!("statement_{}();", i)).collect();
+        let old_text = lines.join("\n");
+        let mut block: Vec<String> = lines.drain(2..7).collect();
+        block[2] = "renamed_call();".to_string();
+        for (i, line) in block.into_iter().enumerate() {
+            lines.insert(10 + i, line);
+        }
+        let new_text = lines.join("\n");
+
+        let result = compute_diff(&old_text, &new_text, &options).unwrap();
+        let changes: Vec<&DiffChange> = result
+            .hunks
+            .iter()
+            .flat_map(|hunk| &hunk.changes)
+            .filter(|change| change.change_type != ChangeType::Unchanged)
+            .collect();
+        let moved = changes.iter().filter(|c| c.change_type == ChangeType::Moved).count();
+        assert_eq!(moved, 8);
+
+        // The edited line stays a removal and an addition
+        let removed: Vec<_> = changes.iter().filter(|c| c.change_type == ChangeType::Removed).collect();
+        let added: Vec<_> = changes.iter().filter(|c| c.change_type == ChangeType::Added).collect();
+        assert_eq!((removed.len(), added.len()), (1, 1));
+        assert_eq!(removed[0].content, "statement_5();");
+        assert_eq!(added[0].content, "renamed_call();");
+    }
+
+    #[test]
+    fn test_ignore_whitespace_keeps_blank_lines() {
+        let old_text = "fn main() {\n    let total = compute();\n    println!(\"{}\", total);\n}\n";
+        let new_text = "fn main() {\n  let total = compute();   \n\n\tprintln!(\"{}\", total);\n}\n";
+
+        // Reindenting is ignored, but the added blank line is still there
+        let options = DiffOptions { ignore_whitespace: true, ..DiffOptions::default() };
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        assert_eq!((result.stats.added_lines, result.stats.removed_lines, result.stats.modified_lines), (1, 0, 0));
+        let hunk = &result.hunks[0];
+        let change = &hunk.changes[hunk.leading_context().len()];
+        assert_eq!(change.change_type, ChangeType::Added);
+        assert_eq!(change.new_line_number, Some(3));
+        let last = hunk.changes.last().unwrap();
+        assert_eq!((last.old_line_number, last.new_line_number), (Some(4), Some(5)));
+
+        // Blank lines are their own option, and line numbers still match the inputs
+        let options = DiffOptions { ignore_blank_lines: true, ..options };
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        assert!(!result.stats.has_changes);
+
+        let edited = new_text.replace("compute()", "compute(1)");
+        let result = compute_diff(old_text, &edited, &options).unwrap();
+        let hunk = &result.hunks[0];
+        let change = &hunk.changes[hunk.leading_context().len()];
+        assert_eq!(change.change_type, ChangeType::Modified);
+        assert_eq!((change.old_line_number, change.new_line_number), (Some(2), Some(2)));
+        let last = hunk.changes.last().unwrap();
+        assert_eq!((last.old_line_number, last.new_line_number), (Some(4), Some(5)));
+    }
+
+    #[test]
+    fn test_smart_blank_lines() {
+        let options = |language: &str| DiffOptions {
+            language: Some(language.to_string()),
+            smart_blank_lines: true,
+            ..DiffOptions::default()
+        };
+
+        // Blank lines inside a Python function don't matter, between defs they do
+        let python = "def load(path):\n    data = read(path)\n\n    return data\n\n\ndef save(path):\n    pass\n";
+        let reflowed = "def load(path):\n\n    data = read(path)\n    return data\n\n\ndef save(path):\n    pass\n";
+        let result = compute_diff(python, reflowed, &options("python")).unwrap();
+        assert!(!result.stats.has_changes);
+
+        let joined = "def load(path):\n    data = read(path)\n\n    return data\ndef save(path):\n    pass\n";
+        let result = compute_diff(python, joined, &options("python")).unwrap();
+        assert_eq!(result.stats.removed_lines, 2);
+
+        // In Rust no blank line matters, and line numbers still match the inputs
+        let rust = "fn load() {\n    read();\n}\n\nfn save() {\n    write();\n}\n";
+        let edited = "fn load() {\n\n    read();\n}\nfn save() {\n    write(1);\n}\n";
+        let result = compute_diff(rust, edited, &options("rust")).unwrap();
+        assert_eq!(result.hunks.len(), 1);
+        let hunk = &result.hunks[0];
+        let change = &hunk.changes[hunk.leading_context().len()];
+        assert_eq!(change.change_type, ChangeType::Modified);
+        assert_eq!((change.old_line_number, change.new_line_number), (Some(6), Some(6)));
+        assert!(result.hunks[0].header.starts_with(&format!("@@ -{},", result.hunks[0].old_start)));
+
+        // Off by default
+        let result = compute_diff(python, reflowed, &DiffOptions::default()).unwrap();
+        assert!(result.stats.has_changes);
+    }
+
+    #[test]
+    fn test_invalid_bytes_flagged() {
+        let old_bytes = b"first\nsecond \xff\xfe line\nthird\n";
+        let new_bytes = b"first\nsecond \xff line\nthird \xef\xbf\xbd\n";
+
+        let result = compute_diff_bytes(old_bytes, new_bytes, &DiffOptions::default()).unwrap();
+        let changes: Vec<_> = result.hunks.iter().flat_map(|h| &h.changes).collect();
+
+        // The damaged line is still diffed like any other
+        let second = changes
+            .iter()
+            .find(|c| c.new_line_number == Some(2) && c.change_type != ChangeType::Unchanged)
+            .unwrap();
+        assert_eq!(second.content, "second \u{fffd} line");
+        assert!(second.has_invalid_bytes);
+
+        // A literal U+FFFD in valid UTF-8 is real content, not an artifact
+        let third = changes.iter().find(|c| c.new_line_number == Some(3)).unwrap();
+        assert_eq!(third.content, "third \u{fffd}");
+        assert!(!third.has_invalid_bytes);
+
+        assert!(changes.iter().filter(|c| c.new_line_number == Some(1)).all(|c| !c.has_invalid_bytes));
+    }
+
+    #[test]
+    fn test_max_payload_bytes() {
+        let old_lines: Vec<String> = (0..200).map(|i| format!("let value_{} = {};", i, i)).collect();
+        let mut new_lines = old_lines.clone();
+        for i in (5..200).step_by(20) {
+            new_lines[i] = format!("let value_{} = {} + 1;", i, i);
+        }
+        let (old_text, new_text) = (old_lines.join("\n"), new_lines.join("\n"));
+
+        let options = DiffOptions {
+            language: Some("rust".to_string()),
+            ..DiffOptions::default()
+        };
+        let full = compute_diff(&old_text, &new_text, &options).unwrap();
+        assert!(full.payload_reduction.is_none());
+        let full_size = serde_json::to_string(&full).unwrap().len();
+
+        // Just under the full size: dropping tokens is enough
+        let budget = full_size - 1;
+        let options = DiffOptions {
+            max_payload_bytes: Some(budget),
+            ..options
+        };
+        let reduced = compute_diff(&old_text, &new_text, &options).unwrap();
+        let reduction = reduced.payload_reduction.clone().unwrap();
+        assert!(reduction.tokens_dropped);
+        assert!(!reduction.details_dropped);
+        assert_eq!(reduction.hunks_dropped, 0);
+        assert_eq!(reduced.hunks.len(), full.hunks.len());
+        assert!(reduced.hunks.iter().flat_map(|h| &h.changes).all(|c| c.tokens.is_none()));
+        assert!(serde_json::to_string(&reduced).unwrap().len() <= budget);
+
+        // A tight budget cuts trailing hunks, but stats still cover everything
+        let budget = full_size / 4;
+        let options = DiffOptions {
+            max_payload_bytes: Some(budget),
+            ..options
+        };
+        let reduced = compute_diff(&old_text, &new_text, &options).unwrap();
+        let reduction = reduced.payload_reduction.clone().unwrap();
+        assert!(reduction.details_dropped);
+        assert!(reduction.hunks_dropped > 0);
+        assert_eq!(reduced.hunks.len() + reduction.hunks_dropped, full.hunks.len());
+        assert_eq!(reduced.stats.modified_lines, full.stats.modified_lines);
+        assert!(serde_json::to_string(&reduced).unwrap().len() <= budget);
+    }
+
+    #[test]
+    fn test_aggregate_by_language() {
+        let options = DiffOptions::default();
+        let results = vec![
+            ("src/a.rs".to_string(), compute_diff("a\nb\nc", "a\nb\nc\nd", &options).unwrap()),
+            ("src/b.rs".to_string(), compute_diff("a\nb", "a", &options).unwrap()),
+            ("tools/c.py".to_string(), compute_diff("a", "a\nb\nc", &options).unwrap()),
+        ];
+
+        let by_language = aggregate_by_language(&results, &options);
+        assert_eq!(by_language.len(), 2);
+
+        let rust = &by_language["rust"];
+        assert_eq!((rust.added_lines, rust.removed_lines, rust.total_lines), (1, 1, 6));
+
+        let python = &by_language["python"];
+        assert_eq!((python.added_lines, python.removed_lines, python.total_lines), (2, 0, 3));
+        assert!(python.has_changes);
+    }
+
+    #[test]
+    fn test_stats_csv() {
+        let result = compute_diff("one\ntwo\nthree", "one\ntwo!\nthree\nfour\nfive", &DiffOptions::default()).unwrap();
+        let columns: Vec<&str> = stats_csv_header().split(',').collect();
+        let row = stats_to_csv_row("src/lib.rs", &result.stats);
+        let values: Vec<&str> = row.split(',').collect();
+        assert_eq!(columns.len(), values.len());
+        assert_eq!(&values[..4], ["src/lib.rs", "2", "0", "1"]);
+        assert_eq!(values[4], format!("{:.4}", result.stats.similarity));
+        assert_eq!(values[5], "4");
+
+        let row = stats_to_csv_row("a,\"b\".txt", &result.stats);
+        assert!(row.starts_with("\"a,\"\"b\"\".txt\","));
+    }
+
+    #[test]
+    fn test_non_substantive_changes() {
+        let options = DiffOptions::default();
+        let text = "fn main() {\n    run();\n}\n";
+
+        let with_bom = compute_diff(text, &format!("\u{feff}{}", text), &options).unwrap();
+        assert!(with_bom.bom_changed);
+        assert!(!with_bom.is_substantive_change());
+
+        let without_newline = compute_diff(text, text.trim_end(), &options).unwrap();
+        assert!(without_newline.final_newline_changed);
+        assert!(!without_newline.bom_changed);
+        assert!(!without_newline.is_substantive_change());
+
+        let crlf = compute_diff(text, &text.replace('\n', "\r\n"), &options).unwrap();
+        assert!(!crlf.is_substantive_change());
+
+        // The mark doesn't hide a real edit after it
+        let edited = compute_diff(text, &format!("\u{feff}{}", text.replace("run", "stop")), &options).unwrap();
+        assert!(edited.bom_changed);
+        assert!(edited.is_substantive_change());
+        assert_eq!(edited.stats.modified_lines, 1);
+    }
+
+    #[test]
+    fn test_compute_batch() {
+        let base: String = (0..200).map(|i| format!("line {}\n", i)).collect();
+        let nearly_same = base.replacen("line 100\n", "line 100!\n", 1);
+        let rewritten: String = (0..200).map(|i| format!("other {}\n", i * 7)).collect();
+        let pairs = [
+            ("same.txt", base.as_str(), base.as_str()),
+            ("nearly.txt", base.as_str(), nearly_same.as_str()),
+            ("rewritten.txt", base.as_str(), rewritten.as_str()),
+        ];
+
+        let entries = compute_batch(&pairs, &DiffOptions::default(), 0.01).unwrap();
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["same.txt", "nearly.txt", "rewritten.txt"]);
+
+        // Trivial pairs keep their similarity but skip the full diff
+        assert!(entries[0].result.is_none());
+        assert_eq!(entries[0].similarity, 1.0);
+        assert!(entries[1].result.is_none());
+        assert!(entries[1].similarity > 0.99 && entries[1].similarity < 1.0);
+        let result = entries[2].result.as_ref().unwrap();
+        assert_eq!(result.stats.similarity, entries[2].similarity);
+        assert!(!result.hunks.is_empty());
+
+        // Replaying the survey's alignment gives the same result as a fresh diff
+        let fresh = compute_diff(&base, &rewritten, &DiffOptions::default()).unwrap();
+        assert_eq!(serde_json::to_value(result).unwrap(), serde_json::to_value(&fresh).unwrap());
+
+        // Nothing is skipped without a minimum
+        let entries = compute_batch(&pairs, &DiffOptions::default(), 0.0).unwrap();
+        assert!(entries.iter().all(|e| e.result.is_some()));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_batch_matches_sequential() {
+        let texts: Vec<(String, String, String)> = (0..40)
+            .map(|i| {
+                let old: String = (0..100).map(|j| format!("fn f{}() {{ {} }}\n", j, j * i)).collect();
+                let new = old.replace(&format!("f{}()", i), "changed()");
+                (format!("file{}.rs", i), old, new)
+            })
+            .collect();
+        let pairs: Vec<(&str, &str, &str)> = texts.iter().map(|(p, o, n)| (p.as_str(), o.as_str(), n.as_str())).collect();
+        let options = DiffOptions {
+            language: Some("rust".to_string()),
+            ..DiffOptions::default()
+        };
+
+        let parallel = compute_batch(&pairs, &options, 0.001).unwrap();
+        let sequential = compute_batch_sequential(&pairs, &options, 0.001).unwrap();
+        assert_eq!(serde_json::to_value(&parallel).unwrap(), serde_json::to_value(&sequential).unwrap());
+        assert!(parallel.iter().any(|e| e.result.is_some()));
+    }
+
+    #[test]
+    fn test_compute_heatmap() {
+        let old: Vec<String> = (0..1000).map(|i| format!("line {}", i)).collect();
+        let mut new = old.clone();
+        for line in new.iter_mut().take(720).skip(700) {
+            line.push('!');
+        }
+        new[150].push('!');
+
+        let heatmap = compute_heatmap(&old.join("\n"), &new.join("\n"), 10, &DiffOptions::default()).unwrap();
+        assert_eq!(heatmap.len(), 10);
+
+        let peak = heatmap
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+            .map(|(bucket, _)| bucket);
+        assert_eq!(peak, Some(7));
+        assert!((heatmap[7] - 0.2).abs() < 1e-6);
+        assert!((heatmap[1] - 0.01).abs() < 1e-6);
+        assert_eq!(heatmap[0], 0.0);
+    }
+
+    #[test]
+    fn test_engine_heatmap_matches_library() {
+        let old = "a\nb\nc\nd";
+        let new = "a\nB\nc\nd";
+        let engine = DiffEngine::new();
+
+        let heatmap = engine.compute_heatmap(old, new, 4).unwrap();
+        assert_eq!(heatmap, compute_heatmap(old, new, 4, &DiffOptions::default()).unwrap());
+        assert!(heatmap[1] > 0.0);
+    }
+
+    #[test]
+    fn test_streamed_hunks_match_full_change_list() {
+        let options = DiffOptions {
+            semantic_diff: false,
+            syntax_highlight: false,
+            ..DiffOptions::default()
+        };
+        let old: Vec<String> = (0..500).map(|i| format!("line {}", i)).collect();
+        let mut new = old.clone();
+        for i in [0, 3, 10, 17, 18, 120, 127, 300, 499] {
+            new[i].push('!');
+        }
+        new.remove(400);
+        new.insert(200, "inserted".to_string());
+        let (old_text, new_text) = (old.join("\n"), new.join("\n"));
+
+        let streamed = compute_diff(&old_text, &new_text, &options).unwrap();
+        let full_options = DiffOptions {
+            emit_all_lines: true,
+            ..options.clone()
+        };
+        let full = compute_diff(&old_text, &new_text, &full_options).unwrap();
+        assert_eq!(full.hunks.len(), 1);
+
+        // Group the full change list by hand: each change with its context,
+        // merged where the ranges touch
+        let all = &full.hunks[0].changes;
+        let context = options.context_lines;
+        let mut groups: Vec<std::ops::Range<usize>> = Vec::new();
+        for (i, _) in all.iter().enumerate().filter(|(_, c)| c.change_type != ChangeType::Unchanged) {
+            let range = i.saturating_sub(context)..(i + context + 1).min(all.len());
+            match groups.last_mut() {
+                Some(last) if range.start <= last.end => last.end = range.end,
+                _ => groups.push(range),
+            }
+        }
+
+        let key = |c: &DiffChange| (c.change_type, c.old_line_number, c.new_line_number, c.content.clone());
+        let expected: Vec<Vec<_>> = groups.into_iter().map(|range| all[range].iter().map(key).collect()).collect();
+        let actual: Vec<Vec<_>> = streamed.hunks.iter().map(|h| h.changes.iter().map(key).collect()).collect();
+        assert_eq!(actual.len(), 6);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_extract_context() {
+        let old_lines: Vec<String> = (1..=100).map(|i| format!("line {}", i)).collect();
+        let mut new_lines = old_lines.clone();
+        new_lines.insert(0, "header".to_string());
+
+        let old_text = old_lines.join("\n");
+        let new_text = new_lines.join("\n");
+
+        // Expand ten lines starting at old line 40, which is new line 41
+        let context = extract_context(&old_text, &new_text, 40..50, 41..51);
+        assert_eq!(context.len(), 10);
+        assert!(context.iter().all(|c| c.change_type == ChangeType::Unchanged));
+        assert_eq!(context[0].content, "line 40");
+        assert_eq!(context[0].old_line_number, Some(40));
+        assert_eq!(context[0].new_line_number, Some(41));
+        assert_eq!(context[9].content, "line 49");
+        assert_eq!(context[9].old_line_number, Some(49));
+        assert_eq!(context[9].new_line_number, Some(50));
+
+        // Ranges past the end of the file are clipped
+        assert_eq!(extract_context(&old_text, &new_text, 95..105, 96..106).len(), 6);
+    }
+
+    #[test]
+    fn test_binary_detection() {
+        let binary_data = vec![0u8, 1, 2, 3, 255, 254];
+        let text_data = b"Hello, world!";
+        
+        let is_binary = |bytes: &[u8]| {
+            let text = String::from_utf8_lossy(bytes);
+            compute_diff("", &text, &DiffOptions::default()).unwrap().is_binary
+        };
+        assert!(is_binary(&binary_data));
+        assert!(!is_binary(text_data));
+    }
+
+    #[test]
+    fn test_syntax_highlighting() {
+        use diffit_diff_engine::syntax::*;
+        
+        let highlighter = SyntaxHighlighter::new("rust");
+        assert!(highlighter.is_ok());
+        
+        let highlighter = highlighter.unwrap();
+        let tokens = highlighter.highlight("fn main() { println!(\"Hello\"); }");
+        
+        assert!(!tokens.is_empty());
+        assert!(tokens.iter().any(|t| t.0 == "keyword"));
+
+        assert!(matches!(SyntaxHighlighter::new("klingon"), Err(DiffError::SyntaxError(_))));
+    }
+
+    #[test]
+    fn test_adjacent_tokens_merge() {
+        use diffit_diff_engine::syntax::*;
+
+        let highlighter = SyntaxHighlighter::new("javascript").unwrap();
+        let tokens = highlighter.highlight(r#"x = "a""b";"#);
+
+        let strings: Vec<_> = tokens.iter().filter(|t| t.0 == "string").collect();
+        assert_eq!(strings.len(), 1);
+        assert_eq!((strings[0].2, strings[0].3), (4, 10));
+    }
+
+    #[test]
+    fn test_typescript_highlighting() {
+        use diffit_diff_engine::syntax::*;
+
+        let highlighter = SyntaxHighlighter::new("typescript").unwrap();
+        let line = "interface Foo { x: number }";
+        let tokens = highlighter.highlight(line);
+        let text = |t: &(String, String, usize, usize)| &line[t.2..t.3];
+
+        assert!(tokens.iter().any(|t| t.0 == "keyword" && text(t) == "interface"));
+        assert!(tokens.iter().any(|t| t.0 == "type" && text(t) == "number"));
+
+        let line = "@Input() readonly label = `id ${x}`; // 'quoted' /* no */";
+        let tokens = highlighter.highlight(line);
+        let kinds: Vec<&str> = tokens.iter().map(|t| t.0.as_str()).collect();
+        assert_eq!(kinds, ["decorator", "keyword", "string", "comment"]);
+    }
+
+    #[test]
+    fn test_c_family_highlighting() {
+        use diffit_diff_engine::syntax::*;
+
+        let snippets = [
+            ("go", "func main() { s := `raw`; r := 'x'; fmt.Println(\"hi\", 0x1F) } // done", "func", "`raw`"),
+            ("java", "public static final String NAME = \"x\"; char c = '\\n'; /* note */", "public", "'\\n'"),
+            ("c", "static const char *name = \"x\\\"y\"; return 10UL;", "static", "\"x\\\"y\""),
+            ("cpp", "template <typename T> constexpr auto s = \"x\"; // c++", "template", "\"x\""),
+        ];
+        for (language, line, keyword, string) in snippets {
+            let tokens = SyntaxHighlighter::new(language).unwrap().highlight(line);
+            let has = |token_type: &str, text: &str| {
+                tokens.iter().any(|t| t.0 == token_type && &line[t.2..t.3] == text)
+            };
+            assert!(has("keyword", keyword), "{} keyword", language);
+            assert!(has("string", string), "{} string", language);
+            assert!(tokens.iter().any(|t| t.0 == "comment" || t.0 == "number"), "{}", language);
+        }
+
+        // C has none of the C++ keywords
+        let tokens = SyntaxHighlighter::new("c").unwrap().highlight("template");
+        assert!(tokens.is_empty());
+    }
+
+    #[test]
+    fn test_multiline_block_comments() {
+        use diffit_diff_engine::syntax::*;
+
+        let highlighter = SyntaxHighlighter::new("rust").unwrap();
+        let text = "let a = 1; /* starts\n   still \"inside\" fn\n ends */ let b = 2;";
+        let tokens = highlighter.highlight(text);
+        let comments: Vec<&str> = tokens.iter().filter(|t| t.0 == "comment").map(|t| &text[t.2..t.3]).collect();
+        assert_eq!(comments, ["/* starts", "   still \"inside\" fn", " ends */"]);
+
+        // Code after the close is highlighted again, nothing inside is
+        let kinds: Vec<&str> = tokens.iter().map(|t| t.0.as_str()).collect();
+        assert_eq!(kinds, ["keyword", "number", "comment", "comment", "comment", "keyword", "number"]);
+
+        // Opened and closed on one line, with code on both sides
+        let line = "int x = /* width */ 10; /**/ return x;";
+        let tokens = SyntaxHighlighter::new("c").unwrap().highlight(line);
+        let comments: Vec<&str> = tokens.iter().filter(|t| t.0 == "comment").map(|t| &line[t.2..t.3]).collect();
+        assert_eq!(comments, ["/* width */", "/**/"]);
+        assert!(tokens.iter().any(|t| t.0 == "keyword" && &line[t.2..t.3] == "return"));
+    }
+
+    #[test]
+    fn test_block_comments_across_diff_lines() {
+        let old_text = "fn main() {\n    let a = 1;\n    let b = 2;\n}\n";
+        let new_text = "fn main() {\n    let a = 1;\n    /* note\n    let x = still comment\n    */ let b = 3;\n}\n";
+        let options = DiffOptions {
+            language: Some("rust".to_string()),
+            ..DiffOptions::default()
+        };
+
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        let kinds = |content: &str| -> Vec<String> {
+            let change = result
+                .hunks
+                .iter()
+                .flat_map(|h| &h.changes)
+                .find(|c| c.content == content && c.change_type != ChangeType::Removed)
+                .unwrap();
+            change.tokens.iter().flatten().map(|t| t.token_type.clone()).collect()
+        };
+
+        // Each change line is highlighted on its own, but the comment opened
+        // on one carries into the next
+        assert_eq!(kinds("    /* note"), ["comment"]);
+        assert_eq!(kinds("    let x = still comment"), ["comment"]);
+        assert_eq!(kinds("    */ let b = 3;"), ["comment", "keyword", "number"]);
+        assert_eq!(kinds("}"), Vec::<String>::new());
+
+        // The removed line is highlighted from the old file's state
+        let removed = result
+            .hunks
+            .iter()
+            .flat_map(|h| &h.changes)
+            .find(|c| c.change_type == ChangeType::Removed)
+            .unwrap();
+        assert_eq!(removed.tokens.as_ref().unwrap()[0].token_type, "keyword");
+    }
+
+    #[test]
+    fn test_block_comments_opened_outside_hunk() {
+        let options = DiffOptions {
+            language: Some("rust".to_string()),
+            context_lines: 0,
+            ..DiffOptions::default()
+        };
+        let changed_kinds = |old_text: &str, new_text: &str, options: &DiffOptions| -> Vec<String> {
+            let result = compute_diff(old_text, new_text, options).unwrap();
+            let change = result.hunks[0]
+                .changes
+                .iter()
+                .find(|c| c.change_type != ChangeType::Removed)
+                .unwrap();
+            change.tokens.iter().flatten().map(|t| t.token_type.clone()).collect()
+        };
+
+        // The comment opens three lines above the hunk
+        let old_text = "/* start\nlet a = 1;\nlet b = 2;\nlet c = 3;\n*/\nlet d = 4;\n";
+        let new_text = "/* start\nlet a = 1;\nlet b = 2;\nlet c = 30;\n*/\nlet d = 4;\n";
+        assert_eq!(changed_kinds(old_text, new_text, &options), ["comment"]);
+
+        // Later hunks see comments closed above them
+        let old_text = "/* one */ let a = 1;\nlet b = 2;\n/*\n*/\nlet c = 3;\n";
+        let new_text = "/* one */ let a = 10;\nlet b = 2;\n/*\n*/\nlet c = 30;\n";
+        let result = compute_diff(old_text, new_text, &options).unwrap();
+        let last = result.hunks.last().unwrap().changes.last().unwrap();
+        assert_eq!(last.tokens.as_ref().unwrap()[0].token_type, "keyword");
+
+        // A line too long to highlight still opens the comment
+        let long = DiffOptions {
+            max_highlight_line_length: 20,
+            context_lines: 1,
+            ..options
+        };
+        let old_text = "let a = 1; /* this line is long\nlet b = 2;\n*/\n";
+        let new_text = "let a = 1; /* this line is long\nlet b = 3;\n*/\n";
+        let result = compute_diff(old_text, new_text, &long).unwrap();
+        let changes = &result.hunks[0].changes;
+        assert!(changes[0].tokens.is_none());
+        let added = changes
+            .iter()
+            .find(|c| !matches!(c.change_type, ChangeType::Unchanged | ChangeType::Removed))
+            .unwrap();
+        let kinds: Vec<&str> = added.tokens.iter().flatten().map(|t| t.token_type.as_str()).collect();
+        assert_eq!(kinds, ["comment"]);
+    }
+
+    #[test]
+    fn test_multibyte_token_offsets() {
+        use diffit_diff_engine::syntax::*;
+
+        let highlighter = SyntaxHighlighter::new("rust").unwrap();
+        let text = "// ünïcödé 🚀\r\nlet s = \"héllo 🚀\"; let n = 1;\nfn é() {}";
+        let tokens = highlighter.highlight(text);
+        let spans: Vec<(&str, &str)> = tokens.iter().map(|t| (t.0.as_str(), &text[t.2..t.3])).collect();
+
+        assert_eq!(
+            spans,
+            [
+                ("comment", "// ünïcödé 🚀"),
+                ("keyword", "let"),
+                ("string", "\"héllo 🚀\""),
+                ("keyword", "let"),
+                ("number", "1"),
+                ("keyword", "fn"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_highlight_to_html() {
+        use diffit_diff_engine::syntax::*;
+
+        let highlighter = SyntaxHighlighter::new("javascript").unwrap();
+        let html = highlighter.highlight_to_html(r#"if (a < b) return "<b>&";"#);
+
+        assert_eq!(
+            html,
+            "<span class=\"keyword\">if</span> (a &lt; b) <span class=\"keyword\">return</span> \
+             <span class=\"string\">&quot;&lt;b&gt;&amp;&quot;</span>;"
+        );
+    }
+
+    #[test]
+    fn test_supported_languages_sorted() {
+        use diffit_diff_engine::syntax::*;
+
+        let languages = get_supported_languages();
+        assert!(languages.windows(2).all(|pair| pair[0] < pair[1]));
+        for expected in ["javascript", "python", "rust", "typescript"] {
+            assert!(languages.iter().any(|l| l == expected));
+        }
+        assert_eq!(languages, get_supported_languages());
+    }
+
+    #[test]
+    fn test_register_extension() {
+        use diffit_diff_engine::syntax::*;
+
+        let mut options = DiffOptions::default();
+        options.register_extension(".foo", "rust");
+        let extensions = &options.extension_languages;
+        assert_eq!(detect_language_with("main.foo", "", extensions), "rust");
+        assert_eq!(detect_language_with("MAIN.FOO", "", extensions), "rust");
+        assert_eq!(detect_language("main.foo", ""), "text");
+        assert_eq!(detect_language_with("main.foo", "", &DiffOptions::default().extension_languages), "text");
+
+        let results = vec![("lib.foo".to_string(), compute_diff("", "x", &DiffOptions::default()).unwrap())];
+        assert!(aggregate_by_language(&results, &options).contains_key("rust"));
+        assert!(aggregate_by_language(&results, &DiffOptions::default()).contains_key("text"));
+
+        // A file name picks the language when none is set
+        let named = DiffOptions {
+            filename: Some("lib.foo".to_string()),
+            ..options.clone()
+        };
+        let result = compute_diff("let a = 1;", "let a = 2;", &named).unwrap();
+        assert_eq!(result.file_language.as_deref(), Some("rust"));
+        assert!(result.hunks[0].changes.iter().any(|c| c.tokens.is_some()));
+
+        let mut engine = DiffEngine::new();
+        engine.register_extension("foo", "rust");
+        engine.set_filename(Some("lib.foo".to_string()));
+        let bytes = engine.compute_diff_binary("let a = 1;", "let a = 2;").unwrap();
+        let decoded = DiffResult::from_msgpack(&bytes).unwrap();
+        assert_eq!(decoded.file_language.as_deref(), Some("rust"));
+
+        assert_eq!(detect_language("App.vue", ""), "javascript");
+        assert_eq!(detect_language("App.svelte", ""), "javascript");
+    }
+
+    #[test]
+    fn test_semantic_analysis() {
+        use diffit_diff_engine::semantic::*;
+        
+        let analyzer = SemanticAnalyzer::new(Some("rust"));
+        let info = analyzer.extract_semantic_info("pub fn test() {", &[]);
+        
+        assert!(info.is_some());
+        let info = info.unwrap();
+        assert_eq!(info.entity_type, "function");
+        assert_eq!(info.entity_name, Some("test".to_string()));
+    }
+
+    #[test]
+    fn test_performance() {
+        use std::time::Instant;
+        
+        let old_text = "a\n".repeat(1000);
+        let new_text = "b\n".repeat(1000);
+        
+        let start = Instant::now();
+        let options = DiffOptions::default();
+        let result = compute_diff(&old_text, &new_text, &options);
+        let duration = start.elapsed();
+        
+        assert!(result.is_ok());
+        assert!(duration.as_millis() < 1000); // Should complete in under 1 second
+    }
+
+    #[test]
+    fn test_huge_single_lines() {
+        use std::time::Instant;
+
+        let old_text = "x".repeat(2 * 1024 * 1024);
+        let mut new_text = old_text.clone();
+        new_text.replace_range(1024 * 1024..1024 * 1024 + 1, "y");
+
+        let start = Instant::now();
+        let result = compute_diff(&old_text, &new_text, &DiffOptions::default()).unwrap();
+        assert!(start.elapsed().as_secs() < 10);
+
+        let changes = &result.hunks[0].changes;
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].change_type, ChangeType::Modified);
+        assert!(result.is_large_file);
+
+        // Lines with nothing in common are still a removal and an addition
+        let other = "z".repeat(2 * 1024 * 1024);
+        let result = compute_diff(&old_text, &other, &DiffOptions::default()).unwrap();
+        assert_eq!((result.stats.removed_lines, result.stats.added_lines), (1, 1));
+    }
+
+    #[test]
+    fn test_warmup_matches_cold_diff() {
+        let options = DiffOptions {
+            language: Some("rust".to_string()),
+            ..DiffOptions::default()
+        };
+        let old_text = "fn main() {\n    let x = 1;\n}";
+        let new_text = "fn main() {\n    let x = 2;\n}";
+
+        let cold = compute_diff(old_text, new_text, &options).unwrap();
+
+        let engine = DiffEngine::new();
+        engine.warmup();
+        assert!(engine.warmup_language("rust"));
+        assert!(!engine.warmup_language("klingon"));
+
+        let warm = compute_diff(old_text, new_text, &options).unwrap();
+        assert_eq!(
+            serde_json::to_string(&cold).unwrap(),
+            serde_json::to_string(&warm).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_baseline_index_matches_compute_diff() {
+        let lines: Vec<String> = (0..200).map(|i| format!("line {}", i % 17)).collect();
+        let old_text = lines.join("\r\n");
+        let baseline = BaselineIndex::build(&old_text);
+        assert_eq!(baseline.text(), old_text);
+
+        let edits: [fn(&mut Vec<String>); 6] = [
+            |_| {},
+            |l| l[150] = "edited".to_string(),
+            |l| l.insert(100, "line 3".to_string()),
+            |l| {
+                l.remove(0);
+                l.push("tail".to_string());
+            },
+            |l| l.truncate(120),
+            |l| l.clear(),
+        ];
+        let ignore_case = DiffOptions {
+            ignore_case: true,
+            ..DiffOptions::default()
+        };
+        for edit in &edits {
+            let mut new_lines = lines.clone();
+            edit(&mut new_lines);
+            let new_text = new_lines.join("\n");
+
+            for options in [&DiffOptions::default(), &ignore_case] {
+                let indexed = compute_diff_against_baseline(&baseline, &new_text, options).unwrap();
+                let direct = compute_diff(&old_text, &new_text, options).unwrap();
+                assert_eq!(
+                    serde_json::to_value(&indexed).unwrap(),
+                    serde_json::to_value(&direct).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_static_patterns_compile() {
+        // Built-in patterns are compiled on first use, so a bad edit to one
+        // would otherwise only panic once someone diffed that language
+        diffit_diff_engine::syntax::warmup();
+        diffit_diff_engine::semantic::warmup();
+        for language in diffit_diff_engine::syntax::get_supported_languages() {
+            assert!(diffit_diff_engine::syntax::warmup_language(&language), "{}", language);
+        }
+
+        let options = DiffOptions {
+            ignore_number_format: true,
+            ..DiffOptions::default()
+        };
+        assert!(compute_diff("x = 1.0", "x = 1", &options).unwrap().hunks.is_empty());
     }
 
     #[test]